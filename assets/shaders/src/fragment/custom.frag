@@ -193,30 +193,54 @@
 
 layout(set = 1, binding = 0) uniform Material {
     UvOffset uv_offset;
+    uint layers;
 //     float alpha_cutoff;
 };
 
 layout(set = 1, binding = 1) uniform sampler2D diffuse;
 // layout(set = 1, binding = 2) uniform sampler2D emission;
 
+layout(std140, set = 2, binding = 0) uniform Fog {
+    vec4 fog_color;
+    float fog_density;
+};
+
 layout(location = 0) in VertexData {
     vec3 position;
     vec3 normal;
     // uint mtl_idx;
     vec2 tex_coord;
     vec4 color;
+    vec2 light;
+    flat uint texture_layer;
 } vertex;
 
 layout(location = 0) out vec4 out_color;
 
+const vec3 BLOCK_LIGHT_COLOR = vec3(1.0, 0.85, 0.6);
+
+// Each voxel light level is 80% as bright as the one above it.
+float voxel_light_curve(float level) {
+    return level > 0.0 ? pow(0.8, 15.0 * (1.0 - level)) : 0.0;
+}
 
 void main() {
     vec2 final_tex_coords   = tex_coords(vertex.tex_coord, uv_offset);
+    // Layers are stacked from top to bottom, the layer of the instance wraps around. Kept half a texel inside the
+    // layer, so that its edges do not sample the neighbouring layers.
+    if(layers > 1) {
+        float half_texel = 0.5 * float(layers) / float(textureSize(diffuse, 0).y);
+        float v = clamp(final_tex_coords.y, half_texel, 1.0 - half_texel);
+        final_tex_coords.y = (v + float(vertex.texture_layer % layers)) / float(layers);
+    }
     vec4 diffuse_alpha       = texture(diffuse, final_tex_coords);
     float alpha             = diffuse_alpha.a;
     // if(alpha < alpha_cutoff) discard;
+    // Cut out overlays such as the grass side overlay.
+    if(alpha < 0.5) discard;
 
-    vec3 diffuse = diffuse_alpha.rgb;
+    // Tinted before lighting so the fog is not tinted.
+    vec3 diffuse = diffuse_alpha.rgb * vertex.color.rgb;
     // vec3 emission = texture(emission, final_tex_coords).rgb;
 
     vec3 lighting = vec3(0.0);
@@ -239,6 +263,15 @@ void main() {
         lighting += diffuse * dlight[i].intensity;
     }
     lighting += ambient_color;
+    // Sky light shades the lights coming from outside, block light adds the light of emissive blocks.
+    lighting *= voxel_light_curve(vertex.light.x);
+    lighting += BLOCK_LIGHT_COLOR * voxel_light_curve(vertex.light.y);
     lighting = min(lighting, 1.0);
-    out_color = vec4(lighting * diffuse/* + emission*/, alpha) * vertex.color;
+    vec3 color = lighting * diffuse/* + emission*/;
+
+    float fog_distance = length(camera_position - vertex.position) * fog_density;
+    float fog = 1.0 - exp(-fog_distance * fog_distance);
+    color = mix(color, fog_color.rgb, fog);
+
+    out_color = vec4(color, alpha * vertex.color.a);
 }
@@ -44,15 +44,19 @@ layout(std140, set = 0, binding = 0) uniform Projview {
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 tex_coord;
-layout(location = 3) in mat4 model; // instance rate
-// layout(location = 7) in vec4 tint; // instance rate
-// layout(location = 8) in uint mtl_idx; // instance rate
+layout(location = 3) in vec2 voxel_light;
+layout(location = 4) in vec4 color;
+layout(location = 5) in mat4 model; // instance rate
+layout(location = 9) in vec4 tint; // instance rate
+layout(location = 10) in uint texture_layer; // instance rate
 
 layout(location = 0) out VertexData {
     vec3 position;
     vec3 normal;
     vec2 tex_coord;
     vec4 color;
+    vec2 light;
+    flat uint texture_layer;
 } vertex;
 
 void main() {
@@ -60,6 +64,8 @@ void main() {
     vertex.position = vertex_position.xyz;
     vertex.normal = mat3(model) * normal;
     vertex.tex_coord = tex_coord;
-    vertex.color = vec4(1.0);//tint;
+    vertex.color = color * tint;
+    vertex.light = voxel_light;
+    vertex.texture_layer = texture_layer;
     gl_Position = proj_view * vertex_position;
 }
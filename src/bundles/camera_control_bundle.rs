@@ -2,15 +2,15 @@ use amethyst::{
     controls::{CursorHideSystemDesc, HideCursor, MouseFocusUpdateSystemDesc, WindowFocus},
     core::{
         bundle::SystemBundle,
-        math::{convert, one, Unit, Vector3},
+        math::Vector3,
         timing::Time,
         transform::Transform,
         SystemDesc,
     },
     derive::SystemDesc,
     ecs::{
-        prelude::{Component, DispatcherBuilder, Join, NullStorage, World},
-        Read, ReadStorage, System, SystemData, WriteStorage,
+        prelude::{Component, DenseVecStorage, DispatcherBuilder, Entities, Entity, Join, NullStorage, World},
+        Read, ReadStorage, System, SystemData, Write, WriteStorage,
     },
     error::Error,
     input::{get_input_axis_simple, BindingTypes, InputHandler},
@@ -46,37 +46,47 @@ use std::f32::consts::{FRAC_PI_2, PI};
 /// * `CursorHideSystem`
 #[derive(Debug)]
 pub struct CameraControlBundle<T: BindingTypes> {
-    sensitivity_x: f32,
-    sensitivity_y: f32,
-    speed: f32,
+    settings: CameraControlSettings,
     side_input_axis: Option<T::Axis>,
     up_input_axis: Option<T::Axis>,
     forward_input_axis: Option<T::Axis>,
+    look_x_input_axis: Option<T::Axis>,
+    look_y_input_axis: Option<T::Axis>,
+    sprint_action: Option<T::Action>,
+    precision_action: Option<T::Action>,
 }
 
 impl<T: BindingTypes> CameraControlBundle<T> {
     /// Builds a new camera control bundle using the provided axes as controls.
     pub fn new() -> Self {
         CameraControlBundle {
-            sensitivity_x: 1.0,
-            sensitivity_y: 1.0,
-            speed: one(),
+            settings: CameraControlSettings::default(),
             side_input_axis: None,
             up_input_axis: None,
             forward_input_axis: None,
+            look_x_input_axis: None,
+            look_y_input_axis: None,
+            sprint_action: None,
+            precision_action: None,
         }
     }
 
+    /// Replaces all tuning values on this `CameraControlBundle`, e.g. with the ones loaded from the user config.
+    pub fn with_settings(mut self, settings: CameraControlSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
     /// Alters the mouse sensitivy on this `FlyControlBundle`
     pub fn with_sensitivity(mut self, x: f32, y: f32) -> Self {
-        self.sensitivity_x = x;
-        self.sensitivity_y = y;
+        self.settings.sensitivity_x = x;
+        self.settings.sensitivity_y = y;
         self
     }
 
     /// Alters the speed on this `FlyControlBundle`.
     pub fn with_speed(mut self, speed: f32) -> Self {
-        self.speed = speed;
+        self.settings.speed = speed;
         self
     }
 
@@ -94,25 +104,58 @@ impl<T: BindingTypes> CameraControlBundle<T> {
         self.up_input_axis = up_input_axis;
         self
     }
+
+    /// Axes used to look around with a gamepad stick, horizontal and vertical.
+    pub fn with_look_input_axes(mut self, look_x_input_axis: Option<T::Axis>, look_y_input_axis: Option<T::Axis>) -> Self {
+        self.look_x_input_axis = look_x_input_axis;
+        self.look_y_input_axis = look_y_input_axis;
+        self
+    }
+
+    /// Action that multiplies the speed while held.
+    pub fn with_sprint_action(mut self, sprint_action: Option<T::Action>) -> Self {
+        self.sprint_action = sprint_action;
+        self
+    }
+
+    /// Action that slows the movement down for precise placement while held.
+    pub fn with_precision_action(mut self, precision_action: Option<T::Action>) -> Self {
+        self.precision_action = precision_action;
+        self
+    }
+
+    /// Alters the rotation speed at full stick deflection, in degrees per second.
+    pub fn with_look_speed(mut self, look_speed: f32) -> Self {
+        self.settings.look_speed = look_speed;
+        self
+    }
+
+    /// Alters the dead zone and the response curve exponent applied to analog input.
+    pub fn with_stick_response(mut self, dead_zone: f32, exponent: f32) -> Self {
+        self.settings.stick_response = StickResponse { dead_zone, exponent };
+        self
+    }
 }
 
 impl<'a, 'b, T: BindingTypes> SystemBundle<'a, 'b> for CameraControlBundle<T> {
     fn build(
         self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>,
     ) -> Result<(), Error> {
+        world.insert(self.settings);
         builder.add(
             CreativeMovementSystemDesc::<T>::new(
-                self.speed,
                 self.side_input_axis,
                 self.up_input_axis,
                 self.forward_input_axis,
+                self.sprint_action,
+                self.precision_action,
             )
             .build(world),
             "creative_movement",
             &[],
         );
         builder.add(
-            MouseRotationSystemDesc::new(self.sensitivity_x, self.sensitivity_y).build(world),
+            MouseRotationSystemDesc::<T>::new(self.look_x_input_axis, self.look_y_input_axis).build(world),
             "mouse_rotation",
             &[],
         );
@@ -137,6 +180,95 @@ impl<'a, 'b, T: BindingTypes> SystemBundle<'a, 'b> for CameraControlBundle<T> {
 
 // endregion
 
+// region - Settings
+
+/// Camera tuning shared by the movement and rotation systems.
+///
+/// Lives in the world as a resource, so it can be changed at runtime and persisted to the user config.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraControlSettings {
+    /// Mouse sensitivity, in degrees per pixel of motion.
+    pub sensitivity_x: f32,
+    pub sensitivity_y: f32,
+    /// Movement speed, in meters per second.
+    pub speed: f32,
+    /// Rotation speed at full stick deflection, in degrees per second.
+    pub look_speed: f32,
+    pub stick_response: StickResponse,
+    /// How fast the velocity approaches the requested one when speeding up, per second.
+    pub acceleration: f32,
+    /// How fast the velocity approaches the requested one when slowing down, per second.
+    pub deceleration: f32,
+    /// Speed multiplier while the sprint action is held.
+    pub sprint_multiplier: f32,
+    /// Speed multiplier while the precision action is held.
+    pub precision_multiplier: f32,
+    /// Speed multiplier applied per mouse wheel notch.
+    pub scroll_speed_step: f32,
+    /// Bounds of the speed reachable with the mouse wheel.
+    pub min_speed: f32,
+    pub max_speed: f32,
+}
+
+impl Default for CameraControlSettings {
+    fn default() -> Self {
+        CameraControlSettings {
+            sensitivity_x: 0.1,
+            sensitivity_y: 0.1,
+            speed: 3.0,
+            look_speed: 180.0,
+            stick_response: StickResponse::default(),
+            acceleration: 8.0,
+            deceleration: 12.0,
+            sprint_multiplier: 4.0,
+            precision_multiplier: 0.25,
+            scroll_speed_step: 1.25,
+            min_speed: 0.5,
+            max_speed: 256.0,
+        }
+    }
+}
+
+// endregion
+
+// region - Stick Response
+
+/// Dead zone and response curve applied to analog stick and trigger input.
+///
+/// Keyboard axes always report `-1.0`, `0.0` or `1.0` and pass through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StickResponse {
+    /// Deflection below which input is ignored, in the `0.0..1.0` range.
+    pub dead_zone: f32,
+    /// Exponent of the response curve. `1.0` is linear, larger values give finer control near the center.
+    pub exponent: f32,
+}
+
+impl Default for StickResponse {
+    fn default() -> Self {
+        StickResponse {
+            dead_zone: 0.15,
+            exponent: 2.0,
+        }
+    }
+}
+
+impl StickResponse {
+    /// Remaps raw axis value so that the output starts from zero right at the edge of the dead zone.
+    pub fn apply(&self, value: f32) -> f32 {
+        let magnitude = value.abs();
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+        let range = (1.0 - self.dead_zone).max(std::f32::EPSILON);
+        let scaled = ((magnitude - self.dead_zone) / range).min(1.0);
+        scaled.powf(self.exponent) * value.signum()
+    }
+}
+
+// endregion
+
 // region - Creative Fly
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
@@ -146,6 +278,26 @@ impl Component for CreativeMovementControlTag {
     type Storage = NullStorage<CreativeMovementControlTag>;
 }
 
+/// World space velocity of a `CreativeMovementControlTag` entity, in meters per second.
+///
+/// Written by `CreativeMovementSystem` every frame, other systems can read it to react to camera motion.
+/// Inserted automatically for tagged entities that don't have one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraVelocity(pub Vector3<f32>);
+
+impl Default for CameraVelocity {
+    fn default() -> Self {
+        CameraVelocity(Vector3::zeros())
+    }
+}
+
+impl Component for CameraVelocity {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Velocity below which a decelerating camera snaps to a full stop.
+const STOP_VELOCITY: f32 = 1.0e-3;
+
 /// The system that manages the creative movement.
 ///
 /// # Type parameters
@@ -157,40 +309,85 @@ pub struct CreativeMovementSystem<T>
 where
     T: BindingTypes,
 {
-    speed: f32,
     side_input_axis: Option<T::Axis>,
     up_input_axis: Option<T::Axis>,
     forward_input_axis: Option<T::Axis>,
+    sprint_action: Option<T::Action>,
+    precision_action: Option<T::Action>,
 }
 
 impl<'a, T: BindingTypes> System<'a> for CreativeMovementSystem<T> {
     type SystemData = (
+        Entities<'a>,
         Read<'a, Time>,
         WriteStorage<'a, Transform>,
+        WriteStorage<'a, CameraVelocity>,
         Read<'a, InputHandler<T>>,
+        Write<'a, CameraControlSettings>,
         ReadStorage<'a, CreativeMovementControlTag>,
     );
 
-    fn run(&mut self, (time, mut transform, input, tag): Self::SystemData) {
+    fn run(
+        &mut self, (entities, time, mut transform, mut velocity, input, mut settings, tag): Self::SystemData,
+    ) {
         // #[cfg(feature = "profiler")]
         // profile_scope!("fly_movement_system");
 
-        let x = get_input_axis_simple(&self.side_input_axis, &input);
-        let y = get_input_axis_simple(&self.up_input_axis, &input);
-        let z = get_input_axis_simple(&self.forward_input_axis, &input);
+        let missing: Vec<Entity> = (&entities, &tag, !&velocity).join().map(|(e, _, _)| e).collect();
+        for entity in missing {
+            velocity
+                .insert(entity, CameraVelocity::default())
+                .expect("Entity was just joined, so it is alive");
+        }
 
-        if let Some(dir) = Unit::try_new(Vector3::new(0.0, y, 0.0), convert(1.0e-1)) {
-            for (transform, _) in (&mut transform, &tag).join() {
-                let delta_sec = time.delta_seconds();
-                transform.prepend_translation_along(dir, delta_sec * self.speed);
-            }
+        // Each wheel notch scales the base speed, so the same gesture works for building and for crossing the map.
+        let wheel = input.mouse_wheel_value(false);
+        if wheel != 0.0 {
+            settings.speed = (settings.speed * settings.scroll_speed_step.powf(wheel))
+                .max(settings.min_speed)
+                .min(settings.max_speed);
         }
 
-        if let Some(dir) = Unit::try_new(Vector3::new(x, 0.0, z), convert(1.0e-1)) {
-            for (transform, _) in (&mut transform, &tag).join() {
-                let delta_sec = time.delta_seconds();
-                transform.append_translation_along(dir, delta_sec * self.speed);
+        let x = settings.stick_response.apply(get_input_axis_simple(&self.side_input_axis, &input));
+        let y = settings.stick_response.apply(get_input_axis_simple(&self.up_input_axis, &input));
+        let z = settings.stick_response.apply(get_input_axis_simple(&self.forward_input_axis, &input));
+
+        let is_down = |action: &Option<T::Action>| {
+            action
+                .as_ref()
+                .and_then(|action| input.action_is_down(action))
+                .unwrap_or(false)
+        };
+        let mut speed = settings.speed;
+        if is_down(&self.sprint_action) {
+            speed *= settings.sprint_multiplier;
+        }
+        if is_down(&self.precision_action) {
+            speed *= settings.precision_multiplier;
+        }
+
+        // Partial stick deflection scales the speed, full deflection and keys move at `speed`.
+        let mut horizontal = Vector3::new(x, 0.0, z);
+        if horizontal.norm() > 1.0 {
+            horizontal.normalize_mut();
+        }
+        let vertical = y.max(-1.0).min(1.0);
+
+        let delta_sec = time.delta_seconds();
+        for (transform, velocity, _) in (&mut transform, &mut velocity, &tag).join() {
+            // Horizontal input follows where the camera looks, vertical input is always along world up.
+            let target = (transform.rotation() * horizontal + Vector3::y() * vertical) * speed;
+            let rate = if target.norm_squared() > velocity.0.norm_squared() {
+                settings.acceleration
+            } else {
+                settings.deceleration
+            };
+            // Exponential approach keeps the response identical at any frame rate.
+            velocity.0 += (target - velocity.0) * (1.0 - (-rate * delta_sec).exp());
+            if target.norm_squared() == 0.0 && velocity.0.norm() < STOP_VELOCITY {
+                velocity.0 = Vector3::zeros();
             }
+            transform.prepend_translation(velocity.0 * delta_sec);
         }
     }
 }
@@ -208,59 +405,61 @@ impl Component for MouseControlTag {
     type Storage = NullStorage<MouseControlTag>;
 }
 
+/// The system that rotates `MouseControlTag` entities from mouse motion and gamepad look axes.
+///
+/// # Type parameters
+///
+/// * `T`: This are the keys the `InputHandler` is using for axes and actions. Often, this is a `StringBindings`.
 #[derive(Debug)]
-pub struct MouseRotationSystem {
-    sensitivity_x: f32,
-    sensitivity_y: f32,
+pub struct MouseRotationSystem<T: BindingTypes> {
+    look_x_input_axis: Option<T::Axis>,
+    look_y_input_axis: Option<T::Axis>,
     // #[system_desc(event_channel_reader)]
     event_reader: ReaderId<Event>,
 }
 
 #[derive(Debug)]
-pub struct MouseRotationSystemDesc {
-    sensitivity_x: f32,
-    sensitivity_y: f32,
+pub struct MouseRotationSystemDesc<T: BindingTypes> {
+    look_x_input_axis: Option<T::Axis>,
+    look_y_input_axis: Option<T::Axis>,
 }
 
-impl MouseRotationSystemDesc {
-    fn new(sensitivity_x: f32, sensitivity_y: f32) -> Self {
+impl<T: BindingTypes> MouseRotationSystemDesc<T> {
+    fn new(look_x_input_axis: Option<T::Axis>, look_y_input_axis: Option<T::Axis>) -> Self {
         MouseRotationSystemDesc {
-            sensitivity_x,
-            sensitivity_y,
+            look_x_input_axis,
+            look_y_input_axis,
         }
     }
 }
 
-impl<'a, 'b> SystemDesc<'a, 'b, MouseRotationSystem> for MouseRotationSystemDesc {
-    fn build(self, world: &mut World) -> MouseRotationSystem {
-        <MouseRotationSystem as System<'_>>::SystemData::setup(world);
+impl<'a, 'b, T: BindingTypes> SystemDesc<'a, 'b, MouseRotationSystem<T>> for MouseRotationSystemDesc<T> {
+    fn build(self, world: &mut World) -> MouseRotationSystem<T> {
+        <MouseRotationSystem<T> as System<'_>>::SystemData::setup(world);
 
         let reader_id = world.fetch_mut::<EventChannel<Event>>().register_reader();
 
-        MouseRotationSystem::new(self.sensitivity_x, self.sensitivity_y, reader_id)
-    }
-}
-
-impl MouseRotationSystem {
-    pub fn new(sensitivity_x: f32, sensitivity_y: f32, event_reader: ReaderId<Event>) -> Self {
         MouseRotationSystem {
-            sensitivity_x,
-            sensitivity_y,
-            event_reader,
+            look_x_input_axis: self.look_x_input_axis,
+            look_y_input_axis: self.look_y_input_axis,
+            event_reader: reader_id,
         }
     }
 }
 
-impl<'a> System<'a> for MouseRotationSystem {
+impl<'a, T: BindingTypes> System<'a> for MouseRotationSystem<T> {
     type SystemData = (
         Read<'a, EventChannel<Event>>,
+        Read<'a, Time>,
+        Read<'a, InputHandler<T>>,
+        Read<'a, CameraControlSettings>,
         WriteStorage<'a, Transform>,
         ReadStorage<'a, MouseControlTag>,
         Read<'a, WindowFocus>,
         Read<'a, HideCursor>,
     );
 
-    fn run(&mut self, (events, mut transform, tag, focus, hide): Self::SystemData) {
+    fn run(&mut self, (events, time, input, settings, mut transform, tag, focus, hide): Self::SystemData) {
         let focused = focus.is_focused;
         for event in events.read(&mut self.event_reader) {
             if !focused || !hide.hide {
@@ -270,22 +469,43 @@ impl<'a> System<'a> for MouseRotationSystem {
             guard!(let DeviceEvent::MouseMotion { delta: (x, y) } = *event else { continue });
 
             for (transform, _) in (&mut transform, &tag).join() {
-                transform.append_rotation_x_axis((-(y as f32) * self.sensitivity_y).to_radians());
-                transform.prepend_rotation_y_axis((-(x as f32) * self.sensitivity_x).to_radians());
-                let angles = transform.euler_angles();
-                let z = angles.2;
-                let mut x = angles.0;
-                if z > -FRAC_PI_2 && z <= FRAC_PI_2 {
-                    x = x.max(-FRAC_PI_2).min(FRAC_PI_2);
-                } else if x < 0_f32 {
-                    x = x.max(-PI).min(-FRAC_PI_2)
-                } else {
-                    x = x.max(FRAC_PI_2).min(PI);
-                }
-                transform.set_rotation_euler(x, angles.1, z);
+                rotate(transform, -(x as f32) * settings.sensitivity_x, -(y as f32) * settings.sensitivity_y);
             }
         }
+
+        if !focused || !hide.hide {
+            return;
+        }
+
+        // Sticks report a deflection rather than a delta, so they rotate at `look_speed` per second.
+        let look_x = settings.stick_response.apply(get_input_axis_simple(&self.look_x_input_axis, &input));
+        let look_y = settings.stick_response.apply(get_input_axis_simple(&self.look_y_input_axis, &input));
+        if look_x == 0.0 && look_y == 0.0 {
+            return;
+        }
+        let step = settings.look_speed * time.delta_seconds();
+        for (transform, _) in (&mut transform, &tag).join() {
+            rotate(transform, -look_x * step, -look_y * step);
+        }
     }
+}
 
-    // endregion
+/// Applies yaw around the world up axis and pitch around the local horizontal axis, both in degrees,
+/// keeping the pitch within straight up and straight down.
+fn rotate(transform: &mut Transform, yaw: f32, pitch: f32) {
+    transform.append_rotation_x_axis(pitch.to_radians());
+    transform.prepend_rotation_y_axis(yaw.to_radians());
+    let angles = transform.euler_angles();
+    let z = angles.2;
+    let mut x = angles.0;
+    if z > -FRAC_PI_2 && z <= FRAC_PI_2 {
+        x = x.max(-FRAC_PI_2).min(FRAC_PI_2);
+    } else if x < 0_f32 {
+        x = x.max(-PI).min(-FRAC_PI_2)
+    } else {
+        x = x.max(FRAC_PI_2).min(PI);
+    }
+    transform.set_rotation_euler(x, angles.1, z);
 }
+
+// endregion
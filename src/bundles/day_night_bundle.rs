@@ -0,0 +1,307 @@
+use amethyst::{
+    core::{bundle::SystemBundle, math::Vector3, timing::Time, SystemDesc},
+    derive::SystemDesc,
+    ecs::{
+        prelude::{Component, DispatcherBuilder, Join, NullStorage, World},
+        Read, ReadStorage, System, SystemData, Write, WriteExpect, WriteStorage,
+    },
+    error::Error,
+    input::{BindingTypes, InputHandler},
+    renderer::{
+        light::{DirectionalLight, Light},
+        palette::{Srgb, Srgba},
+        pass::SkyboxSettings,
+        resources::AmbientColor,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::render_fog::Fog;
+
+// region - Day Night Bundle
+
+/// The bundle that animates the sun, the sky and the fog over the day.
+///
+/// Note: Will not create the sun. Add a `SunTag` to an entity with a `Light` and a `Transform` to have it
+/// driven by the cycle.
+///
+/// # Type parameters
+///
+/// * `T`: This are the keys the `InputHandler` is using for axes and actions. Often, this is a `StringBindings`.
+///
+/// # Systems
+///
+/// This bundle adds the following systems:
+///
+/// * `DayNightSystem`
+#[derive(Debug)]
+pub struct DayNightBundle<T: BindingTypes> {
+    time_of_day: TimeOfDay,
+    pause_action: Option<T::Action>,
+    backward_action: Option<T::Action>,
+    forward_action: Option<T::Action>,
+}
+
+impl<T: BindingTypes> DayNightBundle<T> {
+    pub fn new() -> Self {
+        DayNightBundle {
+            time_of_day: TimeOfDay::default(),
+            pause_action: None,
+            backward_action: None,
+            forward_action: None,
+        }
+    }
+
+    /// Alters the duration of a full day, in seconds.
+    pub fn with_day_length(mut self, day_length: f32) -> Self {
+        self.time_of_day.day_length = day_length;
+        self
+    }
+
+    /// Alters the time of day the world starts at, as a fraction of the day.
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time_of_day.time = time.rem_euclid(1.0);
+        self
+    }
+
+    /// Action that toggles the cycle on and off.
+    pub fn with_pause_action(mut self, pause_action: Option<T::Action>) -> Self {
+        self.pause_action = pause_action;
+        self
+    }
+
+    /// Actions that move the time backward and forward while held.
+    pub fn with_scrub_actions(mut self, backward_action: Option<T::Action>, forward_action: Option<T::Action>) -> Self {
+        self.backward_action = backward_action;
+        self.forward_action = forward_action;
+        self
+    }
+
+    /// Alters how fast the scrub actions move the time, in days per second.
+    pub fn with_scrub_speed(mut self, scrub_speed: f32) -> Self {
+        self.time_of_day.scrub_speed = scrub_speed;
+        self
+    }
+}
+
+impl<'a, 'b, T: BindingTypes> SystemBundle<'a, 'b> for DayNightBundle<T> {
+    fn build(self, world: &mut World, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let sky = SkyState::at(self.time_of_day.time);
+        world.insert(SkyboxSettings {
+            zenith_color: sky.zenith,
+            nadir_color: sky.nadir,
+        });
+        world.insert(self.time_of_day);
+        builder.add(
+            DayNightSystemDesc::<T>::new(self.pause_action, self.backward_action, self.forward_action).build(world),
+            "day_night",
+            &[],
+        );
+        Ok(())
+    }
+}
+
+// endregion
+
+// region - Time Of Day
+
+/// Current time of the day cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeOfDay {
+    /// Fraction of the day in the `0.0..1.0` range, `0.0` is midnight, `0.25` sunrise and `0.5` noon.
+    pub time: f32,
+    /// Duration of a full day, in seconds.
+    pub day_length: f32,
+    /// Stops the time from advancing, scrubbing still works.
+    pub paused: bool,
+    /// Scrubbing speed, in days per second.
+    pub scrub_speed: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay {
+            time: 0.35,
+            day_length: 600.0,
+            paused: false,
+            scrub_speed: 0.1,
+        }
+    }
+}
+
+/// Add this to the directional light that should act as the sun and the moon.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SunTag;
+
+impl Component for SunTag {
+    type Storage = NullStorage<SunTag>;
+}
+
+// endregion
+
+// region - Sky
+
+/// Lighting of the world at a single moment of the day.
+#[derive(Debug, Clone, Copy)]
+struct SkyState {
+    zenith: Srgb,
+    nadir: Srgb,
+    ambient: Srgb,
+    fog: Srgb,
+    fog_density: f32,
+    light: Srgb,
+    light_intensity: f32,
+}
+
+/// Key moments of the day, interpolated in between. Must be sorted by time and wrap around midnight.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const SKY_KEYS: [(f32, SkyState); 7] = [
+    (0.00, SkyState { zenith: srgb(0.01, 0.01, 0.05), nadir: srgb(0.03, 0.03, 0.08), ambient: srgb(0.03, 0.03, 0.05),
+                      fog: srgb(0.02, 0.02, 0.05), fog_density: 0.020, light: srgb(0.60, 0.70, 1.00), light_intensity: 0.15 }),
+    (0.22, SkyState { zenith: srgb(0.05, 0.05, 0.20), nadir: srgb(0.30, 0.20, 0.30), ambient: srgb(0.05, 0.05, 0.08),
+                      fog: srgb(0.20, 0.15, 0.25), fog_density: 0.025, light: srgb(0.60, 0.70, 1.00), light_intensity: 0.05 }),
+    (0.27, SkyState { zenith: srgb(0.18, 0.11, 0.85), nadir: srgb(0.82, 0.51, 0.50), ambient: srgb(0.10, 0.08, 0.08),
+                      fog: srgb(0.80, 0.55, 0.50), fog_density: 0.020, light: srgb(1.00, 0.60, 0.40), light_intensity: 0.40 }),
+    (0.50, SkyState { zenith: srgb(0.25, 0.50, 0.95), nadir: srgb(0.70, 0.85, 1.00), ambient: srgb(0.15, 0.15, 0.15),
+                      fog: srgb(0.70, 0.80, 0.95), fog_density: 0.008, light: srgb(1.00, 0.95, 0.90), light_intensity: 1.00 }),
+    (0.73, SkyState { zenith: srgb(0.18, 0.11, 0.85), nadir: srgb(0.82, 0.51, 0.50), ambient: srgb(0.10, 0.08, 0.08),
+                      fog: srgb(0.80, 0.55, 0.50), fog_density: 0.020, light: srgb(1.00, 0.60, 0.40), light_intensity: 0.40 }),
+    (0.78, SkyState { zenith: srgb(0.05, 0.05, 0.20), nadir: srgb(0.30, 0.20, 0.30), ambient: srgb(0.05, 0.05, 0.08),
+                      fog: srgb(0.20, 0.15, 0.25), fog_density: 0.025, light: srgb(0.60, 0.70, 1.00), light_intensity: 0.05 }),
+    (1.00, SkyState { zenith: srgb(0.01, 0.01, 0.05), nadir: srgb(0.03, 0.03, 0.08), ambient: srgb(0.03, 0.03, 0.05),
+                      fog: srgb(0.02, 0.02, 0.05), fog_density: 0.020, light: srgb(0.60, 0.70, 1.00), light_intensity: 0.15 }),
+];
+
+const fn srgb(red: f32, green: f32, blue: f32) -> Srgb {
+    Srgb {
+        red,
+        green,
+        blue,
+        standard: std::marker::PhantomData,
+    }
+}
+
+impl SkyState {
+    fn at(time: f32) -> Self {
+        let time = time.rem_euclid(1.0);
+        let next = SKY_KEYS.iter().position(|(key, _)| *key > time).unwrap_or(SKY_KEYS.len() - 1);
+        let (from_time, from) = SKY_KEYS[next - 1];
+        let (to_time, to) = SKY_KEYS[next];
+        let t = ((time - from_time) / (to_time - from_time)).max(0.0).min(1.0);
+        SkyState {
+            zenith: mix(from.zenith, to.zenith, t),
+            nadir: mix(from.nadir, to.nadir, t),
+            ambient: mix(from.ambient, to.ambient, t),
+            fog: mix(from.fog, to.fog, t),
+            fog_density: from.fog_density + (to.fog_density - from.fog_density) * t,
+            light: mix(from.light, to.light, t),
+            light_intensity: from.light_intensity + (to.light_intensity - from.light_intensity) * t,
+        }
+    }
+}
+
+fn mix(from: Srgb, to: Srgb, t: f32) -> Srgb {
+    Srgb::new(
+        from.red + (to.red - from.red) * t,
+        from.green + (to.green - from.green) * t,
+        from.blue + (to.blue - from.blue) * t,
+    )
+}
+
+/// Direction the light travels in: from the sun during the day, from the moon opposite to it at night.
+fn light_direction(time: f32) -> Vector3<f32> {
+    let angle = (time - 0.25) * 2.0 * PI;
+    // The sun rises in +X, sets in -X and leans slightly towards -Z so noon shadows are not straight down.
+    let sun = Vector3::new(angle.cos(), angle.sin(), -0.3).normalize();
+    if sun.y >= 0.0 {
+        -sun
+    } else {
+        sun
+    }
+}
+
+// endregion
+
+// region - System
+
+/// The system that advances the time of day and applies it to the sun, the sky, the ambient light and the fog.
+///
+/// # Type parameters
+///
+/// * `T`: This are the keys the `InputHandler` is using for axes and actions. Often, this is a `StringBindings`.
+#[derive(Debug, SystemDesc)]
+#[system_desc(name(DayNightSystemDesc))]
+pub struct DayNightSystem<T>
+where
+    T: BindingTypes,
+{
+    pause_action: Option<T::Action>,
+    backward_action: Option<T::Action>,
+    forward_action: Option<T::Action>,
+    #[system_desc(skip)]
+    pause_was_down: bool,
+}
+
+impl<'a, T: BindingTypes> System<'a> for DayNightSystem<T> {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, InputHandler<T>>,
+        Write<'a, TimeOfDay>,
+        Write<'a, AmbientColor>,
+        Write<'a, Fog>,
+        WriteExpect<'a, SkyboxSettings>,
+        WriteStorage<'a, Light>,
+        ReadStorage<'a, SunTag>,
+    );
+
+    fn run(
+        &mut self,
+        (time, input, mut time_of_day, mut ambient, mut fog, mut skybox, mut lights, sun): Self::SystemData,
+    ) {
+        let is_down = |action: &Option<T::Action>| {
+            action
+                .as_ref()
+                .and_then(|action| input.action_is_down(action))
+                .unwrap_or(false)
+        };
+
+        let pause_down = is_down(&self.pause_action);
+        if pause_down && !self.pause_was_down {
+            time_of_day.paused = !time_of_day.paused;
+        }
+        self.pause_was_down = pause_down;
+
+        let delta_sec = time.delta_seconds();
+        let mut advance = 0.0;
+        if !time_of_day.paused && time_of_day.day_length > 0.0 {
+            advance += delta_sec / time_of_day.day_length;
+        }
+        if is_down(&self.forward_action) {
+            advance += delta_sec * time_of_day.scrub_speed;
+        }
+        if is_down(&self.backward_action) {
+            advance -= delta_sec * time_of_day.scrub_speed;
+        }
+        time_of_day.time = (time_of_day.time + advance).rem_euclid(1.0);
+
+        let sky = SkyState::at(time_of_day.time);
+        skybox.zenith_color = sky.zenith;
+        skybox.nadir_color = sky.nadir;
+        ambient.0 = Srgba::new(sky.ambient.red, sky.ambient.green, sky.ambient.blue, 1.0);
+        fog.color = sky.fog;
+        fog.density = sky.fog_density;
+
+        let direction = light_direction(time_of_day.time);
+        for (light, _) in (&mut lights, &sun).join() {
+            *light = Light::Directional(DirectionalLight {
+                color: sky.light,
+                intensity: sky.light_intensity,
+                direction,
+            });
+        }
+    }
+}
+
+// endregion
@@ -1 +1,2 @@
-pub mod camera_control_bundle;
\ No newline at end of file
+pub mod camera_control_bundle;
+pub mod day_night_bundle;
\ No newline at end of file
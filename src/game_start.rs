@@ -1,10 +1,24 @@
 // use std::path::PathBuf;
 use crate::render_voxel::{Voxel, Material};
 use crate::bundles::camera_control_bundle::{CreativeMovementControlTag, MouseControlTag};
+use crate::bundles::day_night_bundle::{SunTag, TimeOfDay};
+use crate::systems::chunk_streaming::UnsavedChunks;
+use crate::systems::controls_menu::CONTROLS_MENU_UI_ID;
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{split_block_pos, Chunk, ChunkPos};
+use crate::world::level::{CameraState, Level};
+use crate::world::save::WorldSave;
+use crate::world::voxel_world::VoxelWorld;
+use crate::worldgen::biome::{BiomeMap, BiomeSettings, BiomeStage};
+use crate::worldgen::caves::{CaveCarver, CaveSettings};
+use crate::worldgen::decoration::{DecorationSettings, DecorationStage};
+use crate::worldgen::generator::{ActiveGenerator, GeneratorPipeline};
+use crate::worldgen::structure::StructureTemplate;
+use crate::worldgen::terrain::{HeightmapGenerator, TerrainBlocks, TerrainSettings};
 
 use amethyst::{
     // assets::{AssetStorage, Loader, Handle},
-    assets::{Loader},
+    assets::{AssetStorage, Handle, Loader, ProgressCounter, RonFormat},
     controls::HideCursor,
     core::{
         math::{Point3, Vector3},
@@ -12,13 +26,13 @@ use amethyst::{
     },
     // assets::RonFormat,
     // core::transform::TransformBundle,
-    ecs::{WorldExt, Write}, //prelude::Write, EntityBuilder, },
+    ecs::{Join, WorldExt}, //prelude::Write, EntityBuilder, },
     // error::Error,
     input::{is_key_down, is_mouse_button_down},
     prelude::*,
     renderer::{
         debug_drawing::DebugLinesComponent, // DebugLine, DebugLines, DebugLinesParams},
-        light::{Light, PointLight},         //, SunLight},
+        light::{DirectionalLight, Light, PointLight}, //, SunLight},
         // ImageFormat, SpriteRender, SpriteSheet, SpriteSheetFormat, Texture,
         // mtl::{Material as AmethystMaterial, MaterialDefaults},
         palette::{Srgb, Srgba},
@@ -27,28 +41,41 @@ use amethyst::{
         // texture::palette::{load_from_linear_rgba, load_from_srgb, load_from_srgba},
         // util::types::vertex::{Color, PosColor, PosTex},
         // },
-        resources::AmbientColor,
+        // resources::AmbientColor,
         // shape::Shape,
         // types::{Mesh, MeshData}, //, Texture},
         Camera,
         // ImageFormat,
         // Texture,
     },
-    ui::{Anchor, TtfFormat, UiText, UiTransform},
-    utils::auto_fov::AutoFov,
+    ui::{Anchor, LineMode, TtfFormat, UiText, UiTransform},
+    utils::{application_root_dir, auto_fov::AutoFov},
     window::ScreenDimensions,
     winit::{MouseButton, VirtualKeyCode},
 };
 
+use std::collections::HashMap;
 use std::f32::consts::{FRAC_PI_4, FRAC_PI_8};
 
 extern crate rand;
 use rand::distributions::{Distribution, Uniform};
 
-pub struct GameStart;
+/// Starting state. The level is loaded right away, the world generator and the camera wait for the structure
+/// templates to load, the terrain is then streamed in around the camera.
+#[derive(Default)]
+pub struct GameStart {
+    progress: ProgressCounter,
+    structures: Vec<(String, Handle<StructureTemplate>)>,
+    level: Level,
+    generator_started: bool,
+}
 
-const SPHERE_RADIUS: f32 = 6.0_f32;
+const _SPHERE_RADIUS: f32 = 6.0_f32;
 const CAMERA_DISTANCE_M: f32 = 6.0_f32;
+/// Seed of new worlds.
+const WORLD_SEED: u64 = 51;
+/// Save directory, relative to the application root.
+const SAVE_DIRECTORY: &str = "saves/world";
 
 impl SimpleState for GameStart {
     
@@ -56,13 +83,28 @@ impl SimpleState for GameStart {
         let world = data.world;
 
         spawn_axis(world);
+        self.level = load_level(world);
         // spawn_blocks(world);
-        spawn_block_sphere(world, SPHERE_RADIUS);
+        // _spawn_block_sphere(world, _SPHERE_RADIUS);
+        self.structures = load_structures(world, &DecorationSettings::default(), &mut self.progress);
         spawn_lights(world);
-        initialize_camera(world);
         initialize_ui(world);
     }
 
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        if !self.generator_started && self.progress.is_complete() {
+            let world = &mut *data.world;
+            let ground = start_generator(world, &self.level, &self.structures);
+            initialize_camera(world, ground, self.level.camera);
+            self.generator_started = true;
+        }
+        Trans::None
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        save_world(data.world, &mut self.level);
+    }
+
     fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
         let StateData { world, .. } = data;
         if let StateEvent::Window(event) = &event {
@@ -80,13 +122,20 @@ impl SimpleState for GameStart {
 
 // region - Camera
 
-fn initialize_camera(world: &mut World) {
-    let distance = CAMERA_DISTANCE_M;
-    let mut transform = Transform::default();
-    transform
-        .set_translation_xyz(2. * distance, 1. * distance, 2. * distance)
-        .append_rotation_y_axis(FRAC_PI_4)
-        .append_rotation_x_axis(-FRAC_PI_8);
+/// Places the camera where it was saved, or above the ground near the origin in a new world.
+fn initialize_camera(world: &mut World, ground: f32, saved: Option<CameraState>) {
+    let transform = match saved {
+        Some(saved) => saved.to_transform(),
+        None => {
+            let distance = CAMERA_DISTANCE_M;
+            let mut transform = Transform::default();
+            transform
+                .set_translation_xyz(2. * distance, ground + 1. * distance, 2. * distance)
+                .append_rotation_y_axis(FRAC_PI_4)
+                .append_rotation_x_axis(-FRAC_PI_8);
+            transform
+        }
+    };
 
     let (width, height) = {
         let dim = world.read_resource::<ScreenDimensions>();
@@ -124,6 +173,18 @@ fn initialize_ui(world: &mut World) {
         .with(transform)
         .with(UiText::new(font.clone(), "".to_string(), [1., 1., 1., 1.], 50.))
         .build();
+
+    let transform = UiTransform::new(
+        CONTROLS_MENU_UI_ID.to_string(), Anchor::TopLeft, Anchor::TopLeft,
+        0., -50., 1., 640., 430.,
+    );
+    let mut text = UiText::new(font, "".to_string(), [1., 1., 1., 1.], 18.);
+    text.line_mode = LineMode::Wrap;
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .build();
 }
 
 // endregion
@@ -132,9 +193,14 @@ fn initialize_ui(world: &mut World) {
 
 // 1 -1 2
 fn spawn_lights(world: &mut World) {
-    world.exec(|mut color: Write<'_, AmbientColor>| {
-        color.0 = Srgba::new(0.15, 0.15, 0.15, 1.0);
-    });
+    // Ambient color, sun color and direction are driven by `DayNightSystem`.
+    let sun: Light = DirectionalLight {
+        color: Srgb::new(1.0, 1.0, 1.0),
+        intensity: 0.0,
+        direction: Vector3::new(0.0, -1.0, 0.0),
+    }
+    .into();
+    world.create_entity().with(sun).with(SunTag).with(Transform::default()).build();
 
     let light1: Light = PointLight {
         intensity: 14.0,
@@ -231,8 +297,18 @@ fn spawn_block(world: &mut World, position: [i128; 3], material: Material) {
     block.create_entity(world).with(trans).build();
 }
 
-fn spawn_block_sphere(world: &mut World, radius: f32) {
-    let r = radius.ceil() as i128;
+fn _spawn_block_sphere(world: &mut World, radius: f32) {
+    let registry = world.read_resource::<BlockRegistry>().clone();
+    let grass = registry.id("grass").unwrap_or(BlockId::AIR);
+    let lantern = registry.id("lantern").unwrap_or(BlockId::AIR);
+
+    let mut chunks: HashMap<ChunkPos, Chunk> = HashMap::new();
+    let mut put = |position: [i32; 3], block: BlockId| {
+        let (chunk_pos, [x, y, z]) = split_block_pos(position);
+        chunks.entry(chunk_pos).or_default().set_block(x, y, z, block);
+    };
+
+    let r = radius.ceil() as i32;
     for x in -r..=r {
         for y in -r..=r {
             for z in -r..=r {
@@ -243,11 +319,124 @@ fn spawn_block_sphere(world: &mut World, radius: f32) {
                     (x * x + y * y + z * z).sqrt()
                 };
                 if distance <= radius {
-                    spawn_block(world, [x, y, z], Material::Grass);
+                    put([x, y, z], grass);
                 }
             }
         }
     }
+    // Lights the shadowed side of the sphere at night.
+    put([0, -r - 2, 0], lantern);
+
+    let mut voxel_world = world.write_resource::<VoxelWorld>();
+    // Top down, so the sky light of each chunk is known before the chunk below is lit.
+    let mut chunks: Vec<_> = chunks.into_iter().collect();
+    chunks.sort_by_key(|(pos, _)| (-pos.y, pos.x, pos.z));
+    for (pos, chunk) in chunks {
+        voxel_world.insert_chunk(pos, chunk, &registry);
+    }
+}
+
+fn load_structures(
+    world: &mut World, settings: &DecorationSettings, progress: &mut ProgressCounter,
+) -> Vec<(String, Handle<StructureTemplate>)> {
+    let loader = world.read_resource::<Loader>();
+    settings
+        .rules
+        .iter()
+        .map(|rule| {
+            let handle = loader.load(rule.template.as_str(), RonFormat, &mut *progress, &world.read_resource());
+            (rule.template.clone(), handle)
+        })
+        .collect()
+}
+
+/// Inserts the `ActiveGenerator` the chunks are streamed from, returns the surface height under the default camera.
+fn start_generator(world: &mut World, level: &Level, structures: &[(String, Handle<StructureTemplate>)]) -> f32 {
+    let seed = level.seed;
+    let registry = world.read_resource::<BlockRegistry>().clone();
+    let templates: HashMap<String, StructureTemplate> = {
+        let storage = world.read_resource::<AssetStorage<StructureTemplate>>();
+        structures
+            .iter()
+            .filter_map(|(path, handle)| storage.get(handle).map(|template| (path.clone(), template.clone())))
+            .collect()
+    };
+    let terrain_blocks = TerrainBlocks::from_registry(&registry);
+    let terrain = HeightmapGenerator::new(seed, TerrainSettings::default(), terrain_blocks);
+    let biomes = BiomeMap::new(seed, BiomeSettings::default(), &registry, terrain_blocks);
+    let decoration = DecorationStage::new(
+        seed, DecorationSettings::default(), &templates, &registry, terrain.clone(), biomes.clone(),
+    );
+    let mut generator = GeneratorPipeline::new()
+        .with_stage(terrain.clone())
+        .with_stage(BiomeStage::new(biomes, terrain_blocks));
+    if level.rules.caves {
+        generator = generator.with_stage(CaveCarver::new(seed, CaveSettings::default()));
+    }
+    if level.rules.structures {
+        generator = generator.with_stage(decoration);
+    }
+    world.insert(ActiveGenerator::new(generator));
+
+    let camera_column = (2. * CAMERA_DISTANCE_M) as i32;
+    terrain.surface_height(camera_column, camera_column) as f32
+}
+
+// endregion
+
+// region - Save
+
+/// Inserts the `WorldSave` and reads its level, or starts a new one. The time of day and the streaming settings
+/// are taken from the level.
+fn load_level(world: &mut World) -> Level {
+    let save = match application_root_dir() {
+        Ok(root) => WorldSave::new(root.join(SAVE_DIRECTORY)),
+        Err(error) => {
+            log::error!("World will not be saved, no application root: {}", error);
+            return Level::new(WORLD_SEED);
+        }
+    };
+    let level = match save.load_level() {
+        Ok(Some(level)) => level,
+        Ok(None) => Level::new(WORLD_SEED),
+        Err(error) => {
+            log::error!("Failed to load the level from {}, starting a new one: {}", save.directory().display(), error);
+            Level::new(WORLD_SEED)
+        }
+    };
+    world.insert(save);
+    world.write_resource::<TimeOfDay>().time = level.time_of_day;
+    world.insert(level.rules.streaming.clone());
+    level
+}
+
+/// Saves the level, the modified chunks still loaded and the unloaded ones the streaming did not write yet.
+fn save_world(world: &mut World, level: &mut Level) {
+    guard!(let Some(save) = world.try_fetch::<WorldSave>().map(|save| save.clone()) else { return });
+
+    {
+        let transforms = world.read_storage::<Transform>();
+        let tags = world.read_storage::<MouseControlTag>();
+        if let Some((transform, _)) = (&transforms, &tags).join().next() {
+            level.camera = Some(CameraState::from_transform(transform));
+        }
+    }
+    level.time_of_day = world.read_resource::<TimeOfDay>().time;
+    if let Err(error) = save.save_level(level) {
+        log::error!("Failed to save the level to {}: {}", save.directory().display(), error);
+    }
+
+    let registry = world.read_resource::<BlockRegistry>().clone();
+    if let Some(unsaved) = world.try_fetch::<UnsavedChunks>() {
+        if let Err(error) = save.save_chunks(unsaved.chunks(), &registry) {
+            log::error!("Failed to save unloaded chunks to {}: {}", save.directory().display(), error);
+        }
+    }
+    let mut voxel_world = world.write_resource::<VoxelWorld>();
+    match save.save_world(&mut voxel_world, &registry) {
+        Ok(saved) => log::info!("Saved {} chunks to {}", saved, save.directory().display()),
+        Err(error) => log::error!("Failed to save the world to {}: {}", save.directory().display(), error),
+    }
 }
 
 // endregion
@@ -2,6 +2,7 @@ use amethyst::ui::{UiBundle};
 use amethyst::utils::fps_counter::FpsCounterBundle;
 use amethyst::window::{WindowBundle};
 use amethyst::{
+    config::Config,
     core::transform::TransformBundle,
     input::{InputBundle, StringBindings},
     prelude::*,
@@ -19,30 +20,45 @@ mod game_start;
 mod render_macros;
 
 mod render_backend;
+mod render_bvh;
 mod render_cache;
 mod render_chunk;
+mod render_fog;
+mod render_gltf;
 mod render_graph;
 mod render_material;
 mod render_material_sub;
 mod render_mesh;
+mod render_obj;
 mod render_pass;
+mod render_prop;
 mod render_shader;
 mod render_system;
 mod render_vertex;
 mod render_visibility;
 mod render_voxel;
 mod systems;
+mod world;
+mod worldgen;
 
-use crate::bundles::camera_control_bundle::CameraControlBundle;
+use crate::bundles::camera_control_bundle::{CameraControlBundle, CameraControlSettings};
+use crate::bundles::day_night_bundle::DayNightBundle;
 use crate::game_start::GameStart;
+use crate::render_gltf::GltfScene;
 use crate::render_graph::RenderGraph;
-use crate::render_system::{ExtendedRenderingSystem, MeshProcessorSystem, TextureProcessorSystem};
+use crate::render_system::{ExtendedRenderingSystem, MeshProcessorSystem, MeshUpdateSystem, TextureProcessorSystem};
 use crate::render_material::Material;
+use crate::render_obj::ObjScene;
 
 use crate::render_backend::DefaultExtendedBackend as DefaultBackend;
-use crate::render_visibility::VisibilitySortingSystem;
+use crate::render_visibility::{MeshBoundsSystem, VisibilitySortingSystem};
 
+use crate::systems::chunk_mesh::ChunkMeshSystem;
+use crate::systems::chunk_streaming::ChunkStreamingSystem;
+use crate::systems::controls_menu::{ControlsConfigPaths, ControlsMenuSystemDesc};
 use crate::systems::ui::UISystem;
+use crate::world::vox::VoxScene;
+use crate::worldgen::structure::StructureTemplate;
 
 #[macro_use]
 extern crate guard;
@@ -59,17 +75,39 @@ fn main() -> Result<(), Error> {
 
     let display_config_path = app_root.join("config/display.ron");
 
-    let key_bindings_path = app_root.join("config/input.ron");
+    let controls_paths = ControlsConfigPaths {
+        bindings: app_root.join("config/user/input.ron"),
+        installed_bindings: app_root.join("config/input.ron"),
+        default_bindings: app_root.join("config/default/input.ron"),
+        settings: app_root.join("config/user/camera.ron"),
+    };
+
+    let key_bindings_path = controls_paths.bindings_to_load().to_path_buf();
+
+    let camera_settings = if controls_paths.settings.exists() {
+        CameraControlSettings::load(&controls_paths.settings)?
+    } else {
+        CameraControlSettings::default()
+    };
 
     let game_data = GameDataBuilder::default()
         .with_bundle(InputBundle::<StringBindings>::new().with_bindings_from_file(&key_bindings_path)?)?
         .with_bundle(
             CameraControlBundle::<StringBindings>::new()
-                .with_speed(3.0)
-                .with_sensitivity(0.1, 0.1)
+                .with_settings(camera_settings)
                 .with_side_input_axis(Some(String::from("move_side")))
                 .with_forward_input_axis(Some(String::from("move_forward")))
-                .with_up_input_axis(Some(String::from("move_up"))),
+                .with_up_input_axis(Some(String::from("move_up")))
+                .with_look_input_axes(Some(String::from("look_x")), Some(String::from("look_y")))
+                .with_sprint_action(Some(String::from("sprint")))
+                .with_precision_action(Some(String::from("precision"))),
+        )?
+        .with_system_desc(ControlsMenuSystemDesc::new(controls_paths), "controls_menu", &["input_system"])
+        .with_bundle(
+            DayNightBundle::<StringBindings>::new()
+                .with_day_length(600.0)
+                .with_pause_action(Some(String::from("time_pause")))
+                .with_scrub_actions(Some(String::from("time_backward")), Some(String::from("time_forward"))),
         )?
         .with_bundle(TransformBundle::new().with_dep(&["mouse_rotation", "creative_movement"]))?
         .with_bundle(UiBundle::<StringBindings>::new())?
@@ -78,12 +116,20 @@ fn main() -> Result<(), Error> {
         // The below Systems, are used to handle some rendering resources.
         // Most likely these must be always called as last thing.
         .with_system_desc(UiGlyphsSystemDesc::<DefaultBackend>::default(), "ui_glyph_system", &[])
-        .with(VisibilitySortingSystem::new(), "visibility_sorting_system", &[])
+        .with(ChunkStreamingSystem::default(), "chunk_streaming", &[])
+        .with(ChunkMeshSystem::default(), "chunk_mesh", &["chunk_streaming"])
         .with(
             MeshProcessorSystem::<DefaultBackend>::default(),
             "mesh_processor",
             &[],
         )
+        .with(
+            MeshUpdateSystem::<DefaultBackend>::default(),
+            "mesh_update",
+            &["mesh_processor", "chunk_mesh"],
+        )
+        .with(MeshBoundsSystem::default(), "mesh_bounds", &["mesh_update"])
+        .with(VisibilitySortingSystem::new(), "visibility_sorting_system", &["chunk_mesh", "mesh_bounds"])
         .with(
             TextureProcessorSystem::<DefaultBackend>::default(),
             "texture_processor",
@@ -91,6 +137,10 @@ fn main() -> Result<(), Error> {
         )
         .with(UISystem::default(), "ui_system", &[])
         .with(Processor::<Material>::new(), "material_processor", &[])
+        .with(Processor::<StructureTemplate>::new(), "structure_processor", &[])
+        .with(Processor::<VoxScene>::new(), "vox_processor", &[])
+        .with(Processor::<ObjScene>::new(), "obj_processor", &[])
+        .with(Processor::<GltfScene>::new(), "gltf_processor", &[])
         .with_bundle(WindowBundle::from_config_path(display_config_path)?)?
         // The renderer must be executed on the same thread consecutively, so we initialize it as thread_local
         // which will always execute on the main thread.
@@ -98,7 +148,7 @@ fn main() -> Result<(), Error> {
             RenderGraph::default(),
         ));
 
-    let mut game = Application::build(assets_dir, GameStart)?.build(game_data)?;
+    let mut game = Application::build(assets_dir, GameStart::default())?.build(game_data)?;
     game.run();
     Ok(())
 }
@@ -19,6 +19,8 @@ use crate::render_mesh::{Mesh, GenericMesh};
 pub trait IExtendedBackend: Backend {
     fn unwrap_mesh_element(mesh: &Mesh) -> Option<&GenericMesh<Self>>;
 
+    fn unwrap_mesh_element_mut(mesh: &mut Mesh) -> Option<&mut GenericMesh<Self>>;
+
     fn wrap_mesh_element(mesh: GenericMesh<Self>) -> Mesh;
 }
 
@@ -136,6 +138,15 @@ impl IExtendedBackend for rendy::metal::Backend {
         }
     }
     #[inline]
+    #[allow(irrefutable_let_patterns)]
+    fn unwrap_mesh_element_mut(mesh: &mut Mesh) -> Option<&mut GenericMesh<Self>> {
+        if let Mesh::Metal(inner) = mesh {
+            Some(inner)
+        } else {
+            None
+        }
+    }
+    #[inline]
     fn wrap_mesh_element(mesh: GenericMesh<Self>) -> Mesh {
         Mesh::Metal(mesh)
     }
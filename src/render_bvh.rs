@@ -0,0 +1,398 @@
+//! Bounding volume hierarchy of entity boxes, for frustum culling in roughly logarithmic time.
+use amethyst::core::ecs::Entity;
+use std::collections::HashMap;
+
+use crate::render_visibility::{AxisAlignedBoundingBox, Containment, Frustum};
+
+/// Margin added around leaf boxes, so that small moves do not reinsert them.
+const LEAF_MARGIN: f32 = 0.5;
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    /// Entity and its tight box, the node box being the enlarged one.
+    Leaf(Entity, AxisAlignedBoundingBox),
+    Branch([usize; 2]),
+    Free,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    aabb: AxisAlignedBoundingBox,
+    parent: Option<usize>,
+    /// Levels below the node, `0` for leaves.
+    height: usize,
+    kind: NodeKind,
+}
+
+/// Dynamic tree of world space entity boxes, updated incrementally as entities move.
+///
+/// Leaves are inserted next to the node whose box grows the least, and branches whose children heights differ by more
+/// than one are rotated on the way back up, both as in Box2D's dynamic tree. Leaf boxes are enlarged by `LEAF_MARGIN`
+/// and only reinserted once the entity leaves them.
+#[derive(Debug, Default)]
+pub struct BoundsTree {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// Leaf of each entity, by entity id.
+    leaves: HashMap<u32, usize>,
+}
+
+impl BoundsTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.leaves.contains_key(&entity.id())
+    }
+
+    /// Levels of the tree, `0` when empty.
+    pub fn depth(&self) -> usize {
+        fn depth(nodes: &[Node], node: usize) -> usize {
+            match nodes[node].kind {
+                NodeKind::Branch([left, right]) => 1 + depth(nodes, left).max(depth(nodes, right)),
+                _ => 1,
+            }
+        }
+        self.root.map_or(0, |root| depth(&self.nodes, root))
+    }
+
+    /// Inserts the entity, or moves it when already there. Returns whether its leaf was reinserted.
+    pub fn update(&mut self, entity: Entity, aabb: AxisAlignedBoundingBox) -> bool {
+        if let Some(&leaf) = self.leaves.get(&entity.id()) {
+            if self.nodes[leaf].aabb.contains(&aabb) {
+                self.nodes[leaf].kind = NodeKind::Leaf(entity, aabb);
+                return false;
+            }
+            self.remove_leaf(leaf);
+        }
+        let leaf = self.allocate(Node {
+            aabb: aabb.grown(LEAF_MARGIN),
+            parent: None,
+            height: 0,
+            kind: NodeKind::Leaf(entity, aabb),
+        });
+        self.insert_leaf(leaf);
+        self.leaves.insert(entity.id(), leaf);
+        true
+    }
+
+    /// Removes the entity with this id, returns whether it was there.
+    pub fn remove(&mut self, id: u32) -> bool {
+        guard!(let Some(leaf) = self.leaves.remove(&id) else { return false });
+        self.remove_leaf(leaf);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.leaves.clear();
+    }
+
+    /// Calls `visit` with every entity whose box is in the frustum. Subtrees fully inside are accepted without
+    /// testing their leaves.
+    pub fn query<F: FnMut(Entity)>(&self, frustum: &Frustum, mut visit: F) {
+        guard!(let Some(root) = self.root else { return });
+        let mut stack = vec![(root, false)];
+        while let Some((node, inside)) = stack.pop() {
+            let node = &self.nodes[node];
+            let inside = inside || {
+                match frustum.classify_aabb(&node.aabb) {
+                    Containment::Outside => continue,
+                    Containment::Inside => true,
+                    Containment::Intersecting => false,
+                }
+            };
+            match node.kind {
+                NodeKind::Leaf(entity, ref aabb) => {
+                    if inside || frustum.check_aabb(aabb) {
+                        visit(entity);
+                    }
+                }
+                NodeKind::Branch([left, right]) => {
+                    stack.push((left, inside));
+                    stack.push((right, inside));
+                }
+                NodeKind::Free => {}
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: usize) {
+        self.nodes[index].kind = NodeKind::Free;
+        self.nodes[index].parent = None;
+        self.free.push(index);
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        guard!(let Some(root) = self.root else {
+            self.root = Some(leaf);
+            return;
+        });
+        let aabb = self.nodes[leaf].aabb;
+
+        // Descends towards the child whose box grows the least, until a leaf or a node where stopping is cheaper.
+        let mut sibling = root;
+        while let NodeKind::Branch([left, right]) = self.nodes[sibling].kind {
+            let area = self.nodes[sibling].aabb.surface_area();
+            let combined = self.nodes[sibling].aabb.union(&aabb).surface_area();
+            let cost_here = 2.0 * combined;
+            let inherited = 2.0 * (combined - area);
+            let cost = |child: usize| {
+                let child = &self.nodes[child];
+                let grown = child.aabb.union(&aabb).surface_area();
+                match child.kind {
+                    NodeKind::Leaf(..) => grown + inherited,
+                    _ => grown - child.aabb.surface_area() + inherited,
+                }
+            };
+            let (cost_left, cost_right) = (cost(left), cost(right));
+            if cost_here < cost_left && cost_here < cost_right {
+                break;
+            }
+            sibling = if cost_left <= cost_right { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let parent = self.allocate(Node {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent: old_parent,
+            height: self.nodes[sibling].height + 1,
+            kind: NodeKind::Branch([sibling, leaf]),
+        });
+        self.nodes[sibling].parent = Some(parent);
+        self.nodes[leaf].parent = Some(parent);
+        match old_parent {
+            Some(old_parent) => self.replace_child(old_parent, sibling, parent),
+            None => self.root = Some(parent),
+        }
+        self.refit(old_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        let parent = self.nodes[leaf].parent;
+        self.release(leaf);
+        guard!(let Some(parent) = parent else {
+            self.root = None;
+            return;
+        });
+        let sibling = match self.nodes[parent].kind {
+            NodeKind::Branch([left, right]) => {
+                if left == leaf {
+                    right
+                } else {
+                    left
+                }
+            }
+            _ => unreachable!("the parent of a leaf is a branch"),
+        };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        match grandparent {
+            Some(grandparent) => self.replace_child(grandparent, parent, sibling),
+            None => self.root = Some(sibling),
+        }
+        self.release(parent);
+        self.refit(grandparent);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let NodeKind::Branch(ref mut children) = self.nodes[parent].kind {
+            for child in children.iter_mut().filter(|child| **child == old) {
+                *child = new;
+            }
+        }
+    }
+
+    /// Recomputes the boxes and heights from `node` up to the root, balancing the branches on the way.
+    fn refit(&mut self, mut node: Option<usize>) {
+        while let Some(index) = node {
+            let index = self.balance(index);
+            self.fit(index);
+            node = self.nodes[index].parent;
+        }
+    }
+
+    /// Box and height of a branch from its children.
+    fn fit(&mut self, node: usize) {
+        if let NodeKind::Branch([left, right]) = self.nodes[node].kind {
+            self.nodes[node].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            self.nodes[node].height = 1 + self.nodes[left].height.max(self.nodes[right].height);
+        }
+    }
+
+    /// Rotates the taller child of `node` in its place when it is more than one level taller than the other.
+    /// Returns the node now in that place.
+    fn balance(&mut self, node: usize) -> usize {
+        guard!(let NodeKind::Branch([left, right]) = self.nodes[node].kind else { return node });
+        let (left_height, right_height) = (self.nodes[left].height, self.nodes[right].height);
+        if right_height > left_height + 1 {
+            self.rotate(node, 1)
+        } else if left_height > right_height + 1 {
+            self.rotate(node, 0)
+        } else {
+            node
+        }
+    }
+
+    /// Moves the child `side` of `node` up in its place. `node` becomes its child and takes its shorter child.
+    fn rotate(&mut self, node: usize, side: usize) -> usize {
+        guard!(let NodeKind::Branch(mut children) = self.nodes[node].kind else { return node });
+        let raised = children[side];
+        guard!(let NodeKind::Branch([first, second]) = self.nodes[raised].kind else { return node });
+        let (taller, shorter) = if self.nodes[first].height > self.nodes[second].height {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let parent = self.nodes[node].parent;
+        self.nodes[raised].parent = parent;
+        match parent {
+            Some(parent) => self.replace_child(parent, node, raised),
+            None => self.root = Some(raised),
+        }
+
+        children[side] = shorter;
+        self.nodes[node].kind = NodeKind::Branch(children);
+        self.nodes[node].parent = Some(raised);
+        self.nodes[shorter].parent = Some(node);
+        self.fit(node);
+
+        self.nodes[raised].kind = NodeKind::Branch([node, taller]);
+        self.fit(raised);
+        raised
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::{Matrix4, Point3, Vector3};
+    use amethyst::ecs::{Builder, World, WorldExt};
+    use std::collections::HashSet;
+
+    /// Unit cube with its lowest corner at `min`.
+    fn cube(min: [f32; 3]) -> AxisAlignedBoundingBox {
+        let min = Point3::from(min);
+        AxisAlignedBoundingBox { min, max: min + Vector3::repeat(1.0) }
+    }
+
+    /// Frustum of the box from -10 to 10 on every axis.
+    fn frustum() -> Frustum {
+        Frustum::new(Matrix4::new_nonuniform_scaling(&Vector3::repeat(0.1)))
+    }
+
+    fn in_frustum(aabb: &AxisAlignedBoundingBox) -> bool {
+        (0..3).all(|axis| aabb.max[axis] > -10.0 && aabb.min[axis] < 10.0)
+    }
+
+    fn visible(tree: &BoundsTree) -> HashSet<Entity> {
+        let mut visible = HashSet::new();
+        tree.query(&frustum(), |entity| {
+            visible.insert(entity);
+        });
+        visible
+    }
+
+    /// Unit cubes every 4 blocks on a 20 by 20 grid around the origin, 25 of them in the frustum.
+    fn grid(world: &mut World, tree: &mut BoundsTree) -> Vec<(Entity, AxisAlignedBoundingBox)> {
+        let mut boxes = Vec::new();
+        for x in -10..10 {
+            for z in -10..10 {
+                let (entity, aabb) = (world.create_entity().build(), cube([x as f32 * 4.0, 0.0, z as f32 * 4.0]));
+                assert!(tree.update(entity, aabb));
+                boxes.push((entity, aabb));
+            }
+        }
+        boxes
+    }
+
+    fn expected(boxes: &[(Entity, AxisAlignedBoundingBox)]) -> HashSet<Entity> {
+        boxes.iter().filter(|(_, aabb)| in_frustum(aabb)).map(|(entity, _)| *entity).collect()
+    }
+
+    #[test]
+    fn query_finds_the_inserted_boxes_in_the_frustum() {
+        let (mut world, mut tree) = (World::new(), BoundsTree::new());
+        let boxes = grid(&mut world, &mut tree);
+        assert_eq!(tree.len(), boxes.len());
+        assert_eq!(expected(&boxes).len(), 25);
+        assert_eq!(visible(&tree), expected(&boxes));
+    }
+
+    #[test]
+    fn updates_move_the_boxes() {
+        let (mut world, mut tree) = (World::new(), BoundsTree::new());
+        let mut boxes = grid(&mut world, &mut tree);
+
+        // Within the leaf margin, the leaf stays.
+        let (entity, aabb) = boxes[0];
+        assert!(!tree.update(entity, cube([aabb.min.x + 0.25, aabb.min.y, aabb.min.z])));
+        assert!(tree.contains(entity));
+
+        // Into the frustum, and one of the visible boxes out of it.
+        boxes[0].1 = cube([0.5, 0.5, 0.5]);
+        assert!(tree.update(entity, boxes[0].1));
+        let (index, _) = boxes.iter().enumerate().find(|(_, (_, aabb))| in_frustum(aabb) && aabb.min.x < 0.0).unwrap();
+        boxes[index].1 = cube([30.0, 30.0, 30.0]);
+        assert!(tree.update(boxes[index].0, boxes[index].1));
+
+        assert_eq!(tree.len(), boxes.len());
+        assert!(visible(&tree).contains(&entity));
+        assert!(!visible(&tree).contains(&boxes[index].0));
+        assert_eq!(visible(&tree), expected(&boxes));
+    }
+
+    #[test]
+    fn removed_boxes_are_not_found() {
+        let (mut world, mut tree) = (World::new(), BoundsTree::new());
+        let boxes = grid(&mut world, &mut tree);
+        for entity in expected(&boxes) {
+            assert!(tree.remove(entity.id()));
+            assert!(!tree.remove(entity.id()));
+        }
+        assert_eq!(tree.len(), boxes.len() - 25);
+        assert!(visible(&tree).is_empty());
+
+        for (entity, _) in &boxes {
+            tree.remove(entity.id());
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.depth(), 0);
+    }
+
+    #[test]
+    fn rotations_keep_the_tree_balanced() {
+        // Boxes along a line, each one growing the tree on the same side without rotations.
+        let (mut world, mut tree) = (World::new(), BoundsTree::new());
+        for x in 0..1024 {
+            tree.update(world.create_entity().build(), cube([x as f32 * 2.0, 0.0, 0.0]));
+        }
+        assert!(tree.depth() <= 16, "depth {}", tree.depth());
+    }
+}
@@ -0,0 +1,416 @@
+//! Chunk meshing: turns the blocks of a chunk into meshes with the voxel light and biome tint baked into the
+//! vertices.
+use std::collections::BTreeMap;
+
+use crate::render_mesh::{MeshBuilder, MeshData};
+use crate::render_vertex::{Vertex, VoxelLight};
+use crate::world::block::{BlockFace, BlockId, BlockRegistry};
+use crate::world::chunk::{split_block_pos, Chunk, ChunkPos, CHUNK_SIZE};
+use crate::world::light::{LightChannel, MAX_LIGHT};
+use crate::world::voxel_world::VoxelWorld;
+
+/// Levels of detail of chunk meshes. Level 0 is full detail, level `n` merges `2^n` blocks on each axis.
+pub const LOD_LEVELS: u8 = 4;
+
+/// Faces of a chunk sharing a texture.
+#[derive(Debug)]
+pub struct ChunkMeshSection {
+    /// Texture index in the `BlockRegistry`.
+    pub texture: u32,
+    pub mesh: MeshData,
+}
+
+// region - Faces
+
+struct Face {
+    dir: [i32; 3],
+    side: BlockFace,
+    corners: [[f32; 3]; 4],
+    uvs: [[f32; 2]; 4],
+    indices: [u32; 6],
+}
+
+const UVS: [[f32; 2]; 4] = [[1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]];
+
+/// Same corners, uvs and winding as the single block mesh.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const FACES: [Face; 6] = [
+    // front
+    Face { dir: [0, 0, -1], side: BlockFace::Side, uvs: UVS, indices: [0, 1, 2, 2, 1, 3],
+           corners: [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]] },
+    // top
+    Face { dir: [0, 1, 0], side: BlockFace::Top, uvs: UVS, indices: [0, 1, 2, 3, 2, 1],
+           corners: [[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]] },
+    // back
+    Face { dir: [0, 0, 1], side: BlockFace::Side, uvs: [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0]],
+           indices: [2, 1, 0, 1, 2, 3],
+           corners: [[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0]] },
+    // bottom
+    Face { dir: [0, -1, 0], side: BlockFace::Bottom, uvs: UVS, indices: [0, 2, 1, 3, 1, 2],
+           corners: [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0]] },
+    // left
+    Face { dir: [-1, 0, 0], side: BlockFace::Side, uvs: UVS, indices: [0, 1, 2, 3, 2, 1],
+           corners: [[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]] },
+    // right
+    Face { dir: [1, 0, 0], side: BlockFace::Side, uvs: UVS, indices: [0, 1, 2, 3, 2, 1],
+           corners: [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0]] },
+];
+
+// endregion
+
+// region - Neighbourhood
+
+/// Offsets of the face neighbours of a chunk, in the order `ChunkNeighbourhood` keeps them.
+pub const NEIGHBOURS: [(i32, i32, i32); 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+/// Copy of a chunk and its face neighbours, all the mesher reads. Lets a chunk be meshed off the main thread
+/// while the world keeps changing.
+#[derive(Debug, Clone)]
+pub struct ChunkNeighbourhood {
+    pos: ChunkPos,
+    chunk: Chunk,
+    /// In the order of `NEIGHBOURS`, `None` when not loaded.
+    neighbours: [Option<Chunk>; 6],
+    /// Level of detail the neighbours are meshed at, in the order of `NEIGHBOURS`.
+    neighbour_levels: [u8; 6],
+}
+
+impl ChunkNeighbourhood {
+    /// `None` if the chunk itself is not loaded.
+    pub fn capture(world: &VoxelWorld, pos: ChunkPos) -> Option<Self> {
+        let chunk = world.chunk(pos)?.clone();
+        let neighbour = |index: usize| {
+            let (dx, dy, dz) = NEIGHBOURS[index];
+            world.chunk(pos.offset(dx, dy, dz)).cloned()
+        };
+        Some(ChunkNeighbourhood {
+            pos,
+            chunk,
+            neighbours: [neighbour(0), neighbour(1), neighbour(2), neighbour(3), neighbour(4), neighbour(5)],
+            neighbour_levels: [0; 6],
+        })
+    }
+
+    /// Alters the levels of detail of the neighbours, all `0` by default. Faces against a neighbour meshed at
+    /// another level than the chunk are never culled, its surface does not follow the blocks hiding them.
+    pub fn with_neighbour_levels(mut self, levels: [u8; 6]) -> Self {
+        self.neighbour_levels = levels;
+        self
+    }
+
+    pub fn pos(&self) -> ChunkPos {
+        self.pos
+    }
+
+    fn chunk_at(&self, pos: ChunkPos) -> Option<&Chunk> {
+        if pos == self.pos {
+            return Some(&self.chunk);
+        }
+        let offset = (pos.x - self.pos.x, pos.y - self.pos.y, pos.z - self.pos.z);
+        let index = NEIGHBOURS.iter().position(|neighbour| *neighbour == offset)?;
+        self.neighbours[index].as_ref()
+    }
+
+    /// Whether the neighbour in the direction `dir` is meshed at `level`.
+    fn same_level(&self, dir: [i32; 3], level: u8) -> bool {
+        NEIGHBOURS
+            .iter()
+            .position(|neighbour| *neighbour == (dir[0], dir[1], dir[2]))
+            .map_or(true, |index| self.neighbour_levels[index] == level)
+    }
+
+    /// Air outside of the captured chunks.
+    fn block(&self, pos: [i32; 3]) -> BlockId {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        self.chunk_at(chunk_pos).map_or(BlockId::AIR, |chunk| chunk.block(x, y, z))
+    }
+
+    /// `None` outside of the captured chunks.
+    fn light(&self, pos: [i32; 3], channel: LightChannel) -> Option<u8> {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        self.chunk_at(chunk_pos).map(|chunk| chunk.light(x, y, z, channel))
+    }
+}
+
+// endregion
+
+// region - Mesher
+
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Distance overlays are drawn in front of their face, avoids depth fighting.
+const OVERLAY_LIFT: f32 = 0.002;
+
+/// A block face about to be added to a section.
+#[derive(Clone, Copy)]
+struct Quad<'a> {
+    face: &'a Face,
+    position: [f32; 3],
+    light: [f32; 2],
+    color: [f32; 4],
+    /// Offset along the normal.
+    lift: f32,
+    /// Edge length, in blocks. Uvs repeat the texture once per block.
+    scale: f32,
+}
+
+impl<'a> Quad<'a> {
+    fn push(&self, (vertices, indices): &mut (Vec<Vertex>, Vec<u32>)) {
+        let norm = [self.face.dir[0] as f32, self.face.dir[1] as f32, self.face.dir[2] as f32];
+        let base = vertices.len() as u32;
+        for (corner, uv) in self.face.corners.iter().zip(self.face.uvs.iter()) {
+            vertices.push(Vertex {
+                xyz: [
+                    self.position[0] + corner[0] * self.scale + norm[0] * self.lift,
+                    self.position[1] + corner[1] * self.scale + norm[1] * self.lift,
+                    self.position[2] + corner[2] * self.scale + norm[2] * self.lift,
+                ],
+                norm,
+                uv: [uv[0] * self.scale, uv[1] * self.scale],
+                light: self.light,
+                color: self.color,
+            });
+        }
+        indices.extend(self.face.indices.iter().map(|index| base + index));
+    }
+}
+
+/// Builds one mesh per texture used by the chunk at a level of detail below `LOD_LEVELS`, with vertices relative
+/// to the chunk origin.
+///
+/// Only faces next to a non-opaque block, or on the border with a neighbour at another level, are emitted. Their
+/// light is the light of the block they face, so a face is as bright as the space it is seen from. Coarser levels
+/// are built by `build_coarse_mesh`.
+pub fn build_chunk_mesh(
+    neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, level: u8,
+) -> Vec<ChunkMeshSection> {
+    if level > 0 {
+        return build_coarse_mesh(neighbourhood, registry, level.min(LOD_LEVELS - 1));
+    }
+    let chunk = &neighbourhood.chunk;
+    let origin = neighbourhood.pos.origin();
+    let size = CHUNK_SIZE as usize;
+    let mut sections: BTreeMap<u32, (Vec<Vertex>, Vec<u32>)> = BTreeMap::new();
+
+    for y in 0..size {
+        for z in 0..size {
+            for x in 0..size {
+                let block = chunk.block(x, y, z);
+                if block.is_air() {
+                    continue;
+                }
+                for face in FACES.iter() {
+                    guard!(let Some(texture) = registry.face_texture(block, face.side) else { continue });
+                    let (nx, ny, nz) = (x as i32 + face.dir[0], y as i32 + face.dir[1], z as i32 + face.dir[2]);
+                    let inside = [nx, ny, nz].iter().all(|v| *v >= 0 && *v < CHUNK_SIZE);
+                    let (hidden, sky, block_light) = if inside {
+                        let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+                        (
+                            registry.is_opaque(chunk.block(nx, ny, nz)),
+                            chunk.light(nx, ny, nz, LightChannel::Sky),
+                            chunk.light(nx, ny, nz, LightChannel::Block),
+                        )
+                    } else {
+                        let neighbour = [origin[0] + nx, origin[1] + ny, origin[2] + nz];
+                        (
+                            registry.is_opaque(neighbourhood.block(neighbour)) && neighbourhood.same_level(face.dir, 0),
+                            neighbourhood.light(neighbour, LightChannel::Sky).unwrap_or(MAX_LIGHT),
+                            neighbourhood.light(neighbour, LightChannel::Block).unwrap_or(0),
+                        )
+                    };
+                    if hidden {
+                        continue;
+                    }
+
+                    let light = [sky as f32 / MAX_LIGHT as f32, block_light as f32 / MAX_LIGHT as f32];
+                    let tint = if registry.get(block).tinted {
+                        let [r, g, b] = chunk.tint(x, z);
+                        [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+                    } else {
+                        WHITE
+                    };
+                    let color = if face.side == BlockFace::Top { tint } else { WHITE };
+                    let position = [x as f32, y as f32, z as f32];
+                    let quad = Quad { face, position, light, color, lift: 0.0, scale: 1.0 };
+                    quad.push(sections.entry(texture).or_default());
+
+                    if face.side == BlockFace::Side {
+                        if let Some(overlay) = registry.overlay_texture(block) {
+                            let quad = Quad { color: tint, lift: OVERLAY_LIFT, ..quad };
+                            quad.push(sections.entry(overlay).or_default());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    into_sections(sections)
+}
+
+fn into_sections(sections: BTreeMap<u32, (Vec<Vertex>, Vec<u32>)>) -> Vec<ChunkMeshSection> {
+    sections
+        .into_iter()
+        .map(|(texture, (vertices, indices))| ChunkMeshSection {
+            texture,
+            mesh: MeshBuilder::new().with_vertices(vertices).with_indices(indices).with_dynamic().into(),
+        })
+        .collect()
+}
+
+/// Downsamples a chunk into cells of `scale` blocks on each axis. A cell is filled when at least half of its
+/// blocks are, with its most common block.
+fn coarse_cells(chunk: &Chunk, scale: usize) -> Vec<Option<BlockId>> {
+    let cells = CHUNK_SIZE as usize / scale;
+    let mut grid = Vec::with_capacity(cells * cells * cells);
+    let mut counts: Vec<(BlockId, usize)> = Vec::new();
+    for cy in 0..cells {
+        for cz in 0..cells {
+            for cx in 0..cells {
+                counts.clear();
+                for y in cy * scale..(cy + 1) * scale {
+                    for z in cz * scale..(cz + 1) * scale {
+                        for x in cx * scale..(cx + 1) * scale {
+                            let block = chunk.block(x, y, z);
+                            if block.is_air() {
+                                continue;
+                            }
+                            match counts.iter_mut().find(|(known, _)| *known == block) {
+                                Some((_, count)) => *count += 1,
+                                None => counts.push((block, 1)),
+                            }
+                        }
+                    }
+                }
+                let filled: usize = counts.iter().map(|(_, count)| count).sum();
+                grid.push(if filled * 2 >= scale * scale * scale {
+                    counts.iter().max_by_key(|(_, count)| *count).map(|(block, _)| *block)
+                } else {
+                    None
+                });
+            }
+        }
+    }
+    grid
+}
+
+/// Builds the meshes of a chunk from cells of `2^level` blocks, see `coarse_cells`.
+///
+/// Faces on the chunk border are culled against the cells of a neighbour meshed at the same level. Against a
+/// neighbour at another level they are always emitted, as it keeps its own against this chunk, see
+/// `ChunkNeighbourhood::with_neighbour_levels`. Cells and blocks do not line up there, each side covers what the
+/// other misses and no cracks open between them.
+fn build_coarse_mesh(neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, level: u8) -> Vec<ChunkMeshSection> {
+    let chunk = &neighbourhood.chunk;
+    let origin = neighbourhood.pos.origin();
+    let scale = 1usize << level;
+    let cells = CHUNK_SIZE as usize / scale;
+    let grid = coarse_cells(chunk, scale);
+    let neighbour_grids: Vec<Option<Vec<Option<BlockId>>>> = (0..NEIGHBOURS.len())
+        .map(|index| match &neighbourhood.neighbours[index] {
+            Some(neighbour) if neighbourhood.neighbour_levels[index] == level => Some(coarse_cells(neighbour, scale)),
+            _ => None,
+        })
+        .collect();
+    // Cells past the border are read from the neighbour grids, `None` when the neighbour is not at this level.
+    let cell = |x: i32, y: i32, z: i32| {
+        let size = cells as i32;
+        let offset = (x.div_euclid(size), y.div_euclid(size), z.div_euclid(size));
+        let grid = if offset == (0, 0, 0) {
+            &grid
+        } else {
+            let index = NEIGHBOURS.iter().position(|neighbour| *neighbour == offset)?;
+            neighbour_grids[index].as_ref()?
+        };
+        let (x, y, z) = (x.rem_euclid(size) as usize, y.rem_euclid(size) as usize, z.rem_euclid(size) as usize);
+        grid[(y * cells + z) * cells + x]
+    };
+    let mut sections: BTreeMap<u32, (Vec<Vertex>, Vec<u32>)> = BTreeMap::new();
+
+    for cy in 0..cells as i32 {
+        for cz in 0..cells as i32 {
+            for cx in 0..cells as i32 {
+                guard!(let Some(block) = cell(cx, cy, cz) else { continue });
+                for face in FACES.iter() {
+                    guard!(let Some(texture) = registry.face_texture(block, face.side) else { continue });
+                    if cell(cx + face.dir[0], cy + face.dir[1], cz + face.dir[2])
+                        .map_or(false, |neighbour| registry.is_opaque(neighbour))
+                    {
+                        continue;
+                    }
+
+                    // Light of the block right outside the middle of the face.
+                    let sample: Vec<i32> = (0..3)
+                        .map(|axis| {
+                            let start = [cx, cy, cz][axis] * scale as i32;
+                            match face.dir[axis] {
+                                1 => start + scale as i32,
+                                -1 => start - 1,
+                                _ => start + scale as i32 / 2,
+                            }
+                        })
+                        .collect();
+                    let inside = sample.iter().all(|v| *v >= 0 && *v < CHUNK_SIZE);
+                    let (sky, block_light) = if inside {
+                        let (x, y, z) = (sample[0] as usize, sample[1] as usize, sample[2] as usize);
+                        (chunk.light(x, y, z, LightChannel::Sky), chunk.light(x, y, z, LightChannel::Block))
+                    } else {
+                        let pos = [origin[0] + sample[0], origin[1] + sample[1], origin[2] + sample[2]];
+                        (
+                            neighbourhood.light(pos, LightChannel::Sky).unwrap_or(MAX_LIGHT),
+                            neighbourhood.light(pos, LightChannel::Block).unwrap_or(0),
+                        )
+                    };
+
+                    let light = [sky as f32 / MAX_LIGHT as f32, block_light as f32 / MAX_LIGHT as f32];
+                    let tint = if registry.get(block).tinted {
+                        let [r, g, b] = chunk.tint(cx as usize * scale, cz as usize * scale);
+                        [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+                    } else {
+                        WHITE
+                    };
+                    let color = if face.side == BlockFace::Top { tint } else { WHITE };
+                    let position = [(cx * scale as i32) as f32, (cy * scale as i32) as f32, (cz * scale as i32) as f32];
+                    let quad = Quad { face, position, light, color, lift: 0.0, scale: scale as f32 };
+                    quad.push(sections.entry(texture).or_default());
+
+                    if face.side == BlockFace::Side {
+                        if let Some(overlay) = registry.overlay_texture(block) {
+                            let quad = Quad { color: tint, lift: OVERLAY_LIFT, ..quad };
+                            quad.push(sections.entry(overlay).or_default());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    into_sections(sections)
+}
+
+/// Builds a single mesh of coloured cubes, for voxel models drawn as standalone entities rather than as blocks.
+///
+/// `color` gives the colour of the cell at a position, `None` for empty cells. Cells span `0..size` on each axis
+/// and faces between two filled cells are skipped. Vertices are fully sky lit and shifted by `offset`.
+pub fn build_color_mesh<F>(size: [i32; 3], offset: [f32; 3], color: F) -> MeshData
+where F: Fn([i32; 3]) -> Option<[f32; 4]> {
+    let mut geometry: (Vec<Vertex>, Vec<u32>) = (Vec::new(), Vec::new());
+    for y in 0..size[1] {
+        for z in 0..size[2] {
+            for x in 0..size[0] {
+                guard!(let Some(cell) = color([x, y, z]) else { continue });
+                for face in FACES.iter() {
+                    if color([x + face.dir[0], y + face.dir[1], z + face.dir[2]]).is_some() {
+                        continue;
+                    }
+                    let position = [x as f32 + offset[0], y as f32 + offset[1], z as f32 + offset[2]];
+                    let quad = Quad { face, position, light: VoxelLight::UNLIT, color: cell, lift: 0.0, scale: 1.0 };
+                    quad.push(&mut geometry);
+                }
+            }
+        }
+    }
+    let (vertices, indices) = geometry;
+    MeshBuilder::new().with_vertices(vertices).with_indices(indices).into()
+}
+
+// endregion
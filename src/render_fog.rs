@@ -0,0 +1,50 @@
+//! Distance fog applied by the custom 3D pass.
+use amethyst::renderer::palette::Srgb;
+use glsl_layout::*;
+
+/// Fog resource. Read by the render pass every frame, so it can be animated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fog {
+    pub color: Srgb,
+    /// Exponential squared fog density, `0.0` disables fog.
+    pub density: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            color: Srgb::new(0.7, 0.8, 0.95),
+            density: 0.0,
+        }
+    }
+}
+
+// region - Shader Fog
+
+/// Fog Uniform
+/// ```glsl,ignore
+/// uniform Fog {
+///    vec4 fog_color;
+///    float fog_density;
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(16))]
+pub struct ShaderFog {
+    /// Fog color, alpha is unused
+    pub color: vec4,
+    /// Fog density
+    pub density: float,
+}
+
+impl ShaderFog {
+    /// Helper function from `Fog` resource to POD type.
+    pub fn from_fog(fog: &Fog) -> Self {
+        ShaderFog {
+            color: [fog.color.red, fog.color.green, fog.color.blue, 1.0].into(),
+            density: fog.density,
+        }
+    }
+}
+
+// endregion
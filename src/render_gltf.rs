@@ -0,0 +1,480 @@
+//! glTF 2.0 import: primitives become `CompositeMesh` elements, materials `Material`s and nodes entities.
+//!
+//! Only the base colour of materials is kept. Its texture is the diffuse texture, its factor is multiplied into the
+//! vertex colours. Files referencing external buffers or images must be packed as `.glb` or embed them as data URIs.
+use amethyst::assets::{Asset, AssetStorage, Format, Handle, Loader};
+use amethyst::core::{
+    math::{Quaternion, Unit, Vector3},
+    Parent, Transform,
+};
+use amethyst::ecs::{Entity, VecStorage};
+use amethyst::prelude::{Builder, World, WorldExt};
+use amethyst::renderer::rendy::hal::image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode};
+use amethyst::renderer::rendy::texture::{palette::load_from_srgba, pixel::Rgba8Srgb, TextureBuilder};
+use amethyst::renderer::{palette::Srgba, types::Texture};
+use amethyst::Error;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use crate::render_cache::{MaterialCache, TextureCache};
+use crate::render_material::{CompositeMaterial, Material, MaterialDefaults};
+use crate::render_mesh::{CompositeMesh, Mesh, MeshBuilder, MeshData};
+use crate::render_vertex::{Vertex, VoxelLight};
+
+/// Texture and material cache ids of glTF scenes start here, above the chunk textures.
+pub const GLTF_CACHE_ID_OFFSET: u32 = 1 << 24;
+/// Cache ids reserved for each scene. The last one is the material of primitives without one.
+pub const GLTF_CACHE_IDS_PER_SCENE: u32 = 1 << 10;
+/// Highest scene id whose cache ids still fit in a `u32`.
+pub const GLTF_MAX_SCENE_ID: u32 = (std::u32::MAX - GLTF_CACHE_ID_OFFSET) / GLTF_CACHE_IDS_PER_SCENE;
+/// Materials a scene may have, one cache id is kept for primitives without material.
+pub const GLTF_MAX_MATERIALS: usize = GLTF_CACHE_IDS_PER_SCENE as usize - 1;
+/// Images a scene may have.
+pub const GLTF_MAX_IMAGES: usize = GLTF_CACHE_IDS_PER_SCENE as usize;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// region - Scene
+
+/// Triangles of a glTF primitive, drawn with one material.
+#[derive(Debug, Clone)]
+pub struct GltfPrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into `GltfScene::materials`.
+    pub material: Option<usize>,
+}
+
+impl GltfPrimitive {
+    pub fn mesh(&self) -> MeshData {
+        MeshBuilder::new().with_vertices(self.vertices.clone()).with_indices(self.indices.clone()).into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    /// Index into `GltfScene::images` of the base colour texture.
+    pub image: Option<usize>,
+}
+
+/// Decoded image, 8 bit sRGB RGBA.
+#[derive(Debug, Clone)]
+pub struct GltfImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    /// Relative to the parent node.
+    pub transform: Transform,
+    /// Index into `GltfScene::meshes`.
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// Content of a glTF or GLB file. Asset loaded with `GltfFormat`.
+#[derive(Debug, Clone)]
+pub struct GltfScene {
+    /// Primitives of each glTF mesh.
+    pub meshes: Vec<Vec<GltfPrimitive>>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+    pub nodes: Vec<GltfNode>,
+    /// Nodes of the default scene, or of the first one.
+    pub roots: Vec<usize>,
+}
+
+impl Asset for GltfScene {
+    const NAME: &'static str = "custom:GltfScene";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+// endregion
+
+// region - Parser
+
+/// Converts decoded image pixels to 8 bit RGBA, keeping the high byte of 16 bit channels.
+fn rgba8(image: &gltf::image::Data) -> GltfImage {
+    use gltf::image::Format::*;
+    let (channels, bytes, bgr) = match image.format {
+        R8 => (1, 1, false),
+        R8G8 => (2, 1, false),
+        R8G8B8 => (3, 1, false),
+        R8G8B8A8 => (4, 1, false),
+        B8G8R8 => (3, 1, true),
+        B8G8R8A8 => (4, 1, true),
+        R16 => (1, 2, false),
+        R16G16 => (2, 2, false),
+        R16G16B16 => (3, 2, false),
+        R16G16B16A16 => (4, 2, false),
+    };
+    let mut pixels = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+    for pixel in image.pixels.chunks_exact(channels * bytes) {
+        // Little endian, the high byte of a 16 bit channel is its last one.
+        let channel = |index: usize| pixel[index * bytes + bytes - 1];
+        let rgba = match channels {
+            1 => [channel(0), channel(0), channel(0), 255],
+            2 => [channel(0), channel(0), channel(0), channel(1)],
+            3 => [channel(0), channel(1), channel(2), 255],
+            _ => [channel(0), channel(1), channel(2), channel(3)],
+        };
+        if bgr {
+            pixels.extend_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+        } else {
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+    GltfImage {
+        width: image.width,
+        height: image.height,
+        pixels,
+    }
+}
+
+/// Face normal of each triangle corner, glTF asks for flat normals when a primitive has none.
+fn flat_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    indices
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            let corner = |index: u32| Vector3::from(positions[index as usize]);
+            let (a, b, c) = (corner(triangle[0]), corner(triangle[1]), corner(triangle[2]));
+            let normal = (b - a).cross(&(c - a)).try_normalize(std::f32::EPSILON).unwrap_or_else(Vector3::y);
+            vec![[normal.x, normal.y, normal.z]; 3]
+        })
+        .collect()
+}
+
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> io::Result<Option<GltfPrimitive>> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        return Ok(None);
+    }
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+    guard!(let Some(positions) = reader.read_positions() else { return Err(invalid("primitive without positions")) });
+    let positions: Vec<[f32; 3]> = positions.collect();
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if indices.iter().any(|index| *index as usize >= positions.len()) {
+        return Err(invalid("primitive index out of range"));
+    }
+    // Without normals every triangle gets its own corners, so they can have the face normal.
+    let (normals, corners, indices): (Vec<[f32; 3]>, Vec<usize>, Vec<u32>) = match reader.read_normals() {
+        Some(normals) => (normals.collect(), (0..positions.len()).collect(), indices),
+        None => {
+            let normals = flat_normals(&positions, &indices);
+            let corners = indices.iter().take(normals.len()).map(|index| *index as usize).collect();
+            let count = normals.len() as u32;
+            (normals, corners, (0..count).collect())
+        }
+    };
+
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    let texture_set = pbr.base_color_texture().map_or(0, |info| info.tex_coord());
+    let uvs: Vec<[f32; 2]> = reader.read_tex_coords(texture_set).map_or_else(Vec::new, |uvs| uvs.into_f32().collect());
+    let colors: Vec<[f32; 4]> = reader.read_colors(0).map_or_else(Vec::new, |colors| colors.into_rgba_f32().collect());
+    let factor = pbr.base_color_factor();
+
+    let vertices = corners
+        .iter()
+        .enumerate()
+        .map(|(vertex, &index)| {
+            let color = colors.get(index).cloned().unwrap_or([1.0; 4]);
+            Vertex {
+                xyz: positions[index],
+                norm: normals.get(vertex).cloned().unwrap_or([0.0, 1.0, 0.0]),
+                uv: uvs.get(index).cloned().unwrap_or([0.0, 0.0]),
+                light: VoxelLight::UNLIT,
+                color: [color[0] * factor[0], color[1] * factor[1], color[2] * factor[2], color[3] * factor[3]],
+            }
+        })
+        .collect();
+    Ok(Some(GltfPrimitive {
+        vertices,
+        indices,
+        material: material.index(),
+    }))
+}
+
+fn node_transform(node: &gltf::Node) -> Transform {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    let mut transform = Transform::default();
+    transform.set_translation(Vector3::from(translation));
+    transform.set_rotation(Unit::new_normalize(Quaternion::new(w, x, y, z)));
+    transform.set_scale(Vector3::from(scale));
+    transform
+}
+
+/// Parses a glTF, embedded glTF or GLB file. Primitives other than triangles are skipped.
+pub fn parse_gltf(bytes: &[u8]) -> io::Result<GltfScene> {
+    let (document, buffers, images) = gltf::import_slice(bytes).map_err(|error| invalid(&error.to_string()))?;
+
+    let meshes = document
+        .meshes()
+        .map(|mesh| {
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                if let Some(primitive) = read_primitive(&primitive, &buffers)? {
+                    primitives.push(primitive);
+                }
+            }
+            Ok(primitives)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let materials = document
+        .materials()
+        .map(|material| GltfMaterial {
+            name: material.name().map(String::from),
+            image: material.pbr_metallic_roughness().base_color_texture().map(|info| info.texture().source().index()),
+        })
+        .collect::<Vec<_>>();
+    if materials.len() > GLTF_MAX_MATERIALS || images.len() > GLTF_MAX_IMAGES {
+        return Err(invalid(&format!(
+            "{} materials and {} images, at most {} and {} are supported",
+            materials.len(),
+            images.len(),
+            GLTF_MAX_MATERIALS,
+            GLTF_MAX_IMAGES
+        )));
+    }
+    let nodes = document
+        .nodes()
+        .map(|node| GltfNode {
+            name: node.name().map(String::from),
+            transform: node_transform(&node),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            children: node.children().map(|child| child.index()).collect(),
+        })
+        .collect();
+    let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => (0..document.nodes().len()).collect(),
+    };
+
+    Ok(GltfScene {
+        meshes,
+        materials,
+        images: images.iter().map(rgba8).collect(),
+        nodes,
+        roots,
+    })
+}
+
+// endregion
+
+// region - Format
+
+/// Format of glTF 2.0 files, `.gltf` with embedded data or binary `.glb`.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct GltfFormat;
+
+impl Format<GltfScene> for GltfFormat {
+    fn name(&self) -> &'static str {
+        "GLTF"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<GltfScene, Error> {
+        Ok(parse_gltf(&bytes)?)
+    }
+}
+
+// endregion
+
+// region - Spawn
+
+/// Mesh and material handles of a loaded scene, spawned as many times as needed with `spawn_gltf_scene`.
+#[derive(Debug, Clone)]
+pub struct GltfHandles {
+    /// `Mesh` of each primitive of each glTF mesh.
+    pub meshes: Vec<Vec<Handle<Mesh>>>,
+    /// Material of each primitive of each glTF mesh.
+    pub materials: Vec<Vec<Handle<Material>>>,
+}
+
+fn image_texture(image: &GltfImage) -> TextureBuilder<'static> {
+    let pixels: Vec<Rgba8Srgb> = image
+        .pixels
+        .chunks_exact(4)
+        .map(|pixel| Rgba8Srgb {
+            repr: [pixel[0], pixel[1], pixel[2], pixel[3]],
+        })
+        .collect();
+    TextureBuilder::new()
+        .with_kind(Kind::D2(image.width, image.height, 1, 1))
+        .with_view_kind(ViewKind::D2)
+        .with_data_width(image.width)
+        .with_data_height(image.height)
+        .with_sampler_info(SamplerInfo::new(Filter::Linear, WrapMode::Tile))
+        .with_data(pixels)
+}
+
+impl GltfScene {
+    /// Loads the meshes, textures and materials of the scene.
+    ///
+    /// Textures and materials are cached under the ids of `scene_id`, the same scene always gets the same handles.
+    /// Panics when `scene_id` is above `GLTF_MAX_SCENE_ID` or the scene has too many materials or images.
+    pub fn load(&self, world: &mut World, scene_id: u32) -> GltfHandles {
+        assert!(scene_id <= GLTF_MAX_SCENE_ID, "glTF scene id {} above {}", scene_id, GLTF_MAX_SCENE_ID);
+        assert!(self.materials.len() <= GLTF_MAX_MATERIALS, "glTF scene with {} materials", self.materials.len());
+        assert!(self.images.len() <= GLTF_MAX_IMAGES, "glTF scene with {} images", self.images.len());
+        let base = GLTF_CACHE_ID_OFFSET + scene_id * GLTF_CACHE_IDS_PER_SCENE;
+        let materials: Vec<Handle<Material>> = (0..self.materials.len())
+            .map(|index| self.material(world, base, Some(index)))
+            .collect();
+        let untextured = self.material(world, base, None);
+
+        let loader = world.read_resource::<Loader>();
+        let storage = world.read_resource::<AssetStorage<Mesh>>();
+        GltfHandles {
+            meshes: self
+                .meshes
+                .iter()
+                .map(|primitives| {
+                    primitives.iter().map(|primitive| loader.load_from_data(primitive.mesh(), (), &storage)).collect()
+                })
+                .collect(),
+            materials: self
+                .meshes
+                .iter()
+                .map(|primitives| {
+                    primitives
+                        .iter()
+                        .map(|primitive| match primitive.material {
+                            Some(index) => materials.get(index).cloned().unwrap_or_else(|| untextured.clone()),
+                            None => untextured.clone(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    fn material(&self, world: &mut World, base: u32, index: Option<usize>) -> Handle<Material> {
+        let image = index.and_then(|index| self.materials[index].image);
+        let id = base + index.map_or(GLTF_CACHE_IDS_PER_SCENE - 1, |index| index as u32);
+        MaterialCache::item(id, world, |world| {
+            let image = image.and_then(|image| self.images.get(image).map(|data| (image, data)));
+            let diffuse: Handle<Texture> = match image {
+                Some((image, data)) => TextureCache::item(base + image as u32, world, |world| {
+                    world.read_resource::<Loader>().load_from_data(
+                        image_texture(data).into(),
+                        (),
+                        &world.read_resource::<AssetStorage<Texture>>(),
+                    )
+                }),
+                None => world.read_resource::<Loader>().load_from_data(
+                    load_from_srgba(Srgba::new(1.0, 1.0, 1.0, 1.0)).into(),
+                    (),
+                    &world.read_resource::<AssetStorage<Texture>>(),
+                ),
+            };
+            let defaults = world.read_resource::<MaterialDefaults>().0.clone();
+            world.write_resource::<AssetStorage<Material>>().insert(Material { diffuse, ..defaults })
+        })
+    }
+}
+
+/// Creates an entity per node of the scene under a root entity placed at `transform`, and returns the root.
+///
+/// Nodes keep their glTF transform relative to their parent, those with a mesh get a `CompositeMesh` with one
+/// element per primitive.
+pub fn spawn_gltf_scene(world: &mut World, scene: &GltfScene, handles: &GltfHandles, transform: Transform) -> Entity {
+    let root = world.create_entity().with(transform).build();
+    let mut stack: Vec<(usize, Entity)> = scene.roots.iter().map(|node| (*node, root)).collect();
+    let mut visited = vec![false; scene.nodes.len()];
+    while let Some((index, parent)) = stack.pop() {
+        guard!(let Some(node) = scene.nodes.get(index) else { continue });
+        // Malformed files may list a node twice, each node is spawned once.
+        if std::mem::replace(&mut visited[index], true) {
+            continue;
+        }
+        let mut builder = world.create_entity().with(node.transform.clone()).with(Parent { entity: parent });
+        if let Some(mesh) = node.mesh {
+            if let (Some(elements), Some(components)) = (handles.meshes.get(mesh), handles.materials.get(mesh)) {
+                builder = builder
+                    .with(CompositeMesh { elements: elements.clone() })
+                    .with(CompositeMaterial { components: components.clone() });
+            }
+        }
+        let entity = builder.build();
+        stack.extend(node.children.iter().map(|child| (*child, entity)));
+    }
+    root
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(format: gltf::image::Format, pixels: Vec<u8>) -> gltf::image::Data {
+        gltf::image::Data { pixels, format, width: 2, height: 1 }
+    }
+
+    #[test]
+    fn bgr_pixels_are_swapped_to_rgba() {
+        let converted = rgba8(&image(gltf::image::Format::B8G8R8, vec![10, 20, 30, 40, 50, 60]));
+        assert_eq!(converted.pixels, vec![30, 20, 10, 255, 60, 50, 40, 255]);
+        let converted = rgba8(&image(gltf::image::Format::B8G8R8A8, vec![10, 20, 30, 40, 50, 60, 70, 80]));
+        assert_eq!(converted.pixels, vec![30, 20, 10, 40, 70, 60, 50, 80]);
+        assert_eq!((converted.width, converted.height), (2, 1));
+    }
+
+    #[test]
+    fn sixteen_bit_channels_keep_their_high_byte() {
+        let converted = rgba8(&image(gltf::image::Format::R16, vec![0xFF, 0x12, 0x00, 0xAB]));
+        assert_eq!(converted.pixels, vec![0x12, 0x12, 0x12, 255, 0xAB, 0xAB, 0xAB, 255]);
+        let pixels = vec![1, 0x10, 2, 0x20, 3, 0x30, 4, 0x40, 5, 0x50, 6, 0x60, 7, 0x70, 8, 0x80];
+        let converted = rgba8(&image(gltf::image::Format::R16G16B16A16, pixels));
+        assert_eq!(converted.pixels, vec![0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80]);
+    }
+
+    /// A root node moved to (1, 2, 3) holding a child with a one triangle mesh, and a node outside the scene.
+    const EMBEDDED: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "name": "root", "translation": [1, 2, 3], "children": [1] },
+            { "name": "child", "mesh": 0 },
+            { "name": "loose" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+        "accessors": [{
+            "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+            "min": [0, 0, 0], "max": [1, 1, 0]
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+        }]
+    }"#;
+
+    #[test]
+    fn embedded_gltf_keeps_the_node_hierarchy() {
+        let scene = parse_gltf(EMBEDDED.as_bytes()).unwrap();
+        assert_eq!(scene.roots, vec![0]);
+        let names: Vec<Option<&str>> = scene.nodes.iter().map(|node| node.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("root"), Some("child"), Some("loose")]);
+        assert_eq!(scene.nodes[0].children, vec![1]);
+        assert_eq!((scene.nodes[0].mesh, scene.nodes[1].mesh), (None, Some(0)));
+        assert_eq!(*scene.nodes[0].transform.translation(), Vector3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(scene.meshes.len(), 1);
+        let primitive = &scene.meshes[0][0];
+        assert_eq!(primitive.indices, vec![0, 1, 2]);
+        assert_eq!(primitive.material, None);
+        assert!(primitive.vertices.iter().all(|vertex| vertex.norm == [0.0, 0.0, 1.0]));
+        assert!(scene.materials.is_empty() && scene.images.is_empty());
+    }
+}
@@ -21,10 +21,7 @@ use amethyst::{
     window::{ScreenDimensions, Window},
 };
 
-use amethyst::renderer::{
-    pass::{DrawDebugLinesDesc, DrawSkyboxDesc},
-    palette::Srgb,
-};  
+use amethyst::renderer::pass::{DrawDebugLinesDesc, DrawSkyboxDesc};
 
 use crate::render_backend::DefaultExtendedBackend;
 use crate::render_pass::Draw3DDesc;
@@ -84,10 +81,8 @@ impl GraphCreator<DefaultExtendedBackend> for RenderGraph {
         // Ubuntu.
         let main_pass = graph_builder.add_node(
             SubpassBuilder::new()
-                .with_group(DrawSkyboxDesc::with_colors(
-                    Srgb::new(0.82, 0.51, 0.50), 
-                    Srgb::new(0.18, 0.11, 0.85)
-                ).builder())
+                // Colors come from the `SkyboxSettings` resource animated by the day night cycle.
+                .with_group(DrawSkyboxDesc::new().builder())
                 .with_group(DrawDebugLinesDesc::new().builder())
                 .with_group(Draw3DDesc::new().builder())
                 .with_group(DrawUiDesc::new().builder())
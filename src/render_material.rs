@@ -48,6 +48,8 @@ impl Component for CompositeMaterial {
 pub struct Material {
     pub diffuse: Handle<AmethystTexture>,
     pub uv_offset: TextureOffset,
+    /// Equal layers the diffuse texture is split into from top to bottom, picked per instance by `PropInstance`.
+    pub layers: u32,
 }
 
 amethyst::assets::register_format_type!(Material);
@@ -71,6 +73,7 @@ pub struct MaterialDefaults(pub Material);
 /// ```glsl,ignore
 /// uniform Material {
 ///    UvOffset uv_offset;
+///    uint layers;
 ///    float alpha_cutoff;
 /// };
 /// ```
@@ -79,6 +82,8 @@ pub struct MaterialDefaults(pub Material);
 pub struct ShaderMaterial {
     /// UV offset of material
     pub uv_offset: amethyst::renderer::pod::TextureOffset,
+    /// Layers of the diffuse texture
+    pub layers: uint,
     // /// Material alpha cutoff
     // pub alpha_cutoff: float,
 }
@@ -88,6 +93,7 @@ impl ShaderMaterial {
     pub fn from_material(mat: &Material) -> Self {
         ShaderMaterial {
             uv_offset: amethyst::renderer::pod::TextureOffset::from_offset(&mat.uv_offset),
+            layers: mat.layers.max(1),
             // alpha_cutoff: mat.alpha_cutoff,
         }
     }
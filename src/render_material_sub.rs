@@ -171,7 +171,7 @@ impl<B: Backend, T: for<'a> ITextureSet<'a>> MaterialSub<B, T> {
 
     /// Increment the internal generation counter.
     pub fn maintain(&mut self) {
-        self.generation += self.generation.wrapping_add(1);
+        self.generation = self.generation.wrapping_add(1);
     }
 
     /// Releases any materials not used in the current generation.
@@ -1,6 +1,7 @@
 //! Module for mesh support.
 use amethyst::assets::{Asset, Handle};
-use amethyst::core::ecs::{Component, DenseVecStorage};
+use amethyst::core::ecs::{Component, DenseVecStorage, FlaggedStorage};
+use amethyst::core::math::Vector3;
 use serde::{Deserialize, Serialize};
 
 use amethyst::renderer::rendy::{
@@ -10,10 +11,13 @@ use amethyst::renderer::rendy::{
     memory::{Data, Upload, Write},
     mesh::{AsVertex, VertexFormat},
     resource::{Buffer, BufferInfo, Escape},
-    util::cast_cow,
+    util::{cast_cow, types::vertex::Position},
 };
 use gfx_hal::adapter::PhysicalDevice;
-use std::{borrow::Cow, mem::size_of};
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom, mem::size_of};
+
+use crate::render_vertex::Vertex;
+use crate::render_visibility::MeshBounds;
 
 // endregion
 
@@ -29,15 +33,25 @@ pub enum Mesh {
     Metal(GenericMesh<rendy::metal::Backend>),
 }
 
+impl Mesh {
+    /// Bounds of the vertices, `None` for meshes without positions.
+    pub fn bounds(&self) -> Option<&MeshBounds> {
+        match self {
+            Mesh::Metal(mesh) => mesh.bounds(),
+        }
+    }
+}
+
 // endregion
 
 // region - Components
 
+/// Flagged, so that `MeshBoundsSystem` only recomputes the bounds of the entities whose meshes changed.
 impl Component for CompositeMesh {
     // const NAME: &'static str = "custom:Mesh";
     // type Data = Self;
     // type HandleStorage = DenseVecStorage<Handle<Self>>;
-    type Storage = DenseVecStorage<Self>;
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
 }
 
 // endregion
@@ -81,6 +95,34 @@ impl From<MeshBuilder<'static>> for MeshData {
 //     }
 // }
 
+/// New content for meshes, written in place into their buffers by `MeshUpdateSystem`.
+///
+/// Handles to the meshes, and so `CompositeMesh` elements, stay the same. Meshes still loading are updated once
+/// loaded.
+#[derive(Debug, Default)]
+pub struct MeshUpdates {
+    pending: HashMap<u32, (Handle<Mesh>, MeshData)>,
+}
+
+impl MeshUpdates {
+    /// Queues new content for a mesh, replacing content queued before for it.
+    pub fn update(&mut self, mesh: Handle<Mesh>, data: MeshData) {
+        self.pending.insert(mesh.id(), (mesh, data));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<(Handle<Mesh>, MeshData)> {
+        self.pending.drain().map(|(_, update)| update).collect()
+    }
+}
+
+/// Event sent by `MeshUpdateSystem` with the id of each mesh it rewrote, data derived from their content is stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshChanged(pub u32);
+
 fn deserialize_data<'de, D>(deserializer: D) -> Result<MeshBuilder<'static>, D::Error>
 where
     D: serde::de::Deserializer<'de>,
@@ -106,12 +148,54 @@ pub struct IndexBuffer<B: gfx_hal::Backend> {
     index_type: gfx_hal::IndexType,
 }
 
+/// Part of a mesh drawn on its own, so that one buffer can hold many chunks or material sections.
+///
+/// Indices from `first_index` to `first_index + index_count` are drawn, offset by `base_vertex`. For meshes without
+/// indices the range is one of vertices and `base_vertex` is unused.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubMesh {
+    pub name: String,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+}
+
+/// Indexed indirect draw command, laid out as the GPU reads it.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    first_instance: u32,
+}
+
 /// Abstracts over two types of indices and their absence.
 #[derive(Debug)]
 pub enum Indices<'a> {
+    U16(Cow<'a, [u16]>),
     U32(Cow<'a, [u32]>),
 }
 
+impl From<Vec<u16>> for Indices<'static> {
+    fn from(vec: Vec<u16>) -> Self {
+        Indices::U16(vec.into())
+    }
+}
+
+impl<'a> From<&'a [u16]> for Indices<'a> {
+    fn from(slice: &'a [u16]) -> Self {
+        Indices::U16(slice.into())
+    }
+}
+
+impl<'a> From<Cow<'a, [u16]>> for Indices<'a> {
+    fn from(cow: Cow<'a, [u16]>) -> Self {
+        Indices::U16(cow)
+    }
+}
+
 impl From<Vec<u32>> for Indices<'static> {
     fn from(vec: Vec<u32>) -> Self {
         Indices::U32(vec.into())
@@ -142,6 +226,11 @@ pub struct MeshBuilder<'a> {
     #[serde(borrow)]
     indices: Option<RawIndices<'a>>,
     prim: gfx_hal::Primitive,
+    /// Whether buffers get spare capacity, see `with_dynamic`.
+    #[serde(default)]
+    dynamic: bool,
+    #[serde(default)]
+    sub_meshes: Vec<SubMesh>,
 }
 
 #[derive(Clone, Debug)]
@@ -172,6 +261,11 @@ fn index_stride(index_type: gfx_hal::IndexType) -> usize {
     }
 }
 
+/// Converts `u32` indices to `u16` ones, `None` if one of them does not fit.
+fn narrow_indices(indices: &[u32]) -> Option<Vec<u16>> {
+    indices.iter().map(|index| u16::try_from(*index).ok()).collect()
+}
+
 impl<'a> MeshBuilder<'a> {
     /// Create empty builder.
     pub fn new() -> Self {
@@ -179,6 +273,8 @@ impl<'a> MeshBuilder<'a> {
             vertices: smallvec::SmallVec::new(),
             indices: None,
             prim: gfx_hal::Primitive::TriangleList,
+            dynamic: false,
+            sub_meshes: Vec::new(),
         }
     }
 
@@ -199,6 +295,8 @@ impl<'a> MeshBuilder<'a> {
                 index_type: i.index_type,
             }),
             prim: self.prim,
+            dynamic: self.dynamic,
+            sub_meshes: self.sub_meshes,
         }
     }
 
@@ -212,24 +310,41 @@ impl<'a> MeshBuilder<'a> {
     }
 
     /// Set indices buffer to the `MeshBuilder`
+    ///
+    /// `u32` indices are stored as `u16` when they all fit, halving the index buffer.
     pub fn set_indices<I>(&mut self, indices: I) -> &mut Self
     where
         I: Into<Indices<'a>>,
     {
         self.indices = match indices.into() {
             // Indices::None => None,
-            // Indices::U16(i) => Some(RawIndices {
-            //     indices: cast_cow(i),
-            //     index_type: gfx_hal::IndexType::U16,
-            // }),
-            Indices::U32(i) => Some(RawIndices {
+            Indices::U16(i) => Some(RawIndices {
                 indices: cast_cow(i),
-                index_type: gfx_hal::IndexType::U32,
+                index_type: gfx_hal::IndexType::U16,
+            }),
+            Indices::U32(i) => Some(match narrow_indices(&i) {
+                Some(narrowed) => RawIndices {
+                    indices: cast_cow(Cow::Owned(narrowed)),
+                    index_type: gfx_hal::IndexType::U16,
+                },
+                None => RawIndices {
+                    indices: cast_cow(i),
+                    index_type: gfx_hal::IndexType::U32,
+                },
             }),
         };
         self
     }
 
+    /// Number of vertices, the length of the smallest vertex buffer.
+    pub fn vertex_count(&self) -> u32 {
+        self.vertices
+            .iter()
+            .map(|v| v.vertices.len() as u32 / v.format.stride)
+            .min()
+            .unwrap_or(0)
+    }
+
     /// Add another vertices to the `MeshBuilder`
     pub fn with_vertices<V, D>(mut self, vertices: D) -> Self
     where
@@ -269,27 +384,26 @@ impl<'a> MeshBuilder<'a> {
     //     self
     // }
 
-    /// Builds and returns the new mesh.
-    ///
-    /// A mesh expects all vertex buffers to have the same number of elements.
-    /// If those are not equal, the length of smallest vertex buffer is selected,
-    /// effectively discaring extra data from larger buffers.
-    ///
-    /// Note that contents of index buffer is not validated.
-    pub fn build<B>(&self, queue: QueueId, factory: &Factory<B>) -> Result<GenericMesh<B>, failure::Error>
+    /// Size in bytes of the vertex buffers of `count` vertices each.
+    fn vertex_buffer_size(&self, count: u32) -> u64 {
+        self.vertices.iter().map(|v| (v.format.stride * count) as u64).sum()
+    }
+
+    /// Index buffer content and the type of its indices.
+    fn index_data(&self) -> Option<(&[u8], gfx_hal::IndexType)> {
+        self.indices.as_ref().map(|raw| (&raw.indices[..], raw.index_type))
+    }
+
+    /// Copies `count` vertices of every vertex buffer one after the other at the start of `buffer`.
+    fn upload_vertices<B>(
+        &self, queue: QueueId, factory: &Factory<B>, buffer: &mut Escape<Buffer<B>>, count: u32,
+        last: Option<BufferState>,
+    ) -> Result<Vec<VertexBufferLayout>, failure::Error>
     where
         B: gfx_hal::Backend,
     {
         let align = factory.physical().limits().non_coherent_atom_size;
-        let mut len = self
-            .vertices
-            .iter()
-            .map(|v| v.vertices.len() as u32 / v.format.stride)
-            .min()
-            .unwrap_or(0);
-
-        let buffer_size = self.vertices.iter().map(|v| (v.format.stride * len) as usize).sum();
-
+        let buffer_size = self.vertex_buffer_size(count) as usize;
         let aligned_size = align_by(align, buffer_size) as u64;
 
         let mut staging = factory.create_buffer(
@@ -300,14 +414,6 @@ impl<'a> MeshBuilder<'a> {
             Upload,
         )?;
 
-        let mut buffer = factory.create_buffer(
-            BufferInfo {
-                size: buffer_size as _,
-                usage: gfx_hal::buffer::Usage::VERTEX | gfx_hal::buffer::Usage::TRANSFER_DST,
-            },
-            Data,
-        )?;
-
         let mut mapped = staging.map(factory, 0..aligned_size)?;
         let mut writer = unsafe { mapped.write(factory, 0..aligned_size)? };
         let staging_slice = unsafe { writer.slice() };
@@ -317,16 +423,16 @@ impl<'a> MeshBuilder<'a> {
             .vertices
             .iter()
             .map(|RawVertices { vertices, format }| {
-                let size = (format.stride * len) as usize;
+                let size = (format.stride * count) as usize;
                 staging_slice[offset..offset + size].copy_from_slice(&vertices[0..size]);
                 let this_offset = offset as u64;
                 offset += size;
-                Ok(VertexBufferLayout {
+                VertexBufferLayout {
                     offset: this_offset,
                     format: format.clone(),
-                })
+                }
             })
-            .collect::<Result<_, failure::Error>>()?;
+            .collect();
 
         drop(staging_slice);
         drop(writer);
@@ -334,57 +440,404 @@ impl<'a> MeshBuilder<'a> {
 
         vertex_layouts.sort_unstable_by(|a, b| a.format.cmp(&b.format));
 
-        let index_buffer = match self.indices {
+        unsafe {
+            factory.upload_from_staging_buffer(buffer, 0, staging, last, vertex_buffer_state(queue))?;
+        }
+        Ok(vertex_layouts)
+    }
+
+    /// Builds and returns the new mesh.
+    ///
+    /// A mesh expects all vertex buffers to have the same number of elements.
+    /// If those are not equal, the length of smallest vertex buffer is selected,
+    /// effectively discaring extra data from larger buffers.
+    ///
+    /// `u32` indices are uploaded as `u16` when they fit, see `set_indices`.
+    ///
+    /// Note that contents of index buffer is not validated.
+    pub fn build<B>(&self, queue: QueueId, factory: &Factory<B>) -> Result<GenericMesh<B>, failure::Error>
+    where
+        B: gfx_hal::Backend,
+    {
+        let count = self.vertex_count();
+        let vertex_size = self.vertex_buffer_size(count);
+        let mut vertex_buffer = create_buffer(factory, self.capacity(vertex_size), gfx_hal::buffer::Usage::VERTEX)?;
+        let vertex_layouts = self.upload_vertices(queue, factory, &mut vertex_buffer, count, None)?;
+
+        let mut len = count;
+        let index_buffer = match self.index_data() {
             None => None,
-            Some(RawIndices {
-                ref indices,
-                index_type,
-            }) => {
+            Some((indices, index_type)) => {
                 len = (indices.len() / index_stride(index_type)) as u32;
-                let mut buffer = factory.create_buffer(
-                    BufferInfo {
-                        size: indices.len() as _,
-                        usage: gfx_hal::buffer::Usage::INDEX | gfx_hal::buffer::Usage::TRANSFER_DST,
-                    },
-                    Data,
-                )?;
+                let size = self.capacity(indices.len() as u64);
+                let mut buffer = create_buffer(factory, size, gfx_hal::buffer::Usage::INDEX)?;
                 unsafe {
                     // New buffer can't be touched by device yet.
-                    factory.upload_buffer(
-                        &mut buffer,
-                        0,
-                        &indices,
-                        None,
-                        BufferState::new(queue)
-                            .with_access(gfx_hal::buffer::Access::INDEX_BUFFER_READ)
-                            .with_stage(gfx_hal::pso::PipelineStage::VERTEX_INPUT),
-                    )?;
+                    factory.upload_buffer(&mut buffer, 0, indices, None, index_buffer_state(queue))?;
                 }
 
                 Some(IndexBuffer { buffer, index_type })
             }
         };
 
-        unsafe {
-            factory.upload_from_staging_buffer(
-                &mut buffer,
-                0,
-                staging,
-                None,
-                BufferState::new(queue)
-                    .with_access(gfx_hal::buffer::Access::VERTEX_BUFFER_READ)
-                    .with_stage(gfx_hal::pso::PipelineStage::VERTEX_INPUT),
-            )?;
-        }
-
+        let indirect_buffer = indirect_buffer(queue, factory, &self.sub_meshes, index_buffer.is_some())?;
         Ok(GenericMesh {
             vertex_layouts,
             index_buffer,
-            vertex_buffer: buffer,
+            vertex_buffer,
             prim: self.prim,
             len,
+            vertex_count: count,
+            bounds: self.bounds(),
+            sub_meshes: self.sub_meshes.clone(),
+            indirect_buffer,
         })
     }
+
+    /// Adds a sub mesh. Once a mesh has some, only they are drawn.
+    pub fn with_sub_mesh(mut self, sub_mesh: SubMesh) -> Self {
+        self.add_sub_mesh(sub_mesh);
+        self
+    }
+
+    /// Adds a sub mesh. Once a mesh has some, only they are drawn.
+    pub fn add_sub_mesh(&mut self, sub_mesh: SubMesh) -> &mut Self {
+        self.sub_meshes.push(sub_mesh);
+        self
+    }
+
+    pub fn sub_meshes(&self) -> &[SubMesh] {
+        &self.sub_meshes
+    }
+
+    /// Packs the vertices and indices of several named parts in one mesh, each part becoming a sub mesh. Indices
+    /// stay relative to the vertices of their part.
+    pub fn packed<V, I>(parts: I) -> MeshBuilder<'static>
+    where
+        V: AsVertex + 'static,
+        I: IntoIterator<Item = (String, Vec<V>, Vec<u32>)>,
+    {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut sub_meshes = Vec::new();
+        for (name, part_vertices, part_indices) in parts {
+            sub_meshes.push(SubMesh {
+                name,
+                first_index: indices.len() as u32,
+                index_count: part_indices.len() as u32,
+                base_vertex: vertices.len() as i32,
+            });
+            vertices.extend(part_vertices);
+            indices.extend(part_indices);
+        }
+        let mut builder = MeshBuilder::new().with_vertices(vertices).with_indices(indices);
+        builder.sub_meshes = sub_meshes;
+        builder
+    }
+
+    /// Marks the mesh as rewritten often, its buffers are built with room to grow.
+    pub fn with_dynamic(mut self) -> Self {
+        self.dynamic = true;
+        self
+    }
+
+    fn capacity(&self, size: u64) -> u64 {
+        if self.dynamic {
+            grown_capacity(size)
+        } else {
+            size
+        }
+    }
+}
+
+// region - Validation
+
+/// Sizes of a mesh, for debugging overlays and tests.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshStats {
+    pub vertices: u32,
+    pub indices: u32,
+    pub triangles: u32,
+    /// Bytes of each vertex buffer.
+    pub vertex_bytes: Vec<u64>,
+    pub index_bytes: u64,
+    /// `None` for meshes without indices.
+    pub index_type: Option<gfx_hal::IndexType>,
+    pub sub_meshes: usize,
+}
+
+impl MeshStats {
+    pub fn total_bytes(&self) -> u64 {
+        self.vertex_bytes.iter().sum::<u64>() + self.index_bytes
+    }
+}
+
+/// Report of `MeshBuilder::validate`.
+///
+/// Out of range indices and sub meshes make the GPU read outside the buffers, the other problems only give wrong
+/// results. Positions and normals are checked in vertex buffers of `Vertex` only.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshValidation {
+    pub vertex_count: u32,
+    pub triangle_count: u32,
+    /// Vertex buffers with more vertices than the smallest one, whose extra vertices are dropped, by index.
+    pub mismatched_buffers: Vec<usize>,
+    /// Vertex buffers whose size is not a multiple of their stride, by index.
+    pub partial_buffers: Vec<usize>,
+    /// Indices, plus the base vertex of their sub mesh, outside of the vertices, and the largest past the end.
+    pub out_of_range_indices: usize,
+    pub max_index: Option<u32>,
+    /// Sub meshes reaching past the last index, or vertex for meshes without indices, by index.
+    pub invalid_sub_meshes: Vec<usize>,
+    /// Triangles with the same vertex twice or a zero area.
+    pub degenerate_triangles: usize,
+    /// Vertices with a NaN or infinite position coordinate.
+    pub nan_positions: usize,
+    /// Vertices with a zero length, NaN or infinite normal.
+    pub zero_normals: usize,
+}
+
+impl MeshValidation {
+    /// Whether the mesh can be drawn without reading outside its buffers.
+    pub fn is_valid(&self) -> bool {
+        self.out_of_range_indices == 0 && self.invalid_sub_meshes.is_empty()
+    }
+
+    /// Whether nothing at all was found.
+    pub fn is_clean(&self) -> bool {
+        self.is_valid()
+            && self.mismatched_buffers.is_empty()
+            && self.partial_buffers.is_empty()
+            && self.degenerate_triangles == 0
+            && self.nan_positions == 0
+            && self.zero_normals == 0
+    }
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_ne_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_vec3(bytes: &[u8], offset: usize) -> [f32; 3] {
+    [read_f32(bytes, offset), read_f32(bytes, offset + 4), read_f32(bytes, offset + 8)]
+}
+
+impl<'a> MeshBuilder<'a> {
+    /// Indices as `u32`, whatever their type.
+    fn index_values(&self) -> Option<Vec<u32>> {
+        let raw = self.indices.as_ref()?;
+        Some(match raw.index_type {
+            gfx_hal::IndexType::U16 => raw
+                .indices
+                .chunks_exact(size_of::<u16>())
+                .map(|index| u32::from(u16::from_ne_bytes([index[0], index[1]])))
+                .collect(),
+            gfx_hal::IndexType::U32 => raw
+                .indices
+                .chunks_exact(size_of::<u32>())
+                .map(|index| u32::from_ne_bytes([index[0], index[1], index[2], index[3]]))
+                .collect(),
+        })
+    }
+
+    /// Positions of the vertices, from the first vertex buffer of `Vertex` or `Position`, both starting with it.
+    fn positions(&self, count: u32) -> Option<Vec<[f32; 3]>> {
+        let formats = [Vertex::vertex(), Position::vertex()];
+        let raw = self.vertices.iter().find(|raw| formats.contains(&raw.format))?;
+        Some(
+            raw.vertices
+                .chunks_exact(raw.format.stride as usize)
+                .take(count as usize)
+                .map(|vertex| read_vec3(vertex, 0))
+                .collect(),
+        )
+    }
+
+    /// Box and sphere around the vertices, `None` without vertices or with no buffer of `Vertex` or `Position`.
+    pub fn bounds(&self) -> Option<MeshBounds> {
+        MeshBounds::from_points(&self.positions(self.vertex_count())?)
+    }
+
+    /// Checks the data for anything that would crash the GPU or draw wrong, see `MeshValidation`.
+    pub fn validate(&self) -> MeshValidation {
+        let count = self.vertex_count();
+        let mut report = MeshValidation {
+            vertex_count: count,
+            ..MeshValidation::default()
+        };
+
+        for (index, raw) in self.vertices.iter().enumerate() {
+            let stride = raw.format.stride as usize;
+            if raw.vertices.len() % stride != 0 {
+                report.partial_buffers.push(index);
+            }
+            if raw.vertices.len() / stride > count as usize {
+                report.mismatched_buffers.push(index);
+            }
+        }
+
+        let vertex_format = Vertex::vertex();
+        for raw in self.vertices.iter().filter(|raw| raw.format == vertex_format) {
+            for vertex in raw.vertices.chunks_exact(vertex_format.stride as usize).take(count as usize) {
+                if read_vec3(vertex, 0).iter().any(|value| !value.is_finite()) {
+                    report.nan_positions += 1;
+                }
+                let normal = read_vec3(vertex, 3 * size_of::<f32>());
+                let length = normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2];
+                if !length.is_finite() || length <= std::f32::EPSILON {
+                    report.zero_normals += 1;
+                }
+            }
+        }
+
+        let vertices = self.drawn_vertices(count);
+        for vertex in vertices.iter().filter(|vertex| **vertex < 0 || **vertex >= i64::from(count)) {
+            report.out_of_range_indices += 1;
+            if *vertex >= 0 {
+                report.max_index = report.max_index.max(Some(*vertex as u32));
+            }
+        }
+
+        let positions = self.positions(count);
+        for triangle in vertices.chunks_exact(3) {
+            report.triangle_count += 1;
+            let repeated = triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2];
+            let flat = positions.as_ref().map_or(false, |positions| {
+                let corner = |vertex: i64| if vertex < 0 { None } else { positions.get(vertex as usize) };
+                guard!(let (Some(a), Some(b), Some(c)) = (corner(triangle[0]), corner(triangle[1]), corner(triangle[2]))
+                    else { return false });
+                let (a, b, c) = (Vector3::from(*a), Vector3::from(*b), Vector3::from(*c));
+                (b - a).cross(&(c - a)).norm_squared() <= std::f32::EPSILON * std::f32::EPSILON
+            });
+            if repeated || flat {
+                report.degenerate_triangles += 1;
+            }
+        }
+
+        report.invalid_sub_meshes = self.invalid_sub_meshes(vertices.len());
+        report
+    }
+
+    /// Whether the mesh can be drawn without reading outside its buffers, `MeshValidation::is_valid` without the
+    /// per vertex and per triangle checks of `validate`.
+    pub fn is_drawable(&self) -> bool {
+        let count = self.vertex_count();
+        let vertices = self.drawn_vertices(count);
+        vertices.iter().all(|vertex| *vertex >= 0 && *vertex < i64::from(count))
+            && self.invalid_sub_meshes(vertices.len()).is_empty()
+    }
+
+    /// Vertex drawn by each index, plus the base vertex of its sub mesh, or every vertex for meshes without indices.
+    fn drawn_vertices(&self, count: u32) -> Vec<i64> {
+        guard!(let Some(indices) = self.index_values() else { return (0..i64::from(count)).collect() });
+        let mut bases = vec![0i64; indices.len()];
+        for sub_mesh in &self.sub_meshes {
+            let start = (sub_mesh.first_index as usize).min(indices.len());
+            let end = (sub_mesh.first_index as usize + sub_mesh.index_count as usize).min(indices.len());
+            for base in &mut bases[start..end] {
+                *base = i64::from(sub_mesh.base_vertex);
+            }
+        }
+        indices.iter().zip(bases).map(|(index, base)| i64::from(*index) + base).collect()
+    }
+
+    /// Sub meshes reaching past the last of `drawn` indices or vertices, by index.
+    fn invalid_sub_meshes(&self, drawn: usize) -> Vec<usize> {
+        self.sub_meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, sub_mesh)| u64::from(sub_mesh.first_index) + u64::from(sub_mesh.index_count) > drawn as u64)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Sizes of the mesh as `build` uploads it.
+    pub fn stats(&self) -> MeshStats {
+        let count = self.vertex_count();
+        let indices = self.index_data();
+        let index_count = indices.as_ref().map_or(0, |(data, index_type)| data.len() / index_stride(*index_type));
+        let drawn = if indices.is_some() { index_count as u32 } else { count };
+        MeshStats {
+            vertices: count,
+            indices: index_count as u32,
+            triangles: drawn / 3,
+            vertex_bytes: self.vertices.iter().map(|raw| u64::from(raw.format.stride * count)).collect(),
+            index_bytes: indices.as_ref().map_or(0, |(data, _)| data.len() as u64),
+            index_type: indices.map(|(_, index_type)| index_type),
+            sub_meshes: self.sub_meshes.len(),
+        }
+    }
+}
+
+// endregion
+
+/// Buffer size leaving room for a mesh to grow, at most twice `size`.
+fn grown_capacity(size: u64) -> u64 {
+    size.next_power_of_two()
+}
+
+fn create_buffer<B>(
+    factory: &Factory<B>, size: u64, usage: gfx_hal::buffer::Usage,
+) -> Result<Escape<Buffer<B>>, failure::Error>
+where
+    B: gfx_hal::Backend,
+{
+    Ok(factory.create_buffer(
+        BufferInfo {
+            size,
+            usage: usage | gfx_hal::buffer::Usage::TRANSFER_DST,
+        },
+        Data,
+    )?)
+}
+
+/// Buffer of one indirect command per sub mesh, when there are several to draw at once and the device can.
+fn indirect_buffer<B>(
+    queue: QueueId, factory: &Factory<B>, sub_meshes: &[SubMesh], indexed: bool,
+) -> Result<Option<Escape<Buffer<B>>>, failure::Error>
+where
+    B: gfx_hal::Backend,
+{
+    let supported = factory.physical().features().contains(gfx_hal::Features::MULTI_DRAW_INDIRECT);
+    if !indexed || sub_meshes.len() < 2 || !supported {
+        return Ok(None);
+    }
+    let commands: Vec<DrawIndexedIndirect> = sub_meshes
+        .iter()
+        .map(|sub_mesh| DrawIndexedIndirect {
+            index_count: sub_mesh.index_count,
+            instance_count: 1,
+            first_index: sub_mesh.first_index,
+            vertex_offset: sub_mesh.base_vertex,
+            first_instance: 0,
+        })
+        .collect();
+    let size = (commands.len() * size_of::<DrawIndexedIndirect>()) as u64;
+    let mut buffer = create_buffer(factory, size, gfx_hal::buffer::Usage::INDIRECT)?;
+    unsafe {
+        factory.upload_buffer(
+            &mut buffer,
+            0,
+            &commands,
+            None,
+            BufferState::new(queue)
+                .with_access(gfx_hal::buffer::Access::INDIRECT_COMMAND_READ)
+                .with_stage(gfx_hal::pso::PipelineStage::DRAW_INDIRECT),
+        )?;
+    }
+    Ok(Some(buffer))
+}
+
+fn vertex_buffer_state(queue: QueueId) -> BufferState {
+    BufferState::new(queue)
+        .with_access(gfx_hal::buffer::Access::VERTEX_BUFFER_READ)
+        .with_stage(gfx_hal::pso::PipelineStage::VERTEX_INPUT)
+}
+
+fn index_buffer_state(queue: QueueId) -> BufferState {
+    BufferState::new(queue)
+        .with_access(gfx_hal::buffer::Access::INDEX_BUFFER_READ)
+        .with_stage(gfx_hal::pso::PipelineStage::VERTEX_INPUT)
 }
 
 fn align_by(align: usize, value: usize) -> usize {
@@ -402,6 +855,11 @@ pub struct GenericMesh<B: gfx_hal::Backend> {
     index_buffer: Option<IndexBuffer<B>>,
     prim: gfx_hal::Primitive,
     len: u32,
+    vertex_count: u32,
+    bounds: Option<MeshBounds>,
+    sub_meshes: Vec<SubMesh>,
+    /// Draws all the sub meshes at once, see `bind_and_draw_indirect`.
+    indirect_buffer: Option<Escape<Buffer<B>>>,
 }
 
 impl<B> GenericMesh<B>
@@ -427,6 +885,53 @@ where
     //     self.len
     // }
 
+    /// Rewrites the mesh with the content of `builder` through staging uploads, keeping its buffers when the new
+    /// data fits. Outgrown buffers are replaced by ones with room to grow. Returns whether a buffer was replaced.
+    pub fn update(
+        &mut self, builder: &MeshBuilder<'_>, queue: QueueId, factory: &Factory<B>,
+    ) -> Result<bool, failure::Error> {
+        let mut reallocated = false;
+        let count = builder.vertex_count();
+        let vertex_size = builder.vertex_buffer_size(count);
+        let last = if vertex_size > self.vertex_buffer.size() {
+            self.vertex_buffer =
+                create_buffer(factory, grown_capacity(vertex_size), gfx_hal::buffer::Usage::VERTEX)?;
+            reallocated = true;
+            None
+        } else {
+            Some(vertex_buffer_state(queue))
+        };
+        self.vertex_layouts = builder.upload_vertices(queue, factory, &mut self.vertex_buffer, count, last)?;
+
+        self.len = count;
+        self.vertex_count = count;
+        self.bounds = builder.bounds();
+        self.index_buffer = match builder.index_data() {
+            None => None,
+            Some((indices, index_type)) => {
+                self.len = (indices.len() / index_stride(index_type)) as u32;
+                let (mut buffer, last) = match self.index_buffer.take() {
+                    Some(index_buffer) if index_buffer.buffer.size() >= indices.len() as u64 => {
+                        (index_buffer.buffer, Some(index_buffer_state(queue)))
+                    }
+                    _ => {
+                        reallocated = true;
+                        let size = grown_capacity(indices.len() as u64);
+                        (create_buffer(factory, size, gfx_hal::buffer::Usage::INDEX)?, None)
+                    }
+                };
+                unsafe {
+                    factory.upload_buffer(&mut buffer, 0, indices, last, index_buffer_state(queue))?;
+                }
+                Some(IndexBuffer { buffer, index_type })
+            }
+        };
+        self.prim = builder.prim;
+        self.sub_meshes = builder.sub_meshes.clone();
+        self.indirect_buffer = indirect_buffer(queue, factory, &self.sub_meshes, self.index_buffer.is_some())?;
+        Ok(reallocated)
+    }
+
     fn get_vertex_iter<'a>(
         &'a self, formats: &[VertexFormat],
     ) -> Result<impl IntoIterator<Item = (&'a B::Buffer, u64)>, Incompatible> {
@@ -474,28 +979,123 @@ where
     //     Ok(self.len)
     // }
 
+    pub fn sub_meshes(&self) -> &[SubMesh] {
+        &self.sub_meshes
+    }
+
+    /// Index of the first sub mesh named `name`.
+    pub fn sub_mesh(&self, name: &str) -> Option<usize> {
+        self.sub_meshes.iter().position(|sub_mesh| sub_mesh.name == name)
+    }
+
+    /// Bounds of the vertices, computed when built or updated.
+    pub fn bounds(&self) -> Option<&MeshBounds> {
+        self.bounds.as_ref()
+    }
+
+    /// Sizes of the mesh on the GPU, buffers counted at their capacity.
+    pub fn stats(&self) -> MeshStats {
+        let index_type = self.index_buffer.as_ref().map(|index_buffer| index_buffer.index_type);
+        MeshStats {
+            vertices: self.vertex_count,
+            indices: index_type.map_or(0, |_| self.len),
+            triangles: index_type.map_or(self.vertex_count, |_| self.len) / 3,
+            vertex_bytes: vec![self.vertex_buffer.size()],
+            index_bytes: self.index_buffer.as_ref().map_or(0, |index_buffer| index_buffer.buffer.size()),
+            index_type,
+            sub_meshes: self.sub_meshes.len(),
+        }
+    }
+
+    /// Whether `bind_and_draw_indirect` can draw the mesh.
+    pub fn has_indirect(&self) -> bool {
+        self.indirect_buffer.is_some()
+    }
+
+    fn bind_buffers(
+        &self, first_binding: u32, formats: &[VertexFormat], encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<(), Incompatible> {
+        let vertex_iter = self.get_vertex_iter(formats)?;
+        unsafe {
+            if let Some(index_buffer) = self.index_buffer.as_ref() {
+                encoder.bind_index_buffer(index_buffer.buffer.raw(), 0, index_buffer.index_type);
+            }
+            encoder.bind_vertex_buffers(first_binding, vertex_iter);
+        }
+        Ok(())
+    }
+
+    /// Draws a sub mesh with the buffers already bound, returns the number of indices or vertices drawn.
+    fn draw_sub_mesh(
+        &self, sub_mesh: &SubMesh, instance_range: std::ops::Range<u32>, encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> u32 {
+        let range = sub_mesh.first_index..sub_mesh.first_index + sub_mesh.index_count;
+        unsafe {
+            if self.index_buffer.is_some() {
+                encoder.draw_indexed(range, sub_mesh.base_vertex, instance_range);
+            } else {
+                encoder.draw(range, instance_range);
+            }
+        }
+        sub_mesh.index_count
+    }
+
     /// Bind buffers to specified attribute locations and issue draw calls with given instance range.
+    ///
+    /// Meshes with sub meshes are bound once and drawn with a call per sub mesh.
     pub fn bind_and_draw(
         &self, first_binding: u32, formats: &[VertexFormat], instance_range: std::ops::Range<u32>,
         encoder: &mut RenderPassEncoder<'_, B>,
     ) -> Result<u32, Incompatible> {
-        let vertex_iter = self.get_vertex_iter(formats)?;
+        self.bind_buffers(first_binding, formats, encoder)?;
+        if !self.sub_meshes.is_empty() {
+            return Ok(self
+                .sub_meshes
+                .iter()
+                .map(|sub_mesh| self.draw_sub_mesh(sub_mesh, instance_range.clone(), encoder))
+                .sum());
+        }
         unsafe {
-            match self.index_buffer.as_ref() {
-                Some(index_buffer) => {
-                    encoder.bind_index_buffer(index_buffer.buffer.raw(), 0, index_buffer.index_type);
-                    encoder.bind_vertex_buffers(first_binding, vertex_iter);
-                    encoder.draw_indexed(0..self.len, 0, instance_range);
-                }
-                None => {
-                    encoder.bind_vertex_buffers(first_binding, vertex_iter);
-                    encoder.draw(0..self.len, instance_range);
-                }
+            if self.index_buffer.is_some() {
+                encoder.draw_indexed(0..self.len, 0, instance_range);
+            } else {
+                encoder.draw(0..self.len, instance_range);
             }
         }
 
         Ok(self.len)
     }
+
+    /// Binds the buffers once and draws the given sub meshes, by index, with the given instance range.
+    pub fn bind_and_draw_sub_meshes(
+        &self, first_binding: u32, formats: &[VertexFormat], sub_meshes: &[usize],
+        instance_range: std::ops::Range<u32>, encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<u32, Incompatible> {
+        self.bind_buffers(first_binding, formats, encoder)?;
+        Ok(sub_meshes
+            .iter()
+            .filter_map(|index| self.sub_meshes.get(*index))
+            .map(|sub_mesh| self.draw_sub_mesh(sub_mesh, instance_range.clone(), encoder))
+            .sum())
+    }
+
+    /// Binds the buffers and draws all the sub meshes with a single indirect multi-draw, as one instance of index
+    /// `0`. Returns `false` without drawing when the mesh has no indirect buffer.
+    pub fn bind_and_draw_indirect(
+        &self, first_binding: u32, formats: &[VertexFormat], encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<bool, Incompatible> {
+        guard!(let Some(indirect_buffer) = self.indirect_buffer.as_ref() else { return Ok(false) });
+        self.bind_buffers(first_binding, formats, encoder)?;
+        unsafe {
+            encoder.draw_indexed_indirect(
+                indirect_buffer.raw(),
+                0,
+                self.sub_meshes.len() as u32,
+                size_of::<DrawIndexedIndirect>() as u32,
+            );
+        }
+        Ok(true)
+    }
 }
 
 // endregion
@@ -598,3 +1198,129 @@ macro_rules! impl_builder_from_vec {
 impl_builder_from_vec!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
 
 // endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_vertex::VoxelLight;
+
+    fn vertex(xyz: [f32; 3]) -> Vertex {
+        Vertex { xyz, norm: [0.0, 1.0, 0.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: [1.0; 4] }
+    }
+
+    /// Corners of a unit square in the y = 0 plane.
+    fn square() -> Vec<Vertex> {
+        vec![vertex([0.0, 0.0, 0.0]), vertex([0.0, 0.0, 1.0]), vertex([1.0, 0.0, 1.0]), vertex([1.0, 0.0, 0.0])]
+    }
+
+    #[test]
+    fn square_is_clean() {
+        let report = MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2, 0, 2, 3]).validate();
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!((report.vertex_count, report.triangle_count), (4, 2));
+    }
+
+    #[test]
+    fn out_of_range_indices_are_invalid() {
+        let report = MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 4, 0, 2, 7]).validate();
+        assert!(!report.is_valid());
+        assert_eq!(report.out_of_range_indices, 2);
+        assert_eq!(report.max_index, Some(7));
+    }
+
+    #[test]
+    fn drawable_meshes_are_the_valid_ones() {
+        let sub_mesh = |first_index, index_count, base_vertex| SubMesh {
+            name: String::new(),
+            first_index,
+            index_count,
+            base_vertex,
+        };
+        let meshes = vec![
+            MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2, 0, 2, 3]),
+            MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 4]),
+            MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2]).with_sub_mesh(sub_mesh(0, 3, 1)),
+            MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2]).with_sub_mesh(sub_mesh(0, 3, 2)),
+            MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2]).with_sub_mesh(sub_mesh(3, 3, 0)),
+            MeshBuilder::new().with_vertices(square()).with_sub_mesh(sub_mesh(1, 3, 0)),
+            MeshBuilder::new().with_vertices(square()).with_sub_mesh(sub_mesh(2, 3, 0)),
+        ];
+        let drawable: Vec<bool> = meshes.iter().map(MeshBuilder::is_drawable).collect();
+        assert_eq!(drawable, vec![true, false, true, false, false, true, false]);
+        assert!(meshes.iter().all(|mesh| mesh.is_drawable() == mesh.validate().is_valid()));
+    }
+
+    #[test]
+    fn nan_positions_are_reported() {
+        let mut vertices = square();
+        vertices[1].xyz[0] = std::f32::NAN;
+        vertices[2].xyz[2] = std::f32::INFINITY;
+        let report = MeshBuilder::new().with_vertices(vertices).with_indices(vec![0u32, 1, 2, 0, 2, 3]).validate();
+        assert_eq!(report.nan_positions, 2);
+        assert!(report.is_valid() && !report.is_clean());
+    }
+
+    #[test]
+    fn zero_normals_are_reported() {
+        let mut vertices = square();
+        vertices[0].norm = [0.0; 3];
+        vertices[3].norm = [std::f32::NAN, 0.0, 0.0];
+        let report = MeshBuilder::new().with_vertices(vertices).with_indices(vec![0u32, 1, 2, 0, 2, 3]).validate();
+        assert_eq!(report.zero_normals, 2);
+        assert!(report.is_valid() && !report.is_clean());
+    }
+
+    #[test]
+    fn degenerate_triangles_are_reported() {
+        let mut vertices = square();
+        vertices.push(vertex([2.0, 0.0, 0.0]));
+        // A repeated vertex, then three corners on a line.
+        let indices = vec![0u32, 1, 1, 0, 1, 2, 0, 3, 4, 0, 2, 3];
+        let report = MeshBuilder::new().with_vertices(vertices).with_indices(indices).validate();
+        assert_eq!((report.triangle_count, report.degenerate_triangles), (4, 2));
+        assert!(report.is_valid() && !report.is_clean());
+    }
+
+    #[test]
+    fn mismatched_vertex_buffers_are_reported() {
+        let report = MeshBuilder::new()
+            .with_vertices(square())
+            .with_vertices(vec![Position([0.0; 3]); 6])
+            .with_indices(vec![0u32, 1, 2])
+            .validate();
+        assert_eq!(report.vertex_count, 4);
+        assert_eq!(report.mismatched_buffers, vec![1]);
+        assert!(report.partial_buffers.is_empty());
+        assert!(report.is_valid() && !report.is_clean());
+    }
+
+    #[test]
+    fn stats_count_narrowed_u16_indices() {
+        let stats = MeshBuilder::new().with_vertices(square()).with_indices(vec![0u32, 1, 2, 0, 2, 3]).stats();
+        assert_eq!(stats.index_type, Some(gfx_hal::IndexType::U16));
+        assert_eq!((stats.vertices, stats.indices, stats.triangles), (4, 6, 2));
+        assert_eq!(stats.vertex_bytes, vec![4 * u64::from(Vertex::vertex().stride)]);
+        assert_eq!(stats.index_bytes, 6 * 2);
+        assert_eq!(stats.total_bytes(), stats.vertex_bytes[0] + 12);
+    }
+
+    #[test]
+    fn indices_are_narrowed_whatever_is_set_first() {
+        let stats = MeshBuilder::new().with_indices(vec![0u32, 1, 2, 0, 2, 3]).with_vertices(square()).stats();
+        assert_eq!(stats.index_type, Some(gfx_hal::IndexType::U16));
+        assert_eq!(stats.index_bytes, 6 * 2);
+    }
+
+    #[test]
+    fn stats_count_u32_indices_past_u16() {
+        let count = u32::from(std::u16::MAX) + 2;
+        let stats = MeshBuilder::new()
+            .with_vertices(vec![Position([0.0; 3]); count as usize])
+            .with_indices(vec![0u32, 1, count - 1])
+            .stats();
+        assert_eq!(stats.index_type, Some(gfx_hal::IndexType::U32));
+        assert_eq!((stats.vertices, stats.indices, stats.triangles), (count, 3, 1));
+        assert_eq!(stats.vertex_bytes, vec![u64::from(count) * 12]);
+        assert_eq!(stats.index_bytes, 3 * 4);
+    }
+}
@@ -0,0 +1,309 @@
+//! Wavefront OBJ import into `MeshData`.
+use amethyst::assets::{Asset, AssetStorage, Format, Handle, Loader};
+use amethyst::ecs::VecStorage;
+use amethyst::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+
+use crate::render_mesh::{CompositeMesh, Mesh, MeshBuilder, MeshData};
+use crate::render_vertex::{Vertex, VoxelLight};
+
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// region - Scene
+
+/// Object or group of an OBJ file.
+#[derive(Debug, Clone)]
+pub struct ObjGroup {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl ObjGroup {
+    pub fn mesh(&self) -> MeshData {
+        MeshBuilder::new().with_vertices(self.vertices.clone()).with_indices(self.indices.clone()).into()
+    }
+}
+
+/// Content of an OBJ file, one entry per object or group with faces. Asset loaded with `ObjFormat`.
+#[derive(Debug, Clone)]
+pub struct ObjScene {
+    pub groups: Vec<ObjGroup>,
+}
+
+impl Asset for ObjScene {
+    const NAME: &'static str = "custom:ObjScene";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+impl ObjScene {
+    /// All the groups merged into one mesh.
+    pub fn merged(&self) -> MeshData {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for group in &self.groups {
+            let base = vertices.len() as u32;
+            vertices.extend_from_slice(&group.vertices);
+            indices.extend(group.indices.iter().map(|index| base + index));
+        }
+        MeshBuilder::new().with_vertices(vertices).with_indices(indices).into()
+    }
+
+    /// One `CompositeMesh` element per group, in file order.
+    pub fn composite(&self, loader: &Loader, storage: &AssetStorage<Mesh>) -> CompositeMesh {
+        CompositeMesh {
+            elements: self.groups.iter().map(|group| loader.load_from_data(group.mesh(), (), storage)).collect(),
+        }
+    }
+}
+
+// endregion
+
+// region - Parser
+
+/// Corner of a face, indices into the positions, uvs and normals read so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// Group being read, vertices deduplicated by corner and normal.
+struct GroupBuilder {
+    name: String,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    lookup: HashMap<(Corner, [u32; 3]), u32>,
+}
+
+impl GroupBuilder {
+    fn new(name: String) -> Self {
+        GroupBuilder {
+            name,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+
+    fn finish(self, groups: &mut Vec<ObjGroup>) {
+        if !self.indices.is_empty() {
+            groups.push(ObjGroup {
+                name: self.name,
+                vertices: self.vertices,
+                indices: self.indices,
+            });
+        }
+    }
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+fn parse_floats<'a, I: Iterator<Item = &'a str>>(parts: I, line: usize) -> io::Result<Vec<f32>> {
+    parts
+        .map(|part| part.parse::<f32>().map_err(|_| invalid(line, "invalid number")))
+        .collect()
+}
+
+/// One based index, negative ones count back from the last element read.
+fn resolve_index(part: &str, count: usize, line: usize) -> io::Result<usize> {
+    let index: i64 = part.parse().map_err(|_| invalid(line, "invalid index"))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid(line, "index out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+/// Parses an OBJ file. Polygons are triangulated as fans, faces without normals get their flat normal and faces
+/// without uvs get `(0, 0)`. Materials, lines and points are ignored.
+pub fn parse_obj(text: &str) -> io::Result<ObjScene> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut groups = Vec::new();
+    let mut group = GroupBuilder::new(String::from("default"));
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+        guard!(let Some(keyword) = parts.next() else { continue });
+        match keyword {
+            "v" => {
+                let values = parse_floats(parts, number)?;
+                guard!(let &[x, y, z, ..] = &values[..] else {
+                    return Err(invalid(number, "position needs 3 values"));
+                });
+                positions.push([x, y, z]);
+            }
+            "vt" => {
+                let values = parse_floats(parts, number)?;
+                guard!(let &[u, v, ..] = &values[..] else { return Err(invalid(number, "uv needs 2 values")) });
+                // OBJ puts v = 0 at the bottom of the image, textures are sampled from the top.
+                uvs.push([u, 1.0 - v]);
+            }
+            "vn" => {
+                let values = parse_floats(parts, number)?;
+                guard!(let &[x, y, z, ..] = &values[..] else { return Err(invalid(number, "normal needs 3 values")) });
+                normals.push([x, y, z]);
+            }
+            "o" | "g" => {
+                let name = parts.collect::<Vec<_>>().join(" ");
+                std::mem::replace(&mut group, GroupBuilder::new(name)).finish(&mut groups);
+            }
+            "f" => {
+                let corners = parts
+                    .map(|part| {
+                        let mut indices = part.split('/');
+                        let position = resolve_index(indices.next().unwrap_or(""), positions.len(), number)?;
+                        let uv = match indices.next() {
+                            Some(index) if !index.is_empty() => Some(resolve_index(index, uvs.len(), number)?),
+                            _ => None,
+                        };
+                        let normal = match indices.next() {
+                            Some(index) if !index.is_empty() => Some(resolve_index(index, normals.len(), number)?),
+                            _ => None,
+                        };
+                        Ok(Corner { position, uv, normal })
+                    })
+                    .collect::<io::Result<Vec<Corner>>>()?;
+                if corners.len() < 3 {
+                    return Err(invalid(number, "face needs 3 corners"));
+                }
+                let flat = flat_normal(&positions, &corners);
+                for i in 1..corners.len() - 1 {
+                    for corner in &[corners[0], corners[i], corners[i + 1]] {
+                        let norm = corner.normal.map_or(flat, |normal| normals[normal]);
+                        let key = (*corner, [norm[0].to_bits(), norm[1].to_bits(), norm[2].to_bits()]);
+                        let vertices = &mut group.vertices;
+                        let index = *group.lookup.entry(key).or_insert_with(|| {
+                            vertices.push(Vertex {
+                                xyz: positions[corner.position],
+                                norm,
+                                uv: corner.uv.map_or([0.0, 0.0], |uv| uvs[uv]),
+                                light: VoxelLight::UNLIT,
+                                color: WHITE,
+                            });
+                            vertices.len() as u32 - 1
+                        });
+                        group.indices.push(index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    group.finish(&mut groups);
+    Ok(ObjScene { groups })
+}
+
+/// Normal of the plane of a polygon, from its first three corners.
+fn flat_normal(positions: &[[f32; 3]], corners: &[Corner]) -> [f32; 3] {
+    let [a, b, c] = [positions[corners[0].position], positions[corners[1].position], positions[corners[2].position]];
+    let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+    let n = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if length <= std::f32::EPSILON {
+        return [0.0, 1.0, 0.0];
+    }
+    [n[0] / length, n[1] / length, n[2] / length]
+}
+
+// endregion
+
+// region - Format
+
+/// Format of Wavefront OBJ files. Loads an `ObjScene`, or a `MeshData` of all the groups merged.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ObjFormat;
+
+amethyst::assets::register_format!("OBJ", ObjFormat as MeshData);
+impl Format<MeshData> for ObjFormat {
+    fn name(&self) -> &'static str {
+        "OBJ"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<MeshData, Error> {
+        Ok(parse_obj(&String::from_utf8_lossy(&bytes))?.merged())
+    }
+}
+
+impl Format<ObjScene> for ObjFormat {
+    fn name(&self) -> &'static str {
+        "OBJ"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<ObjScene, Error> {
+        Ok(parse_obj(&String::from_utf8_lossy(&bytes))?)
+    }
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\n";
+
+    fn positions(group: &ObjGroup) -> Vec<[f32; 3]> {
+        group.indices.iter().map(|index| group.vertices[*index as usize].xyz).collect()
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let scene = parse_obj(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap();
+        assert_eq!(scene.groups.len(), 1);
+        let group = &scene.groups[0];
+        assert_eq!(group.name, "default");
+        assert_eq!(group.vertices.len(), 4);
+        assert_eq!(group.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert!(group.vertices.iter().all(|vertex| vertex.norm == [0.0, -1.0, 0.0] && vertex.uv == [0.0, 0.0]));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let absolute = parse_obj(&format!("{}vt 0.25 0.75\nf 2/1 3/1 4/1\n", SQUARE)).unwrap();
+        let relative = parse_obj(&format!("{}vt 0.25 0.75\nf -3/-1 -2/-1 -1/-1\n", SQUARE)).unwrap();
+        assert_eq!(positions(&relative.groups[0]), positions(&absolute.groups[0]));
+        assert_eq!(positions(&relative.groups[0]), vec![[1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]]);
+        // OBJ uvs start at the bottom of the image.
+        assert!(relative.groups[0].vertices.iter().all(|vertex| vertex.uv == [0.25, 0.25]));
+        assert!(parse_obj(&format!("{}f -5 1 2\n", SQUARE)).is_err());
+    }
+
+    #[test]
+    fn normals_without_uvs_are_read() {
+        let scene = parse_obj(&format!("{}vn 0 1 0\nf 1//1 2//1 3//1\n", SQUARE)).unwrap();
+        let group = &scene.groups[0];
+        assert!(group.vertices.iter().all(|vertex| vertex.norm == [0.0, 1.0, 0.0] && vertex.uv == [0.0, 0.0]));
+    }
+
+    #[test]
+    fn objects_and_groups_split_the_faces() {
+        let text = format!("{}f 1 2 3\ng unused\no first\nf 1 2 3\ng second part\nf 1 3 4\nf 1 2 4\n", SQUARE);
+        let scene = parse_obj(&text).unwrap();
+        let names: Vec<&str> = scene.groups.iter().map(|group| group.name.as_str()).collect();
+        assert_eq!(names, vec!["default", "first", "second part"]);
+        assert_eq!(scene.groups[1].indices, vec![0, 1, 2]);
+        assert_eq!(scene.groups[2].vertices.len(), 4);
+        assert_eq!(positions(&scene.groups[2])[3..], [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn corners_are_shared_only_with_the_same_normal() {
+        let shared = parse_obj(&format!("{}vn 0 1 0\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n", SQUARE)).unwrap();
+        assert_eq!(shared.groups[0].vertices.len(), 4);
+        assert_eq!(shared.groups[0].indices, vec![0, 1, 2, 0, 2, 3]);
+
+        let split = parse_obj(&format!("{}vn 0 1 0\nvn 1 0 0\nf 1//1 2//1 3//1\nf 1//2 3//2 4//2\n", SQUARE)).unwrap();
+        assert_eq!(split.groups[0].vertices.len(), 6);
+    }
+}
@@ -1,7 +1,7 @@
 /// Custom version of
 /// use amethyst::renderer::pass::DrawBase3D;
 use amethyst::{
-    assets::{AssetStorage},// Handle},
+    assets::{AssetStorage, Handle},
     core::{
         ecs::{Join, Read, ReadExpect, ReadStorage, SystemData},
         transform::Transform,
@@ -29,19 +29,22 @@ use amethyst::{
             shader::{Shader, SpirvShader},
         },
         // resources::Tint,
-        submodules::{DynamicVertexBuffer, EnvironmentSub}, //, MaterialId, MaterialSub},
+        submodules::{DynamicUniform, DynamicVertexBuffer, EnvironmentSub}, //, MaterialId, MaterialSub},
         transparent::Transparent,
         types::Backend, //, Mesh},
         util,
     },
 };
 use derivative::Derivative;
+use glsl_layout::AsStd140;
 // use smallvec::SmallVec;
 use std::marker::PhantomData;
 
-use crate::render_material::{FullTextureSet, ITextureSet, CompositeMaterial};
+use crate::render_fog::{Fog, ShaderFog};
+use crate::render_material::{FullTextureSet, ITextureSet, CompositeMaterial, Material};
 use crate::render_material_sub::{MaterialId, MaterialSub};
 use crate::render_mesh::{CompositeMesh, Mesh};
+use crate::render_prop::PropInstance;
 use crate::render_vertex::Vertex;
 use crate::render_visibility::{Visibility, VisibilitySortingSystem};
 use crate::render_backend::IExtendedBackend;
@@ -254,6 +257,7 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroupDesc<B, World> for BaseD
 
         let env = EnvironmentSub::new(factory, [ShaderStageFlags::VERTEX, ShaderStageFlags::FRAGMENT])?;
         let materials = MaterialSub::new(factory)?;
+        let fog = DynamicUniform::new(factory, ShaderStageFlags::FRAGMENT)?;
         let mut vertex_format_base = T::base_format();
 
         let (mut pipelines, pipeline_layout) = build_pipelines::<B, T>(
@@ -263,7 +267,7 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroupDesc<B, World> for BaseD
             framebuffer_height,
             &vertex_format_base,
             false,
-            vec![env.raw_layout(), materials.raw_layout()],
+            vec![env.raw_layout(), materials.raw_layout(), fog.raw_layout()],
             // vec![env.raw_layout()],
         )?;
 
@@ -276,7 +280,9 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroupDesc<B, World> for BaseD
             vertex_format_base,
             env,
             materials,
+            fog,
             models: DynamicVertexBuffer::new(),
+            props: Vec::new(),
             change: Default::default(),
             marker: PhantomData,
         }))
@@ -299,7 +305,10 @@ pub struct BaseDraw<B: IExtendedBackend, T: IRenderPassDef> {
     vertex_format_base: Vec<VertexFormat>,
     env: EnvironmentSub<B>,
     materials: MaterialSub<B, T::TextureSet>,
+    fog: DynamicUniform<B, ShaderFog>,
     models: DynamicVertexBuffer<B, VertexArgs>,
+    /// Instances of the visible props, sorted by material and mesh element before batching.
+    props: Vec<(Handle<Material>, u32, VertexArgs)>,
     change: util::ChangeDetection,
     marker: PhantomData<T>,
 }
@@ -322,7 +331,7 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDraw<
             meshes,
             materials,
             transforms,
-            // tints,
+            instances,
         ) = <(
             Read<'_, AssetStorage<Mesh>>,
             ReadExpect<'_, Visibility>,
@@ -332,29 +341,44 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDraw<
             ReadStorage<'_, CompositeMesh>,
             ReadStorage<'_, CompositeMaterial>,
             ReadStorage<'_, Transform>,
-            // ReadStorage<'_, Tint>,
+            ReadStorage<'_, PropInstance>,
         )>::fetch(resources);
 
         // Prepare environment
         self.env.process(factory, index, resources);
         self.materials.maintain();
 
+        let fog = resources.try_fetch::<Fog>().map_or_else(Fog::default, |fog| fog.clone());
+        let mut changed = self.fog.write(factory, index, ShaderFog::from_fog(&fog).std140());
+
         // self.static_batches.clear_inner();
         self.static_batches.swap_clear();
-        let mut changed = false;
 
         let materials_ref = &mut self.materials;
         let statics_ref = &mut self.static_batches;
+        let props = &mut self.props;
+
+        let mut insert_group = |mat: &Handle<Material>, mesh_element_id: u32, data: &mut Vec<VertexArgs>| {
+            if mesh_elements_assets.contains_id(mesh_element_id) {
+                // if let Some((mat, _)) = materials_ref.insert(factory, resources, mat) {
+                //     statics_ref.insert(mat, mesh_element_id, data.drain(..));
+                // }
+                if let Some((mat, this_changed)) = materials_ref.insert(factory, resources, mat) {
+                    changed = changed || this_changed;
+                    statics_ref.insert(mat, mesh_element_id, data.drain(..));
+                }
+            }
+        };
 
         // let static_input = || ((&materials, &meshes, &transforms, tints.maybe()));
-        let static_input = || ((&materials, &meshes, &transforms));
+        let static_input = || ((&materials, &meshes, &transforms, !&instances));
         {
             // profile_scope_impl!("prepare");
             (static_input(), &visibility.visible_unordered)
                 .join()
                 // .map(|((mat, mesh, tform, tint), _)| {
-                .flat_map(|((mat, mesh, tform), _)| {
-                    let args = VertexArgs::from_object_data(tform);
+                .flat_map(|((mat, mesh, tform, ()), _)| {
+                    let args = VertexArgs::from_object_data(tform, None);
                     // ((mat, mesh.id()), VertexArgs::from_object_data(tform, tint, 0))
                     mat.components
                         .iter()
@@ -362,17 +386,25 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDraw<
                         .map(move |(m, e)| ((m, e.id()), args))
                 })
                 // .for_each_group(|(mat, mesh_id), data| {
-                .for_each_group(|(mat, mesh_element_id), data| {
-                    if mesh_elements_assets.contains_id(mesh_element_id) {
-                        // if let Some((mat, _)) = materials_ref.insert(factory, resources, mat) {
-                        //     statics_ref.insert(mat, mesh_element_id, data.drain(..));
-                        // }
-                        if let Some((mat, this_changed)) = materials_ref.insert(factory, resources, mat) {
-                            changed = changed || this_changed;
-                            statics_ref.insert(mat, mesh_element_id, data.drain(..));
-                        }
-                    }
-                });
+                .for_each_group(|(mat, mesh_element_id), data| insert_group(mat, mesh_element_id, data));
+        }
+        {
+            // Instances of a prop are spread over the entities, sorting brings them together in one batch.
+            props.clear();
+            props.extend((&materials, &meshes, &transforms, &instances, &visibility.visible_unordered).join().flat_map(
+                |(mat, mesh, tform, instance, _)| {
+                    let args = VertexArgs::from_object_data(tform, Some(instance));
+                    mat.components
+                        .iter()
+                        .zip(mesh.elements.iter())
+                        .map(move |(m, e)| (m.clone(), e.id(), args))
+                },
+            ));
+            props.sort_by_key(|(mat, mesh_element_id, _)| (mat.id(), *mesh_element_id));
+            props
+                .drain(..)
+                .map(|(mat, mesh_element_id, args)| ((mat, mesh_element_id), args))
+                .for_each_group(|(mat, mesh_element_id), data| insert_group(&mat, mesh_element_id, data));
         }
         //{
         //    profile_scope_impl!("write");
@@ -410,6 +442,7 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDraw<
 
         encoder.bind_graphics_pipeline(&self.pipeline_basic);
         self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+        self.fog.bind(index, &self.pipeline_layout, 2, &mut encoder);
 
         if self.models.bind(index, models_loc, 0, &mut encoder) {
             let mut instances_drawn = 0;
@@ -422,14 +455,23 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDraw<
                             mesh_elements_assets.get_by_id_unchecked(*mesh_element_id)
                         }) {
                             println!("{}", batch_data.len() as u32);
-                            mesh_element
-                                .bind_and_draw(
-                                    0,
-                                    &self.vertex_format_base,
-                                    instances_drawn..instances_drawn + batch_data.len() as u32,
-                                    &mut encoder,
-                                )
-                                .unwrap();
+                            if batch_data.len() == 1 && mesh_element.has_indirect() {
+                                // Indirect commands draw instance 0, the model of this batch is bound there.
+                                self.models.bind(index, models_loc, instances_drawn as u64, &mut encoder);
+                                mesh_element
+                                    .bind_and_draw_indirect(0, &self.vertex_format_base, &mut encoder)
+                                    .unwrap();
+                                self.models.bind(index, models_loc, 0, &mut encoder);
+                            } else {
+                                mesh_element
+                                    .bind_and_draw(
+                                        0,
+                                        &self.vertex_format_base,
+                                        instances_drawn..instances_drawn + batch_data.len() as u32,
+                                        &mut encoder,
+                                    )
+                                    .unwrap();
+                            }
                         }
                         instances_drawn += batch_data.len() as u32;
                     }
@@ -535,13 +577,13 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDrawT
         // profile_scope_impl!("prepare transparent");
 
         // let (mesh_storage, visibility, meshes, materials, transforms, tints) =
-        let (mesh_elements_assets, visibility, meshes, transforms) = <(
+        let (mesh_elements_assets, visibility, meshes, transforms, instances) = <(
             Read<'_, AssetStorage<Mesh>>,
             ReadExpect<'_, Visibility>,
             ReadStorage<'_, CompositeMesh>,
             // ReadStorage<'_, Handle<Material>>,
             ReadStorage<'_, Transform>,
-            // ReadStorage<'_, Tint>,
+            ReadStorage<'_, PropInstance>,
         )>::fetch(resources);
 
         // Prepare environment
@@ -555,14 +597,14 @@ impl<B: IExtendedBackend, T: IRenderPassDef> RenderGroup<B, World> for BaseDrawT
         let mut changed = false;
 
         // let mut joined = (&materials, &meshes, &transforms, tints.maybe()).join();
-        let mut joined = (&meshes, &transforms).join();
+        let mut joined = (&meshes, &transforms, instances.maybe()).join();
         visibility
             .visible_ordered
             .iter()
             .filter_map(|e| joined.get_unchecked(e.id()))
             // .map(|(mat, mesh, tform, tint)| {
-            .flat_map(|(mesh, tform)| {
-                let args = VertexArgs::from_object_data(tform);
+            .flat_map(|(mesh, tform, instance)| {
+                let args = VertexArgs::from_object_data(tform, instance);
                 mesh.elements.iter().map(move |e| (e.id(), args))
             })
             // .for_each_group(|(mat, mesh_id), data| {
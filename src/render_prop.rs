@@ -0,0 +1,132 @@
+//! Props: decorations such as grass tufts and flowers, placed by the thousand with one mesh and one material.
+//!
+//! Every instance of a prop is an entity with its own `Transform` and `PropInstance`. The opaque pass draws all the
+//! visible instances of a prop in a single instanced draw, varying their tint and texture layer per instance.
+use amethyst::assets::{AssetStorage, Handle, Loader};
+use amethyst::core::Transform;
+use amethyst::ecs::{Component, DenseVecStorage, Entity, EntityBuilder};
+use amethyst::prelude::{Builder, World, WorldExt};
+use amethyst::renderer::types::Texture;
+use serde::{Deserialize, Serialize};
+
+use crate::render_cache::{MaterialCache, MeshCache};
+use crate::render_gltf::GLTF_CACHE_ID_OFFSET;
+use crate::render_material::{CompositeMaterial, Material, MaterialDefaults};
+use crate::render_mesh::{CompositeMesh, Mesh, MeshBuilder, MeshData};
+use crate::render_vertex::{Vertex, VoxelLight};
+
+/// Mesh and material cache ids of props start here, between the chunk textures and the glTF scenes.
+pub const PROP_CACHE_ID_OFFSET: u32 = 1 << 20;
+/// Highest prop id whose cache id stays below the glTF scenes.
+pub const PROP_MAX_ID: u32 = GLTF_CACHE_ID_OFFSET - PROP_CACHE_ID_OFFSET - 1;
+
+/// Per-instance variation of a prop.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PropInstance {
+    /// Multiplied with the vertex colors.
+    pub tint: [f32; 4],
+    /// Layer of the material texture, wrapping around `Material::layers`.
+    pub layer: u32,
+}
+
+impl Default for PropInstance {
+    fn default() -> Self {
+        PropInstance {
+            tint: [1.0; 4],
+            layer: 0,
+        }
+    }
+}
+
+impl Component for PropInstance {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Mesh and material shared by all the instances of a prop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prop {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<Material>,
+}
+
+impl Prop {
+    pub fn new(mesh: Handle<Mesh>, material: Handle<Material>) -> Self {
+        Prop { mesh, material }
+    }
+
+    /// Loads the mesh and a material of `texture` split into `layers`, both cached under the prop `id` so that
+    /// loading a prop again shares them. Panics when `id` is above `PROP_MAX_ID`.
+    pub fn load(world: &mut World, id: u32, mesh: MeshData, texture: Handle<Texture>, layers: u32) -> Self {
+        assert!(id <= PROP_MAX_ID, "prop id {} above {}", id, PROP_MAX_ID);
+        let cache_id = PROP_CACHE_ID_OFFSET + id;
+        let mesh = MeshCache::item(cache_id, world, |world| {
+            world.read_resource::<Loader>().load_from_data(mesh, (), &world.read_resource::<AssetStorage<Mesh>>())
+        });
+        let material = MaterialCache::item(cache_id, world, |world| {
+            let defaults = world.read_resource::<MaterialDefaults>().0.clone();
+            world.write_resource::<AssetStorage<Material>>().insert(Material {
+                diffuse: texture,
+                layers,
+                ..defaults
+            })
+        });
+        Prop { mesh, material }
+    }
+
+    /// Starts an instance of the prop placed at `transform`.
+    pub fn create_entity<'a>(
+        &self, world: &'a mut World, transform: Transform, instance: PropInstance,
+    ) -> EntityBuilder<'a> {
+        world
+            .create_entity()
+            .with(CompositeMesh { elements: vec![self.mesh.clone()] })
+            .with(CompositeMaterial { components: vec![self.material.clone()] })
+            .with(transform)
+            .with(instance)
+    }
+
+    /// Creates an instance per transform and variation, and returns them in order.
+    pub fn spawn_all<I>(&self, world: &mut World, instances: I) -> Vec<Entity>
+    where
+        I: IntoIterator<Item = (Transform, PropInstance)>,
+    {
+        instances
+            .into_iter()
+            .map(|(transform, instance)| self.create_entity(world, transform, instance).build())
+            .collect()
+    }
+}
+
+/// Mesh of two quads crossing on the diagonals of a block, `height` tall and seen from both sides, the usual shape
+/// of grass tufts and flowers.
+pub fn cross_mesh(height: f32) -> MeshData {
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    let diagonals = [([0.0, 0.0], [1.0, 1.0]), ([0.0, 1.0], [1.0, 0.0])];
+    let mut vertices = Vec::with_capacity(16);
+    let mut indices: Vec<u16> = Vec::with_capacity(24);
+    for (from, to) in diagonals.iter() {
+        let (dx, dz) = (to[0] - from[0], to[1] - from[1]);
+        let length = (dx * dx + dz * dz).sqrt();
+        let normal = [-dz / length, 0.0, dx / length];
+        for side in [1.0, -1.0].iter() {
+            let norm = [normal[0] * side, 0.0, normal[2] * side];
+            let base = vertices.len() as u16;
+            for (xz, y, uv) in [
+                (from, 0.0, [0.0, 1.0]),
+                (to, 0.0, [1.0, 1.0]),
+                (to, height, [1.0, 0.0]),
+                (from, height, [0.0, 0.0]),
+            ]
+            .iter()
+            {
+                let xyz = [xz[0], *y, xz[1]];
+                vertices.push(Vertex { xyz, norm, uv: *uv, light: VoxelLight::UNLIT, color: WHITE });
+            }
+            // The back side winds the other way round.
+            let quad: [u16; 6] = if *side > 0.0 { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+            indices.extend(quad.iter().map(|index| base + index));
+        }
+    }
+    MeshBuilder::new().with_vertices(vertices).with_indices(indices).into()
+}
@@ -14,6 +14,7 @@ use amethyst::assets::{AssetStorage, Handle, HotReloadStrategy, ProcessingState,
 use amethyst::core::{
     components::Transform,
     ecs::{Read, ReadExpect, ReadStorage, RunNow, System, SystemData, World, Write, WriteExpect},
+    shrev::EventChannel,
     timing::Time,
     Hidden, HiddenPropagate,
 };
@@ -31,7 +32,8 @@ use std::{marker::PhantomData, sync::Arc};
 
 use crate::render_cache::{MaterialCache, MeshCache, TextureCache};
 use crate::render_material::{Material, CompositeMaterial, MaterialDefaults};
-use crate::render_mesh::{Mesh, CompositeMesh};
+use crate::render_mesh::{Mesh, CompositeMesh, MeshChanged, MeshUpdates};
+use crate::render_prop::PropInstance;
 use crate::render_visibility::Visibility;
 use crate::render_backend::IExtendedBackend;
 
@@ -70,6 +72,7 @@ type SetupData<'a> = (
     ReadStorage<'a, Handle<Material>>,
     ReadStorage<'a, CompositeMaterial>,
     ReadStorage<'a, CompositeMesh>,
+    ReadStorage<'a, PropInstance>,
     // ReadStorage<'a, Tint>,
     ReadStorage<'a, Light>,
     ReadStorage<'a, Camera>,
@@ -212,6 +215,19 @@ impl<'a, B: IExtendedBackend> System<'a> for MeshProcessorSystem<B> {
                 // #[cfg(feature = "profiler")]
                 // profile_scope!("process_mesh");
 
+                // The full report walks every vertex and triangle, release builds only check what the GPU needs.
+                if cfg!(debug_assertions) {
+                    let report = b.0.validate();
+                    if !report.is_valid() {
+                        return Err(failure::err_msg(format!("Invalid mesh: {:?}", report)).compat().into());
+                    }
+                    if !report.is_clean() {
+                        log::warn!("Mesh with problems: {:?}", report);
+                    }
+                } else if !b.0.is_drawable() {
+                    return Err(failure::err_msg("Invalid mesh: indices or sub meshes out of range").compat().into());
+                }
+
                 b.0.build(*queue_id, &factory)
                     .map(B::wrap_mesh_element)
                     .map(ProcessingState::Loaded)
@@ -224,6 +240,52 @@ impl<'a, B: IExtendedBackend> System<'a> for MeshProcessorSystem<B> {
     }
 }
 
+/// Rewrites meshes queued in `MeshUpdates` in place and sends a `MeshChanged` for each. Updates that are not
+/// `MeshBuilder::is_drawable` are dropped, debug builds also log the other problems `MeshBuilder::validate` finds.
+#[derive(Debug, derivative::Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct MeshUpdateSystem<B: IExtendedBackend>(PhantomData<B>);
+impl<'a, B: IExtendedBackend> System<'a> for MeshUpdateSystem<B> {
+    type SystemData = (
+        Write<'a, AssetStorage<Mesh>>,
+        Write<'a, MeshUpdates>,
+        Write<'a, EventChannel<MeshChanged>>,
+        ReadExpect<'a, QueueId>,
+        ReadExpect<'a, Factory<B>>,
+    );
+
+    fn run(&mut self, (mut mesh_storage, mut updates, mut changed, queue_id, factory): Self::SystemData) {
+        if updates.is_empty() {
+            return;
+        }
+        for (handle, data) in updates.take() {
+            if cfg!(debug_assertions) {
+                let report = data.0.validate();
+                if !report.is_valid() {
+                    log::error!("Dropped invalid update of mesh {}: {:?}", handle.id(), report);
+                    continue;
+                }
+                if !report.is_clean() {
+                    log::warn!("Mesh update with problems: {:?}", report);
+                }
+            } else if !data.0.is_drawable() {
+                log::error!("Dropped invalid update of mesh {}: indices or sub meshes out of range", handle.id());
+                continue;
+            }
+            match mesh_storage.get_mut(&handle).and_then(B::unwrap_mesh_element_mut) {
+                Some(mesh) => {
+                    match mesh.update(&data.0, *queue_id, &factory) {
+                        Ok(_) => changed.single_write(MeshChanged(handle.id())),
+                        Err(error) => log::error!("Failed to update mesh {}: {}", handle.id(), error),
+                    }
+                }
+                // Not processed yet, kept for a later frame.
+                None => updates.update(handle, data),
+            }
+        }
+    }
+}
+
 /// Asset processing system for `Texture` asset type.
 #[derive(Debug, derivative::Derivative)]
 #[derivative(Default(bound = ""))]
@@ -303,5 +365,6 @@ fn create_default_mat<B: IExtendedBackend>(world: &mut World) -> Material {
         // ambient_occlusion,
         // cavity,
         uv_offset: TextureOffset::default(),
+        layers: 1,
     }
 }
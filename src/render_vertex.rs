@@ -1,7 +1,8 @@
 use amethyst::renderer::rendy::util::types::vertex::{
-    AsVertex,
-    VertexFormat, Normal, Position, TexCoord,
+    AsAttribute, AsVertex,
+    Color, VertexFormat, Normal, Position, TexCoord,
 };
+use gfx_hal::format::Format;
 
 use std::fmt::Debug;
 
@@ -10,7 +11,7 @@ use glsl_layout::*;
 use amethyst::core::{math::Matrix4, Transform};
 
 use amethyst::renderer::rendy::mesh::Model;
-// use gfx_hal::format::Format;
+
 // use amethyst::renderer::pod::{*, Tint};
 // use amethyst::renderer::resources::Tint as TintComponent;
 
@@ -24,54 +25,105 @@ use amethyst::renderer::rendy::mesh::Model;
 // use amethyst::assets::{AssetStorage, Handle};
 use amethyst::core::math::convert;
 
+use crate::render_prop::PropInstance;
+
 // region - Vertex
 
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[repr(C)]
 pub struct Vertex {
     pub xyz: [f32; 3],
     pub norm: [f32; 3],
     pub uv: [f32; 2],
+    /// Sky and block light, see `VoxelLight`.
+    pub light: [f32; 2],
+    /// Multiplied with the texture, biome tint of grass.
+    pub color: [f32; 4],
 }
 
 impl AsVertex for Vertex {
     fn vertex() -> VertexFormat {
-        VertexFormat::new((Position::vertex(), Normal::vertex(), TexCoord::vertex()))
+        VertexFormat::new((
+            Position::vertex(),
+            Normal::vertex(),
+            TexCoord::vertex(),
+            VoxelLight::vertex(),
+            Color::vertex(),
+        ))
     }
 }
 
+/// Voxel light of a vertex: sky light and block light, both in the `0.0..=1.0` range.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct VoxelLight(pub [f32; 2]);
+
+impl VoxelLight {
+    /// Full sky light, for meshes that are not part of the voxel world.
+    pub const UNLIT: [f32; 2] = [1.0, 0.0];
+}
+
+impl AsAttribute for VoxelLight {
+    const NAME: &'static str = "voxel_light";
+    const FORMAT: Format = Format::Rg32Sfloat;
+}
+
+/// Instance-rate color multiplied with the vertex color.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct InstanceTint(pub [f32; 4]);
+
+impl AsAttribute for InstanceTint {
+    const NAME: &'static str = "tint";
+    const FORMAT: Format = Format::Rgba32Sfloat;
+}
+
+/// Instance-rate layer of the material texture, see `Material::layers`.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct TextureLayer(pub u32);
+
+impl AsAttribute for TextureLayer {
+    const NAME: &'static str = "texture_layer";
+    const FORMAT: Format = Format::R32Uint;
+}
+
+// endregion
+
 // region - Shader
 
 /// Material Instance-rate vertex arguments.
 /// ```glsl,ignore
 ///  mat4 model;
+///  vec4 tint;
+///  uint texture_layer;
 /// ```
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
 #[repr(C, packed)]
 pub struct VertexArgs {
     /// Instance-rate model matrix
     pub model: mat4,
-    // /// Instance-rate `Tint`
-    // pub tint: vec4,
+    /// Instance-rate `InstanceTint`
+    pub tint: vec4,
+    /// Instance-rate `TextureLayer`
+    pub texture_layer: uint,
 }
 
 impl AsVertex for VertexArgs {
     fn vertex() -> VertexFormat {
-        // VertexFormat::new((Model::vertex(), Tint::vertex(), MaterialIdx::vertex()))
-        VertexFormat::new(Model::vertex())
+        VertexFormat::new((Model::vertex(), InstanceTint::vertex(), TextureLayer::vertex()))
     }
 }
 
 impl VertexArgs {
-    /// Populate `MaterialVertexArgs` from the supplied `Transform` and `TintComponent`
+    /// Populate `VertexArgs` from the supplied `Transform` and `PropInstance`, white and the first layer without one.
     #[inline]
-    pub fn from_object_data(
-        transform: &Transform,
-        // tint: Option<&TintComponent>,
-    ) -> Self {
+    pub fn from_object_data(transform: &Transform, instance: Option<&PropInstance>) -> Self {
         let model: [[f32; 4]; 4] = convert::<_, Matrix4<f32>>(*transform.global_matrix()).into();
         VertexArgs {
             model: model.into(),
-            // tint: tint.map_or([1.0; 4].into(), |t| t.0.into_pod()),
+            tint: instance.map_or([1.0; 4], |instance| instance.tint).into(),
+            texture_layer: instance.map_or(0, |instance| instance.layer),
         }
     }
 }
@@ -1,5 +1,6 @@
 
 //! Transparency, visibility sorting and camera centroid culling for 3D Meshes.
+use amethyst::assets::AssetStorage;
 use amethyst::renderer::{
     camera::{ActiveCamera, Camera},
     transparent::Transparent,
@@ -8,15 +9,21 @@ use amethyst::core::{
     ecs::{
         hibitset::BitSet,
         prelude::{
-            Component, DenseVecStorage, Entities, Entity, Join, Read, ReadStorage, System, Write,
+            Component, ComponentEvent, DenseVecStorage, Entities, Entity, FlaggedStorage, Join, Read, ReadStorage,
+            ReaderId, System, SystemData, World, Write, WriteStorage,
         },
     },
-    math::{convert, distance_squared, Matrix4, Point3, Vector4},
+    math::{convert, distance, distance_squared, Matrix4, Point3, Vector3, Vector4},
+    shrev::EventChannel,
     Hidden, HiddenPropagate, Transform,
 };
 
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::render_bvh::BoundsTree;
+use crate::render_mesh::{CompositeMesh, Mesh, MeshChanged};
 
 // #[cfg(feature = "profiler")]
 // use amethyst::thread_profiler::profile_scope;
@@ -29,6 +36,8 @@ pub struct Visibility {
     pub visible_unordered: BitSet,
     /// Visible entities that need to be drawn in the given order
     pub visible_ordered: Vec<Entity>,
+    /// Distance from the camera to the centroid of each visible entity.
+    pub camera_distances: HashMap<Entity, f32>,
 }
 
 /// Determine what entities are visible to the camera, and which are not. Will also sort transparent
@@ -36,10 +45,18 @@ pub struct Visibility {
 ///
 /// Note that this should run after `Transform` has been updated for the current frame, and
 /// before rendering occurs.
+///
+/// Entities with an `AxisAlignedBoundingBox` are culled through a `BoundsTree` kept up to date from the changes of
+/// their `Transform` and box, the others are tested one by one against their `BoundingSphere`.
 #[derive(Default, Debug)]
 pub struct VisibilitySortingSystem {
     centroids: Vec<Internals>,
     transparent: Vec<Internals>,
+    tree: BoundsTree,
+    transform_reader: Option<ReaderId<ComponentEvent>>,
+    aabb_reader: Option<ReaderId<ComponentEvent>>,
+    /// Ids of the entities to move in the tree.
+    moved: BitSet,
 }
 
 /// Defines a object's bounding sphere used by frustum culling.
@@ -79,6 +96,209 @@ impl Component for BoundingSphere {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// Defines the axis aligned box around an object, in its local space.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisAlignedBoundingBox {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl AxisAlignedBoundingBox {
+    /// Smallest box holding all the points, `None` without points.
+    pub fn from_points<I: IntoIterator<Item = [f32; 3]>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = Point3::from(points.next()?);
+        Some(points.fold(Self { min: first, max: first }, |aabb, point| Self {
+            min: aabb.min.inf(&Point3::from(point)),
+            max: aabb.max.sup(&Point3::from(point)),
+        }))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis])
+    }
+
+    /// Box enlarged by `margin` on every side.
+    pub fn grown(&self, margin: f32) -> Self {
+        let margin = Vector3::new(margin, margin, margin);
+        Self {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Box around this one once transformed by `matrix`.
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = matrix.transform_point(&self.center());
+        let half = self.half_extents();
+        let extent = |row: usize| {
+            matrix[(row, 0)].abs() * half.x + matrix[(row, 1)].abs() * half.y + matrix[(row, 2)].abs() * half.z
+        };
+        let extents = Vector3::new(extent(0), extent(1), extent(2));
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// Flagged, so that `VisibilitySortingSystem` only moves the boxes that changed in its `BoundsTree`.
+impl Component for AxisAlignedBoundingBox {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+/// Box and sphere around the vertices of a mesh, computed when it is built.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshBounds {
+    pub aabb: AxisAlignedBoundingBox,
+    /// Centered on the box, as small as the vertices allow.
+    pub sphere: BoundingSphere,
+}
+
+impl MeshBounds {
+    /// Bounds of the points, `None` without points.
+    pub fn from_points(points: &[[f32; 3]]) -> Option<Self> {
+        let aabb = AxisAlignedBoundingBox::from_points(points.iter().cloned())?;
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|point| distance_squared(&center, &Point3::from(*point)))
+            .fold(0.0f32, f32::max)
+            .sqrt();
+        Some(Self {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        })
+    }
+
+    /// Bounds holding both, the sphere encloses the two spheres.
+    pub fn union(&self, other: &Self) -> Self {
+        let (a, b) = (&self.sphere, &other.sphere);
+        let between = distance(&a.center, &b.center);
+        let sphere = if between + b.radius <= a.radius {
+            a.clone()
+        } else if between + a.radius <= b.radius {
+            b.clone()
+        } else {
+            let radius = (between + a.radius + b.radius) * 0.5;
+            BoundingSphere {
+                center: a.center + (b.center - a.center) * ((radius - a.radius) / between),
+                radius,
+            }
+        };
+        Self {
+            aabb: self.aabb.union(&other.aabb),
+            sphere,
+        }
+    }
+}
+
+/// Attaches `BoundingSphere` and `AxisAlignedBoundingBox` computed from the meshes to every `CompositeMesh` entity,
+/// and keeps them up to date as meshes load or change.
+///
+/// Only entities whose `CompositeMesh` changed, or with an element in a `MeshChanged` event, are recomputed. Those
+/// whose meshes are not loaded yet keep their bounds and are tried again every frame until they are.
+#[derive(Default, Debug)]
+pub struct MeshBoundsSystem {
+    mesh_reader: Option<ReaderId<ComponentEvent>>,
+    changed_reader: Option<ReaderId<MeshChanged>>,
+    /// Ids of the entities whose bounds are out of date.
+    dirty: BitSet,
+}
+
+impl<'a> System<'a> for MeshBoundsSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, EventChannel<MeshChanged>>,
+        ReadStorage<'a, CompositeMesh>,
+        WriteStorage<'a, BoundingSphere>,
+        WriteStorage<'a, AxisAlignedBoundingBox>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.mesh_reader = Some(WriteStorage::<CompositeMesh>::fetch(world).register_reader());
+        self.changed_reader = Some(world.fetch_mut::<EventChannel<MeshChanged>>().register_reader());
+    }
+
+    fn run(&mut self, (entities, mesh_storage, changed, meshes, mut spheres, mut boxes): Self::SystemData) {
+        for event in meshes.channel().read(self.mesh_reader.as_mut().expect("reader set up")) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    self.dirty.add(*id);
+                }
+                ComponentEvent::Removed(id) => {
+                    self.dirty.remove(*id);
+                }
+            }
+        }
+        let changed: HashSet<u32> = changed
+            .read(self.changed_reader.as_mut().expect("reader set up"))
+            .map(|MeshChanged(id)| *id)
+            .collect();
+        if !changed.is_empty() {
+            for (entity, mesh) in (&entities, &meshes).join() {
+                if mesh.elements.iter().any(|element| changed.contains(&element.id())) {
+                    self.dirty.add(entity.id());
+                }
+            }
+        }
+
+        let mut done = Vec::new();
+        for (entity, mesh, _) in (&entities, &meshes, &self.dirty).join() {
+            // Every element must be loaded, bounds of a part of the mesh would cull the rest.
+            let mut loaded = !mesh.elements.is_empty();
+            let mut bounds: Option<MeshBounds> = None;
+            for element in &mesh.elements {
+                guard!(let Some(element) = mesh_storage.get(element) else {
+                    loaded = false;
+                    break;
+                });
+                if let Some(element) = element.bounds() {
+                    bounds = Some(bounds.map_or_else(|| element.clone(), |bounds| bounds.union(element)));
+                }
+            }
+            if !loaded {
+                continue;
+            }
+            done.push(entity.id());
+            // Meshes without vertices have no bounds.
+            guard!(let Some(bounds) = bounds else { continue });
+
+            if spheres.get(entity) != Some(&bounds.sphere) {
+                spheres.insert(entity, bounds.sphere.clone()).ok();
+            }
+            if boxes.get(entity) != Some(&bounds.aabb) {
+                boxes.insert(entity, bounds.aabb).ok();
+            }
+        }
+        for id in done {
+            self.dirty.remove(id);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Internals {
     entity: Entity,
@@ -105,8 +325,15 @@ impl<'a> System<'a> for VisibilitySortingSystem {
         ReadStorage<'a, Transparent>,
         ReadStorage<'a, Transform>,
         ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, AxisAlignedBoundingBox>,
     );
 
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.transform_reader = Some(WriteStorage::<Transform>::fetch(world).register_reader());
+        self.aabb_reader = Some(WriteStorage::<AxisAlignedBoundingBox>::fetch(world).register_reader());
+    }
+
     fn run(
         &mut self,
         (
@@ -119,6 +346,7 @@ impl<'a> System<'a> for VisibilitySortingSystem {
             transparent,
             transform,
             bound,
+            aabbs,
         ): Self::SystemData,
     ) {
         // #[cfg(feature = "profiler")]
@@ -141,17 +369,54 @@ impl<'a> System<'a> for VisibilitySortingSystem {
                 * camera_transform.global_matrix().try_inverse().unwrap(),
         );
 
+        let transform_events = transform.channel().read(self.transform_reader.as_mut().expect("reader set up"));
+        let aabb_events = aabbs.channel().read(self.aabb_reader.as_mut().expect("reader set up"));
+        for event in transform_events.chain(aabb_events) {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) | ComponentEvent::Removed(id) => {
+                    self.moved.add(*id);
+                }
+            }
+        }
+        for id in (&self.moved).join() {
+            let entity = entities.entity(id);
+            match (entities.is_alive(entity), transform.get(entity), aabbs.get(entity)) {
+                (true, Some(transform), Some(aabb)) => {
+                    self.tree.update(entity, aabb.transformed(transform.global_matrix()));
+                }
+                _ => {
+                    self.tree.remove(id);
+                }
+            }
+        }
+        self.moved.clear();
+
         self.centroids.clear();
+        let centroids = &mut self.centroids;
+        self.tree.query(&frustum, |entity| {
+            if hidden.contains(entity) || hidden_prop.contains(entity) {
+                return;
+            }
+            guard!(let (Some(transform), Some(aabb)) = (transform.get(entity), aabbs.get(entity)) else { return });
+            let centroid = transform.global_matrix().transform_point(&aabb.center());
+            centroids.push(Internals {
+                entity,
+                transparent: transparent.contains(entity),
+                centroid,
+                camera_distance: distance_squared(&centroid, &camera_centroid),
+            });
+        });
         self.centroids.extend(
             (
                 &*entities,
                 &transform,
                 bound.maybe(),
+                !&aabbs,
                 !&hidden,
                 !&hidden_prop,
             )
                 .join()
-                .map(|(entity, transform, sphere, _, _)| {
+                .map(|(entity, transform, sphere, _, _, _)| {
                     let pos = sphere.map_or(&origin, |s| &s.center);
                     let matrix = transform.global_matrix();
                     (
@@ -191,9 +456,22 @@ impl<'a> System<'a> for VisibilitySortingSystem {
         visibility
             .visible_ordered
             .extend(self.transparent.iter().map(|c| c.entity));
+
+        visibility.camera_distances.clear();
+        visibility
+            .camera_distances
+            .extend(self.centroids.iter().map(|c| (c.entity, c.camera_distance.sqrt())));
     }
 }
 
+/// Position of a box relative to a frustum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
 /// Simple view Frustum implementation
 #[derive(Debug)]
 pub struct Frustum {
@@ -233,4 +511,28 @@ impl Frustum {
         }
         true
     }
+
+    /// Check if the given world space box is within the Frustum
+    pub fn check_aabb(&self, aabb: &AxisAlignedBoundingBox) -> bool {
+        self.classify_aabb(aabb) != Containment::Outside
+    }
+
+    /// Whether the given world space box is outside the Frustum, crosses its sides or is fully inside.
+    pub fn classify_aabb(&self, aabb: &AxisAlignedBoundingBox) -> Containment {
+        let center = aabb.center();
+        let half = aabb.half_extents();
+        let mut containment = Containment::Inside;
+        for plane in &self.planes {
+            let normal = plane.xyz();
+            let distance = normal.dot(&center.coords) + plane.w;
+            let reach = normal.x.abs() * half.x + normal.y.abs() * half.y + normal.z.abs() * half.z;
+            if distance <= -reach {
+                return Containment::Outside;
+            }
+            if distance < reach {
+                containment = Containment::Intersecting;
+            }
+        }
+        containment
+    }
 }
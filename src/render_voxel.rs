@@ -5,10 +5,9 @@ use amethyst::{
     assets::{AssetLoaderSystemData, AssetStorage}, //, Handle, Loader},
     ecs::{EntityBuilder, WorldExt, Write},
     // controls::HideCursor,
-    core::{
+    // core::{
     //     transform::Transform,
-        math::{Vector3},
-    },
+    // },
     // error::Error,
     // input::{is_key_down, is_mouse_button_down},
     prelude::*,
@@ -32,8 +31,7 @@ use amethyst::ecs::prelude::{Component, DenseVecStorage};
 use crate::render_cache::{MaterialCache, MeshCache, TextureCache};
 use crate::render_material::{Material as RenderMaterial, CompositeMaterial, MaterialDefaults};
 use crate::render_mesh::{CompositeMesh, Indices, Mesh, MeshBuilder, MeshData};
-use crate::render_vertex::Vertex;
-use crate::render_visibility::BoundingSphere;
+use crate::render_vertex::{Vertex, VoxelLight};
 
 use amethyst::ecs::shred::SystemData;
 
@@ -108,56 +106,52 @@ impl Voxel {
             components: vec![mat_elt],
         };
 
-        let bounds = BoundingSphere {
-            center: Vector3::new(0.5, 0.5, 0.5).into(),
-            radius: 0.8705505633_f32
-        };
-
         world.create_entity().with(mesh).with(mat)
-            .with(bounds)
         // .with(Transparent::default())
     }
 }
 
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 fn block_mesh() -> MeshData {
     
     #[cfg_attr(rustfmt, rustfmt_skip)]
     let vertices: Vec<Vertex> = vec!(
       // Face 1 (front)
-      Vertex { xyz: [0.0, 0.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [1.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [0.0, 1.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [1.0, 0.0] }, /* top left */
-      Vertex { xyz: [1.0, 0.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [0.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [1.0, 1.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [0.0, 0.0] }, /* top right */
+      Vertex { xyz: [0.0, 0.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [0.0, 1.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [1.0, 0.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [1.0, 1.0, 0.0], norm: [0.0, 0.0, -1.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
       // Face 2 (top)
-      Vertex { xyz: [0.0, 1.0, 0.0], norm: [0.0, 1.0, 0.0], uv: [1.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [0.0, 1.0, 1.0], norm: [0.0, 1.0, 0.0], uv: [1.0, 0.0] }, /* top left */
-      Vertex { xyz: [1.0, 1.0, 0.0], norm: [0.0, 1.0, 0.0], uv: [0.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [1.0, 1.0, 1.0], norm: [0.0, 1.0, 0.0], uv: [0.0, 0.0] }, /* top right */
+      Vertex { xyz: [0.0, 1.0, 0.0], norm: [0.0, 1.0, 0.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [0.0, 1.0, 1.0], norm: [0.0, 1.0, 0.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [1.0, 1.0, 0.0], norm: [0.0, 1.0, 0.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [1.0, 1.0, 1.0], norm: [0.0, 1.0, 0.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
       // Face 3 (back)
-      Vertex { xyz: [0.0, 0.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [0.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [0.0, 1.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [0.0, 0.0] }, /* top left */
-      Vertex { xyz: [1.0, 0.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [1.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [1.0, 1.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [1.0, 0.0] }, /* top right */
+      Vertex { xyz: [0.0, 0.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [0.0, 1.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [1.0, 0.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [1.0, 1.0, 1.0], norm: [0.0, 0.0, 1.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
       // Face 4 (bottom)
-      Vertex { xyz: [0.0, 0.0, 0.0], norm: [0.0, -1.0, 0.0], uv: [1.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [0.0, 0.0, 1.0], norm: [0.0, -1.0, 0.0], uv: [1.0, 0.0] }, /* top left */
-      Vertex { xyz: [1.0, 0.0, 0.0], norm: [0.0, -1.0, 0.0], uv: [0.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [1.0, 0.0, 1.0], norm: [0.0, -1.0, 0.0], uv: [0.0, 0.0] }, /* top right */
+      Vertex { xyz: [0.0, 0.0, 0.0], norm: [0.0, -1.0, 0.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [0.0, 0.0, 1.0], norm: [0.0, -1.0, 0.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [1.0, 0.0, 0.0], norm: [0.0, -1.0, 0.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [1.0, 0.0, 1.0], norm: [0.0, -1.0, 0.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
       // Face 5 (left)
-      Vertex { xyz: [0.0, 0.0, 1.0], norm: [-1.0, 0.0, 0.0], uv: [1.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [0.0, 1.0, 1.0], norm: [-1.0, 0.0, 0.0], uv: [1.0, 0.0] }, /* top left */
-      Vertex { xyz: [0.0, 0.0, 0.0], norm: [-1.0, 0.0, 0.0], uv: [0.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [0.0, 1.0, 0.0], norm: [-1.0, 0.0, 0.0], uv: [0.0, 0.0] }, /* top right */
+      Vertex { xyz: [0.0, 0.0, 1.0], norm: [-1.0, 0.0, 0.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [0.0, 1.0, 1.0], norm: [-1.0, 0.0, 0.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [0.0, 0.0, 0.0], norm: [-1.0, 0.0, 0.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [0.0, 1.0, 0.0], norm: [-1.0, 0.0, 0.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
       // Face 6 (right)
-      Vertex { xyz: [1.0, 0.0, 0.0], norm: [1.0, 0.0, 0.0], uv: [1.0, 1.0] }, /* bottom left */
-      Vertex { xyz: [1.0, 1.0, 0.0], norm: [1.0, 0.0, 0.0], uv: [1.0, 0.0] }, /* top left */
-      Vertex { xyz: [1.0, 0.0, 1.0], norm: [1.0, 0.0, 0.0], uv: [0.0, 1.0] }, /* bottom right */
-      Vertex { xyz: [1.0, 1.0, 1.0], norm: [1.0, 0.0, 0.0], uv: [0.0, 0.0] }, /* top right */
+      Vertex { xyz: [1.0, 0.0, 0.0], norm: [1.0, 0.0, 0.0], uv: [1.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom left */
+      Vertex { xyz: [1.0, 1.0, 0.0], norm: [1.0, 0.0, 0.0], uv: [1.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top left */
+      Vertex { xyz: [1.0, 0.0, 1.0], norm: [1.0, 0.0, 0.0], uv: [0.0, 1.0], light: VoxelLight::UNLIT, color: WHITE }, /* bottom right */
+      Vertex { xyz: [1.0, 1.0, 1.0], norm: [1.0, 0.0, 0.0], uv: [0.0, 0.0], light: VoxelLight::UNLIT, color: WHITE }, /* top right */
     );
 
     #[cfg_attr(rustfmt, rustfmt_skip)]
-    let indices: Vec::<u32> = vec!(
+    let indices: Vec::<u16> = vec!(
         0,  1,  2,  2,  1,  3, // front
         4,  5,  6,  7,  6,  5, // top
         10,  9,  8,  9, 10, 11, // back
@@ -168,6 +162,6 @@ fn block_mesh() -> MeshData {
 
     MeshBuilder::new()
         .with_vertices(vertices)
-        .with_indices(Indices::U32(indices.into()))
+        .with_indices(Indices::U16(indices.into()))
         .into()
 }
@@ -0,0 +1,427 @@
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader, ThreadPool},
+    core::{
+        math::{convert, distance_squared, Matrix4, Point3},
+        Transform,
+    },
+    ecs::{
+        prelude::{Entities, Entity, Join, Read, ReadExpect, ReadStorage, System, Write, WriteExpect, WriteStorage},
+    },
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        types::Texture,
+        ImageFormat,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::render_cache::{MaterialCache, TextureCache};
+use crate::render_chunk::{build_chunk_mesh, ChunkMeshSection, ChunkNeighbourhood, LOD_LEVELS, NEIGHBOURS};
+use crate::render_material::{CompositeMaterial, Material, MaterialDefaults};
+use crate::render_mesh::{CompositeMesh, Mesh, MeshUpdates};
+use crate::render_visibility::{Frustum, Visibility};
+use crate::world::block::BlockRegistry;
+use crate::world::chunk::{ChunkPos, CHUNK_SIZE};
+use crate::world::voxel_world::VoxelWorld;
+
+/// Texture and material cache ids of chunk textures start here, below are the single voxel ones.
+pub const CHUNK_CACHE_ID_OFFSET: u32 = 1 << 16;
+
+/// Mesh jobs running on the thread pool at once, the rest wait in the queue.
+const MAX_JOBS_IN_FLIGHT: usize = 8;
+
+/// Resource with the state of the chunk meshing queue.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMeshMetrics {
+    /// Chunks waiting for a mesh job.
+    pub queued: usize,
+    /// Mesh jobs running on the thread pool.
+    pub in_flight: usize,
+    /// Meshes built since the start.
+    pub meshed: u64,
+    /// Jobs dropped because their chunk changed or unloaded before they finished.
+    pub cancelled: u64,
+    /// Time spent building the last mesh, and a moving average over the recent ones.
+    pub last_mesh_time: Duration,
+    pub average_mesh_time: Duration,
+}
+
+impl ChunkMeshMetrics {
+    fn record(&mut self, time: Duration) {
+        self.meshed += 1;
+        self.last_mesh_time = time;
+        self.average_mesh_time = if self.meshed == 1 { time } else { (self.average_mesh_time * 7 + time) / 8 };
+    }
+}
+
+/// Resource with the camera distances, in blocks, at which chunks switch to coarser meshes, see `build_chunk_mesh`.
+///
+/// Coarse meshes have far fewer faces, which is what makes a `StreamingSettings::view_radius` of several hundred
+/// blocks affordable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodSettings {
+    /// Distance from which each level past the first is used.
+    pub distances: [f32; LOD_LEVELS as usize - 1],
+    /// Distance past a threshold before a chunk switches level, so that one sitting near it does not keep being
+    /// meshed again.
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        // All within the 256 blocks of the default `StreamingSettings::view_radius`, so that every level is used.
+        LodSettings {
+            distances: [64.0, 128.0, 192.0],
+            hysteresis: 16.0,
+        }
+    }
+}
+
+impl LodSettings {
+    /// Level of a chunk at this distance, staying at its `current` level while within `hysteresis` of a threshold.
+    pub fn level(&self, current: Option<u8>, distance: f32) -> u8 {
+        let above = |offset: f32| self.distances.iter().filter(|threshold| distance >= **threshold + offset).count();
+        match current {
+            Some(current) => current.max(above(self.hysteresis) as u8).min(above(-self.hysteresis) as u8),
+            None => above(0.0) as u8,
+        }
+    }
+}
+
+// region - Queue
+
+/// Chunk waiting for a mesh job.
+#[derive(Debug, Clone, Copy)]
+struct QueuedChunk {
+    /// Changed by a block edit, meshed before anything else.
+    edited: bool,
+}
+
+/// Mesh job running on the thread pool.
+struct MeshJob {
+    version: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Result of a `MeshJob`, `sections` is `None` when it was cancelled before it started.
+struct MeshedChunk {
+    pos: ChunkPos,
+    version: u64,
+    sections: Option<Vec<ChunkMeshSection>>,
+    time: Duration,
+}
+
+/// Camera position and view used to order the queue.
+struct Viewpoint {
+    position: Point3<f32>,
+    frustum: Frustum,
+}
+
+impl Viewpoint {
+    fn center(pos: ChunkPos) -> Point3<f32> {
+        let half = CHUNK_SIZE as f32 * 0.5;
+        let origin = pos.origin();
+        Point3::new(origin[0] as f32 + half, origin[1] as f32 + half, origin[2] as f32 + half)
+    }
+
+    /// Distance to the center of the chunk, for the first level of chunks not drawn yet.
+    fn distance(&self, pos: ChunkPos) -> f32 {
+        distance_squared(&Self::center(pos), &self.position).sqrt()
+    }
+
+    /// Smaller is meshed first: edits, then chunks in view, each nearest first.
+    fn priority(&self, pos: ChunkPos, queued: QueuedChunk) -> (bool, bool, u64) {
+        let center = Self::center(pos);
+        let half = CHUNK_SIZE as f32 * 0.5;
+        let visible = self.frustum.check_sphere(&center, half * 3.0_f32.sqrt());
+        (!queued.edited, !visible, distance_squared(&center, &self.position) as u64)
+    }
+}
+
+// endregion
+
+/// Rebuilds the meshes of the dirty chunks of the `VoxelWorld`, one entity per chunk, and deletes the entities of
+/// unloaded chunks.
+///
+/// Dirty chunks wait in a queue ordered by `Viewpoint::priority`, a few are meshed at a time on the `ThreadPool`
+/// from a `ChunkNeighbourhood` copy and picked up on a later frame. A chunk changing again while its job runs
+/// cancels that job and goes back in the queue.
+///
+/// Chunks are meshed at the level of detail of their distance to the camera, see `LodSettings`, and meshed again
+/// when they move to another level, along with the neighbours whose border faces depend on it.
+pub struct ChunkMeshSystem {
+    chunks: HashMap<ChunkPos, Entity>,
+    /// Level of detail of the mesh of each chunk, or of its next mesh when queued.
+    lods: HashMap<ChunkPos, u8>,
+    queue: HashMap<ChunkPos, QueuedChunk>,
+    jobs: HashMap<ChunkPos, MeshJob>,
+    /// Jobs started whose result was not received yet, cancelled ones included.
+    running: usize,
+    next_version: u64,
+    sender: Sender<MeshedChunk>,
+    receiver: Receiver<MeshedChunk>,
+}
+
+impl Default for ChunkMeshSystem {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        ChunkMeshSystem {
+            chunks: HashMap::new(),
+            lods: HashMap::new(),
+            queue: HashMap::new(),
+            jobs: HashMap::new(),
+            running: 0,
+            next_version: 0,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl ChunkMeshSystem {
+    /// Cancels the running job of a chunk, if any.
+    fn cancel(&mut self, pos: ChunkPos, metrics: &mut ChunkMeshMetrics) {
+        if let Some(job) = self.jobs.remove(&pos) {
+            job.cancelled.store(true, Ordering::Relaxed);
+            metrics.cancelled += 1;
+        }
+    }
+
+    /// Queues the chunks whose distance to the camera, as sorted by `VisibilitySortingSystem`, moved them to another
+    /// level of detail. Chunks out of view keep their level until they are seen again.
+    fn update_lods(&mut self, settings: &LodSettings, visibility: &Visibility) {
+        let mut changed = Vec::new();
+        for (pos, entity) in &self.chunks {
+            guard!(let Some(distance) = visibility.camera_distances.get(entity) else { continue });
+            let current = self.lods.get(pos).cloned();
+            let level = settings.level(current, *distance);
+            if current != Some(level) {
+                changed.push((*pos, level));
+            }
+        }
+        for (pos, level) in changed {
+            self.set_lod(pos, level);
+            self.queue.entry(pos).or_insert(QueuedChunk { edited: false });
+        }
+    }
+
+    /// Sets the level of detail of a chunk. Neighbours meshed or being meshed whose border faces against it change,
+    /// see `ChunkNeighbourhood::with_neighbour_levels`, are queued again. A chunk without a level is meshed as if its
+    /// neighbours were at its own.
+    fn set_lod(&mut self, pos: ChunkPos, level: u8) {
+        let previous = self.lods.insert(pos, level);
+        for &(dx, dy, dz) in NEIGHBOURS.iter() {
+            let neighbour = pos.offset(dx, dy, dz);
+            guard!(let Some(&neighbour_level) = self.lods.get(&neighbour) else { continue });
+            if !self.chunks.contains_key(&neighbour) && !self.jobs.contains_key(&neighbour) {
+                continue;
+            }
+            if (previous.unwrap_or(neighbour_level) == neighbour_level) != (level == neighbour_level) {
+                self.queue.entry(neighbour).or_insert(QueuedChunk { edited: false });
+            }
+        }
+    }
+
+    /// Levels of the neighbours of a chunk at `level`, in the order of `NEIGHBOURS`.
+    fn neighbour_levels(&self, pos: ChunkPos, level: u8) -> [u8; 6] {
+        let mut levels = [level; 6];
+        for (index, &(dx, dy, dz)) in NEIGHBOURS.iter().enumerate() {
+            if let Some(neighbour_level) = self.lods.get(&pos.offset(dx, dy, dz)) {
+                levels[index] = *neighbour_level;
+            }
+        }
+        levels
+    }
+
+    fn start_jobs(&mut self, world: &VoxelWorld, registry: &BlockRegistry, settings: &LodSettings,
+                  viewpoint: Option<&Viewpoint>, pool: &ThreadPool) {
+        let free = MAX_JOBS_IN_FLIGHT.saturating_sub(self.running);
+        if free == 0 || self.queue.is_empty() {
+            return;
+        }
+        let mut order: Vec<(ChunkPos, QueuedChunk)> = self.queue.iter().map(|(pos, queued)| (*pos, *queued)).collect();
+        match viewpoint {
+            Some(viewpoint) => order.sort_by_key(|(pos, queued)| viewpoint.priority(*pos, *queued)),
+            None => order.sort_by_key(|(_, queued)| !queued.edited),
+        }
+
+        let registry = Arc::new(registry.clone());
+        for (pos, _) in order.into_iter().take(free) {
+            self.queue.remove(&pos);
+            guard!(let Some(neighbourhood) = ChunkNeighbourhood::capture(world, pos) else { continue });
+            let level = match self.lods.get(&pos) {
+                Some(level) => *level,
+                None => {
+                    let level = viewpoint.map_or(0, |viewpoint| settings.level(None, viewpoint.distance(pos)));
+                    self.set_lod(pos, level);
+                    level
+                }
+            };
+            let neighbourhood = neighbourhood.with_neighbour_levels(self.neighbour_levels(pos, level));
+            self.next_version += 1;
+            let version = self.next_version;
+            let cancelled = Arc::new(AtomicBool::new(false));
+            self.jobs.insert(pos, MeshJob { version, cancelled: cancelled.clone() });
+            self.running += 1;
+
+            let sender = self.sender.clone();
+            let registry = registry.clone();
+            pool.spawn(move || {
+                let start = Instant::now();
+                let sections = if cancelled.load(Ordering::Relaxed) {
+                    None
+                } else {
+                    Some(build_chunk_mesh(&neighbourhood, &registry, level))
+                };
+                sender.send(MeshedChunk { pos, version, sections, time: start.elapsed() }).ok();
+            });
+        }
+    }
+}
+
+impl<'a> System<'a> for ChunkMeshSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, VoxelWorld>,
+        Read<'a, BlockRegistry>,
+        Write<'a, ChunkMeshMetrics>,
+        Read<'a, LodSettings>,
+        Read<'a, Visibility>,
+        ReadExpect<'a, Arc<ThreadPool>>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Mesh>>,
+        Write<'a, MeshUpdates>,
+        Read<'a, AssetStorage<Texture>>,
+        Write<'a, AssetStorage<Material>>,
+        ReadExpect<'a, MaterialDefaults>,
+        WriteExpect<'a, TextureCache>,
+        WriteExpect<'a, MaterialCache>,
+        WriteStorage<'a, CompositeMesh>,
+        WriteStorage<'a, CompositeMaterial>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            entities, mut world, registry, mut metrics, lod_settings, visibility, pool, active_camera, cameras, loader,
+            mesh_storage, mut mesh_updates, texture_storage, mut material_storage, material_defaults,
+            mut texture_cache, mut material_cache, mut meshes, mut materials, mut transforms,
+        ) = data;
+
+        // Dropping the components releases the mesh handles.
+        for pos in world.take_removed() {
+            self.queue.remove(&pos);
+            self.cancel(pos, &mut metrics);
+            self.lods.remove(&pos);
+            if let Some(entity) = self.chunks.remove(&pos) {
+                entities.delete(entity).ok();
+            }
+        }
+
+        let edited = world.take_edited();
+        for pos in world.take_dirty() {
+            self.cancel(pos, &mut metrics);
+            self.queue.entry(pos).or_insert(QueuedChunk { edited: false });
+        }
+        for pos in edited {
+            self.queue.insert(pos, QueuedChunk { edited: true });
+        }
+
+        while let Ok(MeshedChunk { pos, version, sections, time }) = self.receiver.try_recv() {
+            self.running -= 1;
+            if self.jobs.get(&pos).map(|job| job.version) != Some(version) {
+                continue;
+            }
+            self.jobs.remove(&pos);
+            guard!(let Some(sections) = sections else { continue });
+            metrics.record(time);
+            if sections.is_empty() {
+                if let Some(entity) = self.chunks.remove(&pos) {
+                    entities.delete(entity).ok();
+                }
+                continue;
+            }
+
+            let mut section_meshes = Vec::with_capacity(sections.len());
+            let mut components = Vec::with_capacity(sections.len());
+            for section in sections {
+                let id = CHUNK_CACHE_ID_OFFSET + section.texture;
+                let material = material_cache.cached(id).unwrap_or_else(|| {
+                    let texture: Handle<Texture> = texture_cache.cached(id).unwrap_or_else(|| {
+                        let texture = loader.load(
+                            format!("texture/{}.png", registry.texture_name(section.texture)),
+                            ImageFormat::default(),
+                            (),
+                            &texture_storage,
+                        );
+                        texture_cache.cache(texture.clone(), id);
+                        texture
+                    });
+                    let material = material_storage.insert(Material {
+                        diffuse: texture,
+                        ..material_defaults.0.clone()
+                    });
+                    material_cache.cache(material.clone(), id);
+                    material
+                });
+                section_meshes.push(section.mesh);
+                components.push(material);
+            }
+
+            let entity = *self.chunks.entry(pos).or_insert_with(|| entities.create());
+            // Same materials as the current mesh, its elements are rewritten in place and keep their handles.
+            let current = match (meshes.get(entity), materials.get(entity)) {
+                (Some(mesh), Some(material)) if material.components == components => Some(mesh.elements.clone()),
+                _ => None,
+            };
+            let elements = match current {
+                Some(elements) if elements.len() == section_meshes.len() => {
+                    for (element, mesh) in elements.iter().zip(section_meshes) {
+                        mesh_updates.update(element.clone(), mesh);
+                    }
+                    elements
+                }
+                _ => section_meshes
+                    .into_iter()
+                    .map(|mesh| loader.load_from_data(mesh, (), &mesh_storage))
+                    .collect(),
+            };
+            let origin = pos.origin();
+            let mut transform = Transform::default();
+            transform.set_translation_xyz(origin[0] as f32, origin[1] as f32, origin[2] as f32);
+
+            // Bounds follow from the meshes, see `MeshBoundsSystem`.
+            meshes.insert(entity, CompositeMesh { elements }).ok();
+            materials.insert(entity, CompositeMaterial { components }).ok();
+            transforms.insert(entity, transform).ok();
+        }
+
+        let viewpoint = {
+            let mut camera_join = (&cameras, &transforms).join();
+            active_camera
+                .entity
+                .and_then(|entity| camera_join.get(entity, &entities))
+                .or_else(|| camera_join.next())
+                .map(|(camera, transform)| Viewpoint {
+                    position: transform.global_matrix().transform_point(&Point3::origin()),
+                    frustum: Frustum::new(
+                        convert::<_, Matrix4<f32>>(*camera.as_matrix())
+                            * transform.global_matrix().try_inverse().unwrap_or_else(Matrix4::identity),
+                    ),
+                })
+        };
+        self.update_lods(&lod_settings, &visibility);
+        self.start_jobs(&world, &registry, &lod_settings, viewpoint.as_ref(), &pool);
+
+        metrics.queued = self.queue.len();
+        metrics.in_flight = self.running;
+    }
+}
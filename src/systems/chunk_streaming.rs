@@ -0,0 +1,260 @@
+use amethyst::{
+    assets::ThreadPool,
+    core::Transform,
+    ecs::prelude::{Join, Read, ReadExpect, ReadStorage, System, Write},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use crate::bundles::camera_control_bundle::MouseControlTag;
+use crate::world::block::BlockRegistry;
+use crate::world::chunk::{Chunk, ChunkPos};
+use crate::world::region::RegionPos;
+use crate::world::save::WorldSave;
+use crate::world::voxel_world::VoxelWorld;
+use crate::worldgen::generator::ActiveGenerator;
+
+/// Resource configuring how much of the world is kept loaded around the camera.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamingSettings {
+    /// Horizontal distance in chunks up to which chunks are loaded.
+    pub view_radius: i32,
+    /// Chunks loaded above and below the camera chunk.
+    pub vertical_radius: i32,
+    /// Extra chunks beyond the view radius before a chunk is unloaded, avoids reloading on the border.
+    pub unload_margin: i32,
+    /// Generation jobs running on the thread pool at once.
+    pub max_pending: usize,
+    /// Generated chunks lit and added to the world per frame.
+    pub max_inserts_per_frame: usize,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        StreamingSettings {
+            view_radius: 16,
+            vertical_radius: 3,
+            unload_margin: 2,
+            max_pending: 16,
+            max_inserts_per_frame: 4,
+        }
+    }
+}
+
+impl StreamingSettings {
+    fn in_range(&self, center: ChunkPos, pos: ChunkPos, margin: i32) -> bool {
+        let (dx, dz) = (pos.x - center.x, pos.z - center.z);
+        let radius = self.view_radius + margin;
+        dx * dx + dz * dz <= radius * radius && (pos.y - center.y).abs() <= self.vertical_radius + margin
+    }
+}
+
+/// Resource with the modified chunks unloaded by the streaming and not written to the `WorldSave` yet.
+#[derive(Debug, Default)]
+pub struct UnsavedChunks {
+    chunks: HashMap<ChunkPos, Chunk>,
+    /// Number of the chunks per region.
+    regions: HashMap<RegionPos, usize>,
+}
+
+impl UnsavedChunks {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> {
+        self.chunks.iter().map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    /// Whether a chunk of the region of `pos` waits to be written, the region file on disk is stale until then.
+    pub fn blocks_region(&self, pos: ChunkPos) -> bool {
+        self.regions.contains_key(&RegionPos::of_chunk(pos))
+    }
+
+    fn insert(&mut self, pos: ChunkPos, chunk: Chunk) {
+        if self.chunks.insert(pos, chunk).is_none() {
+            *self.regions.entry(RegionPos::of_chunk(pos)).or_insert(0) += 1;
+        }
+    }
+
+    fn remove(&mut self, pos: ChunkPos) {
+        if self.chunks.remove(&pos).is_none() {
+            return;
+        }
+        let region = RegionPos::of_chunk(pos);
+        if let Some(count) = self.regions.get_mut(&region) {
+            *count -= 1;
+            if *count == 0 {
+                self.regions.remove(&region);
+            }
+        }
+    }
+}
+
+/// Loads the chunks around the `MouseControlTag` camera and unloads the distant ones.
+///
+/// Chunks come from the `WorldSave` when they were saved before, from the `ActiveGenerator` otherwise. Both run on
+/// the `ThreadPool`, finished chunks are added to the `VoxelWorld` a few per frame, nearest first, so moving around
+/// never waits on the disk or the generator. Modified chunks are kept in `UnsavedChunks` when they are unloaded and
+/// written on the `ThreadPool`, no chunk of their region is loaded again before the write is done.
+pub struct ChunkStreamingSystem {
+    center: Option<ChunkPos>,
+    /// Chunks in view around `center`, nearest first.
+    wanted: Vec<ChunkPos>,
+    pending: HashSet<ChunkPos>,
+    sender: Sender<(ChunkPos, Chunk)>,
+    receiver: Receiver<(ChunkPos, Chunk)>,
+    /// Whether a save job runs, one at a time so a region is never written by two jobs.
+    saving: bool,
+    saved_sender: Sender<Vec<ChunkPos>>,
+    saved_receiver: Receiver<Vec<ChunkPos>>,
+}
+
+impl Default for ChunkStreamingSystem {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        let (saved_sender, saved_receiver) = channel();
+        ChunkStreamingSystem {
+            center: None,
+            wanted: Vec::new(),
+            pending: HashSet::new(),
+            sender,
+            receiver,
+            saving: false,
+            saved_sender,
+            saved_receiver,
+        }
+    }
+}
+
+impl ChunkStreamingSystem {
+    fn recenter(&mut self, center: ChunkPos, settings: &StreamingSettings, world: &mut VoxelWorld,
+                save: Option<&WorldSave>, unsaved: &mut UnsavedChunks, registry: &BlockRegistry) {
+        self.center = Some(center);
+
+        let (radius, vertical) = (settings.view_radius, settings.vertical_radius);
+        self.wanted.clear();
+        for y in center.y - vertical..=center.y + vertical {
+            for x in center.x - radius..=center.x + radius {
+                for z in center.z - radius..=center.z + radius {
+                    let pos = ChunkPos::new(x, y, z);
+                    if settings.in_range(center, pos, 0) {
+                        self.wanted.push(pos);
+                    }
+                }
+            }
+        }
+        // Nearest first, top down at equal distance so the sky light comes from above.
+        self.wanted.sort_by_key(|pos| {
+            let (dx, dy, dz) = (pos.x - center.x, pos.y - center.y, pos.z - center.z);
+            (dx * dx + dy * dy + dz * dz, -pos.y)
+        });
+        world.set_expected(self.wanted.iter().cloned(), registry);
+
+        let distant: Vec<ChunkPos> = world
+            .chunk_positions()
+            .filter(|pos| !settings.in_range(center, **pos, settings.unload_margin))
+            .cloned()
+            .collect();
+        for pos in distant {
+            let is_modified = world.is_modified(pos);
+            if let Some(chunk) = world.remove_chunk(pos) {
+                if is_modified && save.is_some() {
+                    unsaved.insert(pos, chunk);
+                }
+            }
+        }
+    }
+
+    /// Forgets the chunks written by the last save job and starts the next one.
+    fn save_unloaded(&mut self, save: Option<&WorldSave>, unsaved: &mut UnsavedChunks, registry: &BlockRegistry,
+                     pool: &ThreadPool) {
+        while let Ok(saved) = self.saved_receiver.try_recv() {
+            self.saving = false;
+            for pos in saved {
+                unsaved.remove(pos);
+            }
+        }
+        guard!(let Some(save) = save else { return });
+        if self.saving || unsaved.is_empty() {
+            return;
+        }
+
+        self.saving = true;
+        let chunks: Vec<(ChunkPos, Chunk)> = unsaved.chunks.iter().map(|(pos, chunk)| (*pos, chunk.clone())).collect();
+        let (save, registry, sender) = (save.clone(), registry.clone(), self.saved_sender.clone());
+        pool.spawn(move || {
+            if let Err(error) = save.save_chunks(chunks.iter().map(|(pos, chunk)| (*pos, chunk)), &registry) {
+                log::error!("Failed to save unloaded chunks: {}", error);
+            }
+            sender.send(chunks.into_iter().map(|(pos, _)| pos).collect()).ok();
+        });
+    }
+}
+
+impl<'a> System<'a> for ChunkStreamingSystem {
+    type SystemData = (
+        Read<'a, StreamingSettings>,
+        Option<Read<'a, ActiveGenerator>>,
+        Option<Read<'a, WorldSave>>,
+        Write<'a, UnsavedChunks>,
+        Write<'a, VoxelWorld>,
+        Read<'a, BlockRegistry>,
+        ReadExpect<'a, Arc<ThreadPool>>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, MouseControlTag>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (settings, generator, save, mut unsaved, mut world, registry, pool, transforms, tags) = data;
+        guard!(let Some(generator) = generator else { return });
+        guard!(let Some((transform, _)) = (&transforms, &tags).join().next() else { return });
+        let camera = transform.translation();
+        let center = ChunkPos::of_block([camera.x.floor() as i32, camera.y.floor() as i32, camera.z.floor() as i32]);
+        if self.center != Some(center) {
+            self.recenter(center, &settings, &mut world, save.as_ref().map(|save| &**save), &mut unsaved, &registry);
+        }
+        self.save_unloaded(save.as_ref().map(|save| &**save), &mut unsaved, &registry, &pool);
+
+        let mut inserted = 0;
+        while inserted < settings.max_inserts_per_frame {
+            guard!(let Ok((pos, chunk)) = self.receiver.try_recv() else { break });
+            self.pending.remove(&pos);
+            if world.contains_chunk(pos) || !settings.in_range(center, pos, settings.unload_margin) {
+                continue;
+            }
+            world.insert_chunk(pos, chunk, &registry);
+            inserted += 1;
+        }
+
+        let mut source: Option<(Option<WorldSave>, Arc<BlockRegistry>)> = None;
+        for &pos in &self.wanted {
+            if self.pending.len() >= settings.max_pending {
+                break;
+            }
+            if world.contains_chunk(pos) || self.pending.contains(&pos) || unsaved.blocks_region(pos) {
+                continue;
+            }
+            self.pending.insert(pos);
+            let (job_save, job_registry) = source
+                .get_or_insert_with(|| (save.as_ref().map(|save| (**save).clone()), Arc::new(registry.clone())))
+                .clone();
+            let generator = generator.0.clone();
+            let sender = self.sender.clone();
+            pool.spawn(move || {
+                let saved = job_save.and_then(|save| match save.load_chunk(pos, &job_registry) {
+                    Ok(chunk) => chunk,
+                    Err(error) => {
+                        log::error!("Failed to load chunk {:?}, generating it again: {}", pos, error);
+                        None
+                    }
+                });
+                let chunk = saved.unwrap_or_else(|| generator.generate_chunk(pos));
+                sender.send((pos, chunk)).ok();
+            });
+        }
+    }
+}
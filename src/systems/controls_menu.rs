@@ -0,0 +1,530 @@
+//! In-game controls menu: lists bindings, captures new input for them and tunes the camera.
+//!
+//! `F1` opens and closes the menu, arrows navigate and adjust values, `Enter` starts capturing input for
+//! the selected binding and `Escape` cancels a capture. Changes are written to the user config on close, mouse
+//! wheel speed changes once the wheel rests, and whatever is left unsaved on exit.
+use amethyst::{
+    config::Config,
+    core::{SystemDesc, Time},
+    ecs::prelude::{Entity, Read, System, SystemData, World, Write, WriteStorage},
+    input::{Axis, Bindings, Button, ControllerAxis, InputEvent, InputHandler, StringBindings},
+    shrev::{EventChannel, ReaderId},
+    ui::{UiFinder, UiText},
+    winit::VirtualKeyCode,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bundles::camera_control_bundle::CameraControlSettings;
+
+/// `UiTransform` id of the text entity the menu is rendered into.
+pub const CONTROLS_MENU_UI_ID: &str = "CONTROLS";
+
+const SENSITIVITY_STEP: f32 = 1.1;
+const SPEED_STEP: f32 = 1.1;
+
+/// Stick deflection required before a controller axis is captured.
+const STICK_CAPTURE_THRESHOLD: f32 = 0.5;
+
+/// Seconds the mouse wheel has to rest before a speed change made outside the menu is saved.
+const SPEED_SAVE_DELAY: f64 = 1.0;
+
+/// Files the controls menu restores defaults from and persists the user configuration to.
+#[derive(Debug, Clone)]
+pub struct ControlsConfigPaths {
+    /// User bindings, written by the menu outside of the tracked config files.
+    pub bindings: PathBuf,
+    /// Bindings of the installation, loaded when the user has none.
+    pub installed_bindings: PathBuf,
+    /// Bindings shipped with the game, used by "reset to defaults" and when no other bindings exist.
+    pub default_bindings: PathBuf,
+    /// User `CameraControlSettings`.
+    pub settings: PathBuf,
+}
+
+impl ControlsConfigPaths {
+    /// Bindings `InputBundle` loads on start: the user ones, then the installed ones, then the defaults.
+    pub fn bindings_to_load(&self) -> &Path {
+        if self.bindings.exists() {
+            &self.bindings
+        } else if self.installed_bindings.exists() {
+            &self.installed_bindings
+        } else {
+            &self.default_bindings
+        }
+    }
+}
+
+/// Which part of an axis binding a menu entry edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AxisPart {
+    Positive,
+    Negative,
+    Stick,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MenuEntry {
+    /// `slot` indexes into the members of an `Axis::Multiple`, it is always `0` for other axes.
+    Axis { name: String, slot: usize, part: AxisPart },
+    Action { name: String },
+    Sensitivity,
+    Speed,
+    ResetDefaults,
+}
+
+/// Input captured for the selected binding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Captured {
+    Button(Button),
+    Stick {
+        controller_id: u32,
+        axis: ControllerAxis,
+        invert: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct ControlsMenuSystemDesc {
+    paths: ControlsConfigPaths,
+}
+
+impl ControlsMenuSystemDesc {
+    pub fn new(paths: ControlsConfigPaths) -> Self {
+        ControlsMenuSystemDesc { paths }
+    }
+}
+
+impl<'a, 'b> SystemDesc<'a, 'b, ControlsMenuSystem> for ControlsMenuSystemDesc {
+    fn build(self, world: &mut World) -> ControlsMenuSystem {
+        <ControlsMenuSystem as System<'_>>::SystemData::setup(world);
+
+        let event_reader = world
+            .fetch_mut::<EventChannel<InputEvent<StringBindings>>>()
+            .register_reader();
+        let saved_speed = world.fetch::<CameraControlSettings>().speed;
+
+        ControlsMenuSystem {
+            paths: self.paths,
+            open: false,
+            selected: 0,
+            capturing: false,
+            dirty: false,
+            saved_speed,
+            save_at: None,
+            message: String::new(),
+            menu_display: None,
+            event_reader,
+        }
+    }
+}
+
+/// The system that runs the controls menu.
+#[derive(Debug)]
+pub struct ControlsMenuSystem {
+    paths: ControlsConfigPaths,
+    open: bool,
+    selected: usize,
+    capturing: bool,
+    dirty: bool,
+    /// Move speed last seen, the mouse wheel changes it outside the menu.
+    saved_speed: f32,
+    /// Real time in seconds at which the pending wheel speed change is saved.
+    save_at: Option<f64>,
+    message: String,
+    menu_display: Option<Entity>,
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+
+impl<'a> System<'a> for ControlsMenuSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<StringBindings>>>,
+        Write<'a, InputHandler<StringBindings>>,
+        Write<'a, CameraControlSettings>,
+        WriteStorage<'a, UiText>,
+        UiFinder<'a>,
+        Read<'a, Time>,
+    );
+
+    fn run(&mut self, (events, mut input, mut settings, mut ui_text, finder, time): Self::SystemData) {
+        let events: Vec<_> = events.read(&mut self.event_reader).cloned().collect();
+        let mut redraw = false;
+
+        for event in events {
+            if self.capturing {
+                if let InputEvent::KeyPressed {
+                    key_code: VirtualKeyCode::Escape,
+                    ..
+                } = event
+                {
+                    self.capturing = false;
+                    self.message = String::from("Capture cancelled");
+                    redraw = true;
+                } else if let Some(captured) = captured_input(&event) {
+                    self.capturing = false;
+                    self.apply_capture(&mut input.bindings, captured);
+                    redraw = true;
+                }
+                continue;
+            }
+
+            guard!(let InputEvent::KeyPressed { key_code, .. } = event else { continue });
+            if key_code == VirtualKeyCode::F1 {
+                self.toggle(&input.bindings, &settings);
+                redraw = true;
+                continue;
+            }
+            if !self.open {
+                continue;
+            }
+
+            let entries = menu_entries(&input.bindings);
+            redraw = true;
+            match key_code {
+                VirtualKeyCode::Up => {
+                    self.selected = (self.selected + entries.len() - 1) % entries.len();
+                }
+                VirtualKeyCode::Down => {
+                    self.selected = (self.selected + 1) % entries.len();
+                }
+                VirtualKeyCode::Left => self.adjust(&entries, &mut settings, -1.0),
+                VirtualKeyCode::Right => self.adjust(&entries, &mut settings, 1.0),
+                VirtualKeyCode::Return => {
+                    self.activate(&entries, &mut input.bindings, &mut settings);
+                    // The `ButtonPressed` of this same key press follows in the batch, it must not be captured.
+                    if self.capturing {
+                        break;
+                    }
+                }
+                _ => redraw = false,
+            }
+        }
+
+        // Wheel speed changes are saved once the wheel rests, those made while the menu is open when it closes.
+        let now = time.absolute_real_time_seconds();
+        if settings.speed != self.saved_speed {
+            self.saved_speed = settings.speed;
+            self.dirty = true;
+            if self.open {
+                redraw = true;
+            } else {
+                self.save_at = Some(now + SPEED_SAVE_DELAY);
+            }
+        }
+        if !self.open && self.save_at.map_or(false, |at| now >= at) {
+            self.save(&input.bindings, &settings);
+        }
+
+        if !redraw {
+            return;
+        }
+        if self.menu_display.is_none() {
+            self.menu_display = finder.find(CONTROLS_MENU_UI_ID);
+        }
+        if let Some(text) = self.menu_display.and_then(|e| ui_text.get_mut(e)) {
+            text.text = if self.open {
+                self.render(&input.bindings, &settings)
+            } else {
+                String::new()
+            };
+        }
+    }
+
+    fn dispose(mut self, world: &mut World) {
+        if self.dirty {
+            let input = world.fetch::<InputHandler<StringBindings>>();
+            self.save(&input.bindings, &world.fetch::<CameraControlSettings>());
+        }
+    }
+}
+
+impl ControlsMenuSystem {
+    fn toggle(&mut self, bindings: &Bindings<StringBindings>, settings: &CameraControlSettings) {
+        self.open = !self.open;
+        self.message.clear();
+        if !self.open && self.dirty {
+            self.save(bindings, settings);
+        }
+    }
+
+    fn selected_entry(&self, entries: &[MenuEntry]) -> MenuEntry {
+        entries[self.selected.min(entries.len() - 1)].clone()
+    }
+
+    fn adjust(&mut self, entries: &[MenuEntry], settings: &mut CameraControlSettings, direction: f32) {
+        match self.selected_entry(entries) {
+            MenuEntry::Sensitivity => {
+                let factor = SENSITIVITY_STEP.powf(direction);
+                settings.sensitivity_x *= factor;
+                settings.sensitivity_y *= factor;
+            }
+            MenuEntry::Speed => settings.speed *= SPEED_STEP.powf(direction),
+            _ => return,
+        }
+        self.dirty = true;
+    }
+
+    fn activate(
+        &mut self, entries: &[MenuEntry], bindings: &mut Bindings<StringBindings>,
+        settings: &mut CameraControlSettings,
+    ) {
+        match self.selected_entry(entries) {
+            MenuEntry::Axis { .. } | MenuEntry::Action { .. } => {
+                self.capturing = true;
+                self.message = String::from("Press the new input, Escape to cancel");
+            }
+            MenuEntry::ResetDefaults => match Bindings::<StringBindings>::load(&self.paths.default_bindings) {
+                Ok(defaults) => {
+                    *bindings = defaults;
+                    *settings = CameraControlSettings::default();
+                    self.selected = 0;
+                    self.dirty = true;
+                    self.message = String::from("Defaults restored");
+                }
+                Err(err) => self.message = format!("Failed to load defaults: {}", err),
+            },
+            MenuEntry::Sensitivity | MenuEntry::Speed => {}
+        }
+    }
+
+    fn apply_capture(&mut self, bindings: &mut Bindings<StringBindings>, captured: Captured) {
+        let entries = menu_entries(bindings);
+        let result = match self.selected_entry(&entries) {
+            MenuEntry::Axis { name, slot, part } => rebind_axis(bindings, &name, slot, part, captured),
+            MenuEntry::Action { name } => rebind_action(bindings, &name, captured),
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                self.dirty = true;
+                self.message = String::from("Binding updated");
+            }
+            Err(conflict) => self.message = format!("Conflict: {}", conflict),
+        }
+    }
+
+    fn save(&mut self, bindings: &Bindings<StringBindings>, settings: &CameraControlSettings) {
+        self.save_at = None;
+        for path in &[&self.paths.bindings, &self.paths.settings] {
+            if let Some(Err(err)) = path.parent().map(fs::create_dir_all) {
+                log::error!("Failed to create the user config directory of {:?}: {}", path, err);
+                return;
+            }
+        }
+        if let Err(err) = bindings.write(&self.paths.bindings) {
+            log::error!("Failed to save bindings to {:?}: {}", self.paths.bindings, err);
+            return;
+        }
+        if let Err(err) = settings.write(&self.paths.settings) {
+            log::error!("Failed to save camera settings to {:?}: {}", self.paths.settings, err);
+            return;
+        }
+        self.dirty = false;
+    }
+
+    fn render(&self, bindings: &Bindings<StringBindings>, settings: &CameraControlSettings) -> String {
+        let entries = menu_entries(bindings);
+        let mut text = String::from("Controls (F1 to close)\n");
+        for (i, entry) in entries.iter().enumerate() {
+            let marker = if i == self.selected {
+                if self.capturing {
+                    "?"
+                } else {
+                    ">"
+                }
+            } else {
+                " "
+            };
+            text.push_str(&format!("{} {}\n", marker, entry_label(entry, bindings, settings)));
+        }
+        text.push_str(&self.message);
+        text
+    }
+}
+
+// region - Entries
+
+fn menu_entries(bindings: &Bindings<StringBindings>) -> Vec<MenuEntry> {
+    let mut entries = Vec::new();
+
+    let mut axes: Vec<&String> = bindings.axes().collect();
+    axes.sort();
+    for name in axes {
+        guard!(let Some(axis) = bindings.axis(name) else { continue });
+        for (slot, member) in axis_slots(axis).into_iter().enumerate() {
+            let parts: &[AxisPart] = match member {
+                Axis::Emulated { .. } => &[AxisPart::Positive, AxisPart::Negative],
+                Axis::Controller { .. } => &[AxisPart::Stick],
+                _ => &[],
+            };
+            entries.extend(parts.iter().map(|&part| MenuEntry::Axis {
+                name: name.clone(),
+                slot,
+                part,
+            }));
+        }
+    }
+
+    let mut actions: Vec<&String> = bindings.actions().collect();
+    actions.sort();
+    entries.extend(actions.into_iter().map(|name| MenuEntry::Action { name: name.clone() }));
+
+    entries.push(MenuEntry::Sensitivity);
+    entries.push(MenuEntry::Speed);
+    entries.push(MenuEntry::ResetDefaults);
+    entries
+}
+
+fn entry_label(entry: &MenuEntry, bindings: &Bindings<StringBindings>, settings: &CameraControlSettings) -> String {
+    match entry {
+        MenuEntry::Axis { name, slot, part } => {
+            let member = bindings.axis(name).and_then(|axis| axis_slots(axis).into_iter().nth(*slot));
+            let value = match (member, part) {
+                (Some(Axis::Emulated { pos, .. }), AxisPart::Positive) => format!("{:?}", pos),
+                (Some(Axis::Emulated { neg, .. }), AxisPart::Negative) => format!("{:?}", neg),
+                (
+                    Some(Axis::Controller {
+                        controller_id,
+                        axis,
+                        invert,
+                        ..
+                    }),
+                    AxisPart::Stick,
+                ) => format!("Pad {} {:?}{}", controller_id, axis, if *invert { " inverted" } else { "" }),
+                _ => String::from("-"),
+            };
+            let suffix = match part {
+                AxisPart::Positive => "+",
+                AxisPart::Negative => "-",
+                AxisPart::Stick => "",
+            };
+            format!("{}{}: {}", name, suffix, value)
+        }
+        MenuEntry::Action { name } => {
+            let value = bindings
+                .action_bindings(name)
+                .next()
+                .map_or_else(|| String::from("unbound"), |combo| format!("{:?}", combo));
+            format!("{}: {}", name, value)
+        }
+        MenuEntry::Sensitivity => format!("Mouse sensitivity: {:.3} (Left/Right)", settings.sensitivity_x),
+        MenuEntry::Speed => format!("Move speed: {:.2} (Left/Right)", settings.speed),
+        MenuEntry::ResetDefaults => String::from("Reset to defaults"),
+    }
+}
+
+fn axis_slots(axis: &Axis) -> Vec<&Axis> {
+    match axis {
+        Axis::Multiple(members) => members.iter().collect(),
+        other => vec![other],
+    }
+}
+
+// endregion
+
+// region - Rebinding
+
+fn captured_input(event: &InputEvent<StringBindings>) -> Option<Captured> {
+    match *event {
+        // Every key press is reported both as a key and as a scan code, bind the former.
+        InputEvent::ButtonPressed(Button::ScanCode(_)) => None,
+        InputEvent::ButtonPressed(button) => Some(Captured::Button(button)),
+        InputEvent::ControllerAxisMoved { which, axis, value } if value.abs() > STICK_CAPTURE_THRESHOLD => {
+            Some(Captured::Stick {
+                controller_id: which,
+                axis,
+                invert: value < 0.0,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Replaces one part of the axis, restoring the previous binding when the new one conflicts.
+fn rebind_axis(
+    bindings: &mut Bindings<StringBindings>, name: &str, slot: usize, part: AxisPart, captured: Captured,
+) -> Result<(), String> {
+    let old = bindings
+        .remove_axis(name)
+        .ok_or_else(|| format!("axis {} no longer exists", name))?;
+    let new = match replace_slot(&old, slot, part, captured) {
+        Ok(new) => new,
+        Err(err) => {
+            bindings.insert_axis(name.to_owned(), old).ok();
+            return Err(err);
+        }
+    };
+    match bindings.insert_axis(name.to_owned(), new) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            bindings.insert_axis(name.to_owned(), old).ok();
+            Err(err.to_string())
+        }
+    }
+}
+
+fn replace_slot(axis: &Axis, slot: usize, part: AxisPart, captured: Captured) -> Result<Axis, String> {
+    if let Axis::Multiple(members) = axis {
+        let mut members = members.clone();
+        let member = members.get_mut(slot).ok_or_else(|| String::from("binding no longer exists"))?;
+        *member = replace_part(member, part, captured)?;
+        return Ok(Axis::Multiple(members));
+    }
+    replace_part(axis, part, captured)
+}
+
+fn replace_part(axis: &Axis, part: AxisPart, captured: Captured) -> Result<Axis, String> {
+    match (axis, part, captured) {
+        (Axis::Emulated { pos, neg }, AxisPart::Positive, Captured::Button(button)) => {
+            if button == *neg {
+                return Err(format!("{:?} already moves this axis the other way", button));
+            }
+            Ok(Axis::Emulated { pos: button, neg: *neg })
+        }
+        (Axis::Emulated { pos, neg }, AxisPart::Negative, Captured::Button(button)) => {
+            if button == *pos {
+                return Err(format!("{:?} already moves this axis the other way", button));
+            }
+            Ok(Axis::Emulated { pos: *pos, neg: button })
+        }
+        (
+            Axis::Controller { dead_zone, .. },
+            AxisPart::Stick,
+            Captured::Stick {
+                controller_id,
+                axis,
+                invert,
+            },
+        ) => Ok(Axis::Controller {
+            controller_id,
+            axis,
+            invert,
+            dead_zone: *dead_zone,
+        }),
+        (_, AxisPart::Stick, _) => Err(String::from("move a controller stick or trigger to bind it")),
+        _ => Err(String::from("press a key or button to bind it")),
+    }
+}
+
+/// Replaces the primary combination of the action, keeping any other combinations.
+fn rebind_action(bindings: &mut Bindings<StringBindings>, name: &str, captured: Captured) -> Result<(), String> {
+    guard!(let Captured::Button(button) = captured else {
+        return Err(String::from("press a key or button to bind it"));
+    });
+
+    let old: Option<Vec<Button>> = bindings.action_bindings(name).next().map(|combo| combo.to_vec());
+    if let Some(old) = &old {
+        bindings.remove_action_binding(name, old);
+    }
+    match bindings.insert_action_binding(name.to_owned(), Some(button)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            if let Some(old) = old {
+                bindings.insert_action_binding(name.to_owned(), old).ok();
+            }
+            Err(err.to_string())
+        }
+    }
+}
+
+// endregion
@@ -1,2 +1,5 @@
+pub mod chunk_mesh;
+pub mod chunk_streaming;
+pub mod controls_menu;
 mod world_controls;
 pub mod ui;
\ No newline at end of file
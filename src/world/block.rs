@@ -0,0 +1,231 @@
+//! Block types of the voxel world.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::world::light::MAX_LIGHT;
+
+// region - Block Id
+
+/// Index of a block type in the `BlockRegistry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    /// Empty space, always registered first.
+    pub const AIR: BlockId = BlockId(0);
+
+    pub fn is_air(self) -> bool {
+        self == BlockId::AIR
+    }
+}
+
+// endregion
+
+// region - Block Definition
+
+/// Side of a block a texture is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFace {
+    Top,
+    Side,
+    Bottom,
+}
+
+/// Texture names of a block, relative to `texture/` and without the `.png` extension.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockTextures {
+    pub top: String,
+    pub side: String,
+    pub bottom: String,
+    /// Drawn over the sides, tinted like the top of tinted blocks.
+    #[serde(default)]
+    pub side_overlay: Option<String>,
+}
+
+impl BlockTextures {
+    /// Same texture on every face.
+    pub fn all(name: &str) -> Self {
+        BlockTextures {
+            top: name.to_string(),
+            side: name.to_string(),
+            bottom: name.to_string(),
+            side_overlay: None,
+        }
+    }
+}
+
+/// Block type description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockDef {
+    pub name: String,
+    /// `None` for blocks that are never drawn.
+    pub textures: Option<BlockTextures>,
+    /// Opaque blocks stop light and hide the faces of their neighbours.
+    pub opaque: bool,
+    /// Block light emitted, `0` up to `MAX_LIGHT`.
+    pub emission: u8,
+    /// Top face and side overlay take the biome grass colour.
+    #[serde(default)]
+    pub tinted: bool,
+}
+
+impl BlockDef {
+    pub fn solid(name: &str, textures: BlockTextures) -> Self {
+        BlockDef {
+            name: name.to_string(),
+            textures: Some(textures),
+            opaque: true,
+            emission: 0,
+            tinted: false,
+        }
+    }
+
+    /// Alters whether the block takes the biome grass colour.
+    pub fn with_tint(mut self, tinted: bool) -> Self {
+        self.tinted = tinted;
+        self
+    }
+
+    /// Alters the light emitted by the block.
+    pub fn with_emission(mut self, emission: u8) -> Self {
+        self.emission = emission.min(MAX_LIGHT);
+        self
+    }
+}
+
+// endregion
+
+// region - Registry
+
+/// Resource with every known block type. Texture names are deduplicated into indices so the mesher can group
+/// faces by texture.
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDef>,
+    face_textures: Vec<Option<[u32; 3]>>,
+    overlay_textures: Vec<Option<u32>>,
+    textures: Vec<String>,
+    by_name: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    /// Creates a registry that only knows about air.
+    pub fn new() -> Self {
+        let mut registry = BlockRegistry {
+            blocks: Vec::new(),
+            face_textures: Vec::new(),
+            overlay_textures: Vec::new(),
+            textures: Vec::new(),
+            by_name: HashMap::new(),
+        };
+        registry.register(BlockDef {
+            name: "air".to_string(),
+            textures: None,
+            opaque: false,
+            emission: 0,
+            tinted: false,
+        });
+        registry
+    }
+
+    /// Adds a block type, replacing the previous one with the same name.
+    pub fn register(&mut self, def: BlockDef) -> BlockId {
+        let face_textures = def.textures.as_ref().map(|textures| {
+            [
+                self.texture_index(&textures.top),
+                self.texture_index(&textures.side),
+                self.texture_index(&textures.bottom),
+            ]
+        });
+        let overlay_texture = def
+            .textures
+            .as_ref()
+            .and_then(|textures| textures.side_overlay.clone())
+            .map(|overlay| self.texture_index(&overlay));
+        if let Some(&id) = self.by_name.get(&def.name) {
+            self.blocks[id.0 as usize] = def;
+            self.face_textures[id.0 as usize] = face_textures;
+            self.overlay_textures[id.0 as usize] = overlay_texture;
+            return id;
+        }
+        let id = BlockId(self.blocks.len() as u16);
+        self.by_name.insert(def.name.clone(), id);
+        self.blocks.push(def);
+        self.face_textures.push(face_textures);
+        self.overlay_textures.push(overlay_texture);
+        id
+    }
+
+    fn texture_index(&mut self, name: &str) -> u32 {
+        match self.textures.iter().position(|texture| texture == name) {
+            Some(index) => index as u32,
+            None => {
+                self.textures.push(name.to_string());
+                (self.textures.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Unknown ids resolve to air.
+    pub fn get(&self, id: BlockId) -> &BlockDef {
+        self.blocks.get(id.0 as usize).unwrap_or(&self.blocks[0])
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.by_name.get(name).cloned()
+    }
+
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        self.get(id).opaque
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Texture index of a block face, `None` for blocks that are never drawn.
+    pub fn face_texture(&self, id: BlockId, face: BlockFace) -> Option<u32> {
+        let textures = self.face_textures.get(id.0 as usize).cloned().flatten()?;
+        Some(match face {
+            BlockFace::Top => textures[0],
+            BlockFace::Side => textures[1],
+            BlockFace::Bottom => textures[2],
+        })
+    }
+
+    /// Texture index of the side overlay, if the block has one.
+    pub fn overlay_texture(&self, id: BlockId) -> Option<u32> {
+        self.overlay_textures.get(id.0 as usize).cloned().flatten()
+    }
+
+    pub fn texture_name(&self, index: u32) -> &str {
+        &self.textures[index as usize]
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let mut registry = BlockRegistry::new();
+        registry.register(BlockDef::solid("dirt", BlockTextures::all("dirt")));
+        registry.register(
+            BlockDef::solid(
+                "grass",
+                BlockTextures {
+                    top: "grass_block_top".to_string(),
+                    side: "grass_block_side".to_string(),
+                    bottom: "dirt".to_string(),
+                    side_overlay: Some("grass_block_side_overlay".to_string()),
+                },
+            )
+            .with_tint(true),
+        );
+        registry.register(BlockDef::solid("stone", BlockTextures::all("cauldron_top")));
+        registry.register(BlockDef::solid("crate", BlockTextures::all("crate")));
+        registry.register(BlockDef::solid("lantern", BlockTextures::all("crate")).with_emission(14));
+        registry.register(BlockDef::solid("log", BlockTextures::all("crate")));
+        registry.register(BlockDef::solid("leaves", BlockTextures::all("grass_block_top")).with_tint(true));
+        registry
+    }
+}
+
+// endregion
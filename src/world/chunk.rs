@@ -0,0 +1,164 @@
+//! Fixed size cubes of blocks the world is split into.
+use serde::{Deserialize, Serialize};
+
+use crate::world::block::BlockId;
+use crate::world::light::LightChannel;
+use crate::world::palette::PalettedStorage;
+
+/// Edge length of a chunk, in blocks.
+pub const CHUNK_SIZE: i32 = 16;
+/// Number of blocks in a chunk.
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// region - Position
+
+/// Position of a chunk in the chunk grid. Chunk `(0, 0, 0)` spans blocks `0..CHUNK_SIZE` on every axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl ChunkPos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        ChunkPos { x, y, z }
+    }
+
+    /// Chunk containing the block at the given world position.
+    pub fn of_block(pos: [i32; 3]) -> Self {
+        ChunkPos::new(
+            pos[0].div_euclid(CHUNK_SIZE),
+            pos[1].div_euclid(CHUNK_SIZE),
+            pos[2].div_euclid(CHUNK_SIZE),
+        )
+    }
+
+    /// World position of the lowest corner block.
+    pub fn origin(&self) -> [i32; 3] {
+        [self.x * CHUNK_SIZE, self.y * CHUNK_SIZE, self.z * CHUNK_SIZE]
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32, dz: i32) -> Self {
+        ChunkPos::new(self.x + dx, self.y + dy, self.z + dz)
+    }
+}
+
+/// Splits a world block position into its chunk and the local position inside it.
+pub fn split_block_pos(pos: [i32; 3]) -> (ChunkPos, [usize; 3]) {
+    let local = [
+        pos[0].rem_euclid(CHUNK_SIZE) as usize,
+        pos[1].rem_euclid(CHUNK_SIZE) as usize,
+        pos[2].rem_euclid(CHUNK_SIZE) as usize,
+    ];
+    (ChunkPos::of_block(pos), local)
+}
+
+// endregion
+
+// region - Chunk
+
+/// Grass colour of columns no biome was applied to.
+pub const DEFAULT_TINT: [u8; 3] = [124, 189, 107];
+
+/// Blocks and light levels of a chunk, and the grass colour of its columns. Blocks are palette compressed, light
+/// is packed per block, sky light in the high nibble and block light in the low one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    blocks: PalettedStorage,
+    light: Vec<u8>,
+    tints: Vec<[u8; 3]>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::filled(BlockId::AIR)
+    }
+}
+
+impl Chunk {
+    pub fn filled(block: BlockId) -> Self {
+        Chunk {
+            blocks: PalettedStorage::filled(CHUNK_VOLUME, block),
+            light: vec![0; CHUNK_VOLUME],
+            tints: vec![DEFAULT_TINT; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+        }
+    }
+
+    #[inline]
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        let size = CHUNK_SIZE as usize;
+        (y * size + z) * size + x
+    }
+
+    #[inline]
+    pub fn block(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.blocks.get(Chunk::index(x, y, z))
+    }
+
+    #[inline]
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: BlockId) {
+        self.blocks.set(Chunk::index(x, y, z), block);
+    }
+
+    /// Blocks in x, then z, then y order.
+    pub fn blocks(&self) -> &PalettedStorage {
+        &self.blocks
+    }
+
+    /// Chunk with its blocks replaced, light and tints kept. `None` if `blocks` does not have `CHUNK_VOLUME` cells.
+    pub fn with_blocks(mut self, blocks: PalettedStorage) -> Option<Self> {
+        if blocks.len() != CHUNK_VOLUME {
+            return None;
+        }
+        self.blocks = blocks;
+        Some(self)
+    }
+
+    /// Shrinks the block palette to the blocks still present.
+    pub fn compact(&mut self) {
+        self.blocks.compact();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.blocks.uniform() {
+            Some(block) => block.is_air(),
+            None => self.blocks.iter().all(|block| block.is_air()),
+        }
+    }
+
+    /// Grass colour of a column.
+    #[inline]
+    pub fn tint(&self, x: usize, z: usize) -> [u8; 3] {
+        self.tints[z * CHUNK_SIZE as usize + x]
+    }
+
+    #[inline]
+    pub fn set_tint(&mut self, x: usize, z: usize, tint: [u8; 3]) {
+        self.tints[z * CHUNK_SIZE as usize + x] = tint;
+    }
+
+    #[inline]
+    pub fn light(&self, x: usize, y: usize, z: usize, channel: LightChannel) -> u8 {
+        let packed = self.light[Chunk::index(x, y, z)];
+        match channel {
+            LightChannel::Sky => packed >> 4,
+            LightChannel::Block => packed & 0x0F,
+        }
+    }
+
+    #[inline]
+    pub fn set_light(&mut self, x: usize, y: usize, z: usize, channel: LightChannel, level: u8) {
+        let packed = &mut self.light[Chunk::index(x, y, z)];
+        *packed = match channel {
+            LightChannel::Sky => (*packed & 0x0F) | (level << 4),
+            LightChannel::Block => (*packed & 0xF0) | (level & 0x0F),
+        };
+    }
+
+    pub fn clear_light(&mut self) {
+        self.light.iter_mut().for_each(|packed| *packed = 0);
+    }
+}
+
+// endregion
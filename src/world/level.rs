@@ -0,0 +1,102 @@
+//! World metadata saved next to the region files as `level.ron`.
+use amethyst::core::{
+    math::{Quaternion, Unit, Vector3},
+    Transform,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::bundles::day_night_bundle::TimeOfDay;
+use crate::systems::chunk_streaming::StreamingSettings;
+
+/// File name of the level metadata in the save directory.
+pub const LEVEL_FILE: &str = "level.ron";
+
+/// Everything besides the blocks needed to continue a world where it was left.
+///
+/// ```ron
+/// (
+///     seed: 51,
+///     camera: Some((translation: (12.0, 20.0, 12.0), rotation: (0.0, 0.38, 0.0, 0.92))),
+///     time_of_day: 0.35,
+///     rules: (caves: true, structures: true, streaming: (view_radius: 16)),
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Level {
+    /// Seed the world is generated from, unsaved chunks are generated again from it.
+    pub seed: u64,
+    /// Camera placement, `None` until the world was played once.
+    pub camera: Option<CameraState>,
+    /// `TimeOfDay::time`, the rest of the cycle keeps the settings of the `DayNightBundle`.
+    pub time_of_day: f32,
+    pub rules: GameRules,
+}
+
+impl Level {
+    pub fn new(seed: u64) -> Self {
+        Level {
+            seed,
+            ..Level::default()
+        }
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level {
+            seed: 0,
+            camera: None,
+            time_of_day: TimeOfDay::default().time,
+            rules: GameRules::default(),
+        }
+    }
+}
+
+/// Position and orientation of the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub translation: [f32; 3],
+    /// Unit quaternion, as `(i, j, k, w)`.
+    pub rotation: [f32; 4],
+}
+
+impl CameraState {
+    pub fn from_transform(transform: &Transform) -> Self {
+        let translation = transform.translation();
+        let rotation = transform.rotation().quaternion().coords;
+        CameraState {
+            translation: [translation.x, translation.y, translation.z],
+            rotation: [rotation.x, rotation.y, rotation.z, rotation.w],
+        }
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        let [x, y, z, w] = self.rotation;
+        let mut transform = Transform::default();
+        transform.set_translation(Vector3::from(self.translation));
+        transform.set_rotation(Unit::new_normalize(Quaternion::new(w, x, y, z)));
+        transform
+    }
+}
+
+/// World settings chosen when it is created, kept for its whole life.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameRules {
+    /// Whether the generator carves caves.
+    pub caves: bool,
+    /// Whether the generator places trees, boulders and ruins.
+    pub structures: bool,
+    pub streaming: StreamingSettings,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        GameRules {
+            caves: true,
+            structures: true,
+            streaming: StreamingSettings::default(),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Voxel light propagation.
+//!
+//! Two channels are flood filled through non-opaque blocks, losing one level per block:
+//! * sky light enters from above at `MAX_LIGHT` and keeps it while going straight down,
+//! * block light starts at emissive blocks.
+//!
+//! Chunks above the loaded area are assumed to be open sky. Chunks still expected above, see
+//! `VoxelWorld::set_expected`, hold back the sky light until they arrive.
+use std::collections::VecDeque;
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{ChunkPos, CHUNK_SIZE};
+use crate::world::voxel_world::VoxelWorld;
+
+pub const MAX_LIGHT: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    Sky,
+    Block,
+}
+
+const NEIGHBOURS: [[i32; 3]; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const DOWN: usize = 3;
+
+#[inline]
+fn offset(pos: [i32; 3], dir: [i32; 3]) -> [i32; 3] {
+    [pos[0] + dir[0], pos[1] + dir[1], pos[2] + dir[2]]
+}
+
+// region - Updates
+
+/// Recomputes the light of a chunk, removing the light its previous state spread into the loaded neighbours and
+/// spreading the new one.
+///
+/// The chunks below are relit as long as the sky light entering them changes.
+pub fn relight_chunk(world: &mut VoxelWorld, pos: ChunkPos, registry: &BlockRegistry) {
+    let mut current = pos;
+    loop {
+        light_chunk(world, current, registry);
+        let below = current.offset(0, -1, 0);
+        if !sky_changed(world, current, below, registry) {
+            break;
+        }
+        current = below;
+    }
+}
+
+/// Updates the light around a block that changed from `previous` to `block`.
+pub fn update_block(world: &mut VoxelWorld, pos: [i32; 3], previous: BlockId, block: BlockId, registry: &BlockRegistry) {
+    let previous = registry.get(previous);
+    let current = registry.get(block);
+
+    // Block light
+    let level = world.light(pos, LightChannel::Block).unwrap_or(0);
+    let mut seeds = VecDeque::new();
+    if level > 0 && (current.opaque || previous.emission > current.emission) {
+        world.set_light(pos, LightChannel::Block, 0);
+        seeds = unpropagate(world, registry, LightChannel::Block, vec![(pos, level)].into());
+    }
+    if current.emission > world.light(pos, LightChannel::Block).unwrap_or(0) {
+        world.set_light(pos, LightChannel::Block, current.emission);
+        seeds.push_back(pos);
+    }
+    if !current.opaque {
+        seeds.extend(NEIGHBOURS.iter().map(|dir| offset(pos, *dir)));
+    }
+    propagate(world, registry, LightChannel::Block, seeds);
+
+    // Sky light
+    let level = world.light(pos, LightChannel::Sky).unwrap_or(0);
+    let mut seeds = VecDeque::new();
+    if level > 0 && current.opaque {
+        world.set_light(pos, LightChannel::Sky, 0);
+        seeds = unpropagate(world, registry, LightChannel::Sky, vec![(pos, level)].into());
+    }
+    if !current.opaque {
+        let above = offset(pos, NEIGHBOURS[2]);
+        if world.light(above, LightChannel::Sky).is_none() && !world.is_expected(ChunkPos::of_block(above)) {
+            world.set_light(pos, LightChannel::Sky, MAX_LIGHT);
+            seeds.push_back(pos);
+        }
+        seeds.extend(NEIGHBOURS.iter().map(|dir| offset(pos, *dir)));
+    }
+    propagate(world, registry, LightChannel::Sky, seeds);
+}
+
+// endregion
+
+// region - Chunk Lighting
+
+fn light_chunk(world: &mut VoxelWorld, pos: ChunkPos, registry: &BlockRegistry) {
+    let size = CHUNK_SIZE as usize;
+    let origin = pos.origin();
+    let above = match world.chunk(pos.offset(0, 1, 0)) {
+        Some(chunk) => {
+            let mut open = vec![false; size * size];
+            for z in 0..size {
+                for x in 0..size {
+                    open[z * size + x] = chunk.light(x, 0, z, LightChannel::Sky) == MAX_LIGHT;
+                }
+            }
+            Some(open)
+        }
+        // The sky light waits for the chunk above rather than shining into caves until it arrives.
+        None if world.is_expected(pos.offset(0, 1, 0)) => Some(vec![false; size * size]),
+        None => None,
+    };
+
+    guard!(let Some((mut sky, mut block)) = unlight_chunk(world, pos, registry) else { return });
+    {
+        guard!(let Some(chunk) = world.chunk_mut(pos) else { return });
+        for y in 0..size {
+            for z in 0..size {
+                for x in 0..size {
+                    let world_pos = [origin[0] + x as i32, origin[1] + y as i32, origin[2] + z as i32];
+                    let def = registry.get(chunk.block(x, y, z));
+                    if def.emission > 0 {
+                        chunk.set_light(x, y, z, LightChannel::Block, def.emission);
+                        block.push_back(world_pos);
+                    }
+                    let open = above.as_ref().map_or(true, |open| open[z * size + x]);
+                    if y == size - 1 && open && !def.opaque {
+                        chunk.set_light(x, y, z, LightChannel::Sky, MAX_LIGHT);
+                        sky.push_back(world_pos);
+                    }
+                }
+            }
+        }
+    }
+
+    // Light flowing in from the neighbours.
+    for a in 0..CHUNK_SIZE {
+        for b in 0..CHUNK_SIZE {
+            let outside = [
+                [-1, a, b],
+                [CHUNK_SIZE, a, b],
+                [a, -1, b],
+                [a, CHUNK_SIZE, b],
+                [a, b, -1],
+                [a, b, CHUNK_SIZE],
+            ];
+            for outside in outside.iter() {
+                let neighbour = offset(origin, *outside);
+                if world.light(neighbour, LightChannel::Sky).is_some() {
+                    sky.push_back(neighbour);
+                    block.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    propagate(world, registry, LightChannel::Sky, sky);
+    propagate(world, registry, LightChannel::Block, block);
+}
+
+/// Clears the light of a chunk and removes the light it spread into its neighbours.
+///
+/// Returns the sky and block light positions lit from elsewhere that border the darkened area, `None` if the chunk
+/// is not loaded.
+fn unlight_chunk(
+    world: &mut VoxelWorld, pos: ChunkPos, registry: &BlockRegistry,
+) -> Option<(VecDeque<[i32; 3]>, VecDeque<[i32; 3]>)> {
+    let size = CHUNK_SIZE as usize;
+    let origin = pos.origin();
+    let mut sky = VecDeque::new();
+    let mut block = VecDeque::new();
+    {
+        let chunk = world.chunk_mut(pos)?;
+        for y in 0..size {
+            for z in 0..size {
+                for x in 0..size {
+                    let world_pos = [origin[0] + x as i32, origin[1] + y as i32, origin[2] + z as i32];
+                    let level = chunk.light(x, y, z, LightChannel::Sky);
+                    if level > 0 {
+                        sky.push_back((world_pos, level));
+                    }
+                    let level = chunk.light(x, y, z, LightChannel::Block);
+                    if level > 0 {
+                        block.push_back((world_pos, level));
+                    }
+                }
+            }
+        }
+        chunk.clear_light();
+    }
+    Some((
+        unpropagate(world, registry, LightChannel::Sky, sky),
+        unpropagate(world, registry, LightChannel::Block, block),
+    ))
+}
+
+/// Whether the sky light entering the top of `below` no longer matches the bottom of `above`.
+fn sky_changed(world: &VoxelWorld, above: ChunkPos, below: ChunkPos, registry: &BlockRegistry) -> bool {
+    let (above, below) = match (world.chunk(above), world.chunk(below)) {
+        (Some(above), Some(below)) => (above, below),
+        _ => return false,
+    };
+    let size = CHUNK_SIZE as usize;
+    let top = size - 1;
+    (0..size).any(|z| {
+        (0..size).any(|x| {
+            let lit = below.light(x, top, z, LightChannel::Sky) == MAX_LIGHT;
+            let open =
+                above.light(x, 0, z, LightChannel::Sky) == MAX_LIGHT && !registry.is_opaque(below.block(x, top, z));
+            lit != open
+        })
+    })
+}
+
+// endregion
+
+// region - Flood Fill
+
+/// Spreads light from the queued positions into the darker non-opaque neighbours.
+fn propagate(world: &mut VoxelWorld, registry: &BlockRegistry, channel: LightChannel, mut queue: VecDeque<[i32; 3]>) {
+    while let Some(pos) = queue.pop_front() {
+        guard!(let Some(level) = world.light(pos, channel) else { continue });
+        for (index, dir) in NEIGHBOURS.iter().enumerate() {
+            let next_level = if channel == LightChannel::Sky && index == DOWN && level == MAX_LIGHT {
+                MAX_LIGHT
+            } else {
+                level.saturating_sub(1)
+            };
+            if next_level == 0 {
+                continue;
+            }
+            let next = offset(pos, *dir);
+            guard!(let Some(current) = world.light(next, channel) else { continue });
+            if current >= next_level || registry.is_opaque(world.block(next)) {
+                continue;
+            }
+            world.set_light(next, channel, next_level);
+            queue.push_back(next);
+        }
+    }
+}
+
+/// Removes the light that was fed by the queued positions and their previous levels.
+///
+/// Returns the positions lit from elsewhere that border the darkened area, to be propagated again.
+fn unpropagate(
+    world: &mut VoxelWorld, registry: &BlockRegistry, channel: LightChannel, mut queue: VecDeque<([i32; 3], u8)>,
+) -> VecDeque<[i32; 3]> {
+    let mut reseed = VecDeque::new();
+    while let Some((pos, level)) = queue.pop_front() {
+        for (index, dir) in NEIGHBOURS.iter().enumerate() {
+            let next = offset(pos, *dir);
+            guard!(let Some(next_level) = world.light(next, channel) else { continue });
+            if next_level == 0 {
+                continue;
+            }
+            let straight_down = channel == LightChannel::Sky && index == DOWN && level == MAX_LIGHT;
+            if next_level < level || (straight_down && next_level == MAX_LIGHT) {
+                world.set_light(next, channel, 0);
+                queue.push_back((next, next_level));
+                let emission = registry.get(world.block(next)).emission;
+                if channel == LightChannel::Block && emission > 0 {
+                    world.set_light(next, channel, emission);
+                    reseed.push_back(next);
+                }
+            } else {
+                reseed.push_back(next);
+            }
+        }
+    }
+    reseed
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::chunk::Chunk;
+
+    /// A chunk of air under a stone roof at y 12, with a hole above the column at x 8, z 8.
+    fn roofed(registry: &BlockRegistry) -> VoxelWorld {
+        let stone = registry.id("stone").unwrap();
+        let mut chunk = Chunk::default();
+        for z in 0..16 {
+            for x in 0..16 {
+                if (x, z) != (8, 8) {
+                    chunk.set_block(x, 12, z, stone);
+                }
+            }
+        }
+        let mut world = VoxelWorld::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), chunk, registry);
+        world
+    }
+
+    /// Sky and block light of every block of a chunk.
+    fn levels(world: &VoxelWorld, pos: ChunkPos) -> Vec<(u8, u8)> {
+        let chunk = world.chunk(pos).unwrap();
+        (0..16 * 16 * 16)
+            .map(|index| {
+                let (x, y, z) = (index % 16, index / 256, (index / 16) % 16);
+                (chunk.light(x, y, z, LightChannel::Sky), chunk.light(x, y, z, LightChannel::Block))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sky_light_keeps_its_level_straight_down_and_fades_sideways() {
+        let registry = BlockRegistry::default();
+        let world = roofed(&registry);
+        let sky = |pos: [i32; 3]| world.light(pos, LightChannel::Sky).unwrap();
+        assert!((0..16).filter(|y| *y != 12).all(|y| sky([8, y, 8]) == MAX_LIGHT));
+        assert_eq!(sky([3, 15, 3]), MAX_LIGHT);
+        assert_eq!(sky([9, 5, 8]), MAX_LIGHT - 1);
+        assert_eq!(sky([12, 5, 8]), MAX_LIGHT - 4);
+        assert_eq!(sky([12, 0, 12]), MAX_LIGHT - 8);
+        assert_eq!(sky([3, 12, 3]), 0);
+    }
+
+    #[test]
+    fn removing_a_placed_opaque_block_restores_the_light() {
+        let registry = BlockRegistry::default();
+        let mut world = roofed(&registry);
+        let pos = ChunkPos::new(0, 0, 0);
+        let before = levels(&world, pos);
+
+        world.set_block([8, 6, 8], registry.id("stone").unwrap(), &registry);
+        assert_eq!(world.light([8, 6, 8], LightChannel::Sky), Some(0));
+        assert!(world.light([8, 5, 8], LightChannel::Sky).unwrap() < MAX_LIGHT);
+
+        world.set_block([8, 6, 8], BlockId::AIR, &registry);
+        assert_eq!(levels(&world, pos), before);
+    }
+
+    #[test]
+    fn removing_a_placed_emissive_block_clears_its_light() {
+        let registry = BlockRegistry::default();
+        let mut world = roofed(&registry);
+        let lantern = registry.id("lantern").unwrap();
+        let emission = registry.get(lantern).emission;
+
+        world.set_block([4, 4, 4], lantern, &registry);
+        assert_eq!(world.light([4, 4, 4], LightChannel::Block), Some(emission));
+        assert_eq!(world.light([6, 4, 4], LightChannel::Block), Some(emission - 2));
+
+        world.set_block([4, 4, 4], BlockId::AIR, &registry);
+        assert!(levels(&world, ChunkPos::new(0, 0, 0)).iter().all(|(_, block)| *block == 0));
+    }
+
+    #[test]
+    fn inserting_a_chunk_above_darkens_the_chunk_below() {
+        let registry = BlockRegistry::default();
+        let mut world = VoxelWorld::new();
+        let below = ChunkPos::new(0, 0, 0);
+        world.insert_chunk(below, Chunk::default(), &registry);
+        assert!(levels(&world, below).iter().all(|(sky, _)| *sky == MAX_LIGHT));
+
+        world.insert_chunk(below.offset(0, 1, 0), Chunk::filled(registry.id("stone").unwrap()), &registry);
+        assert!(levels(&world, below).iter().all(|(sky, _)| *sky == 0));
+    }
+}
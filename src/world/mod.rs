@@ -0,0 +1,9 @@
+pub mod block;
+pub mod chunk;
+pub mod level;
+pub mod light;
+pub mod palette;
+pub mod region;
+pub mod save;
+pub mod vox;
+pub mod voxel_world;
\ No newline at end of file
@@ -0,0 +1,300 @@
+//! Palette compressed block storage.
+use crate::world::block::BlockId;
+
+/// Index widths in bits, from the smallest. All divide 64 so an index never straddles two words.
+const BIT_WIDTHS: [u8; 5] = [1, 2, 4, 8, 16];
+
+/// Blocks of a fixed number of cells, stored as indices into a palette of the distinct blocks.
+///
+/// Indices are packed at the smallest of 1, 2, 4, 8 or 16 bits that fits the palette and grow when new blocks
+/// appear. A storage holding a single block, such as an all air or all stone chunk, keeps no indices at all.
+///
+/// Removing the last cell of a block does not shrink the palette, `compact` does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PalettedStorage {
+    len: usize,
+    palette: Vec<BlockId>,
+    /// Bits per index, `0` while uniform.
+    bits: u8,
+    words: Vec<u64>,
+}
+
+impl PalettedStorage {
+    pub fn filled(len: usize, block: BlockId) -> Self {
+        PalettedStorage {
+            len,
+            palette: vec![block],
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    /// Storage from the parts returned by `palette`, `bits` and `words`. `None` if they do not fit together.
+    pub fn from_parts(len: usize, palette: Vec<BlockId>, bits: u8, words: Vec<u64>) -> Option<Self> {
+        let valid = if bits == 0 {
+            palette.len() == 1 && words.is_empty()
+        } else {
+            BIT_WIDTHS.contains(&bits)
+                && !palette.is_empty()
+                && palette.len() <= 1 << bits
+                && words.len() == PalettedStorage::word_count(len, bits)
+        };
+        if !valid {
+            return None;
+        }
+        let storage = PalettedStorage { len, palette, bits, words };
+        if (0..len).any(|index| storage.palette_index(index) >= storage.palette.len()) {
+            return None;
+        }
+        Some(storage)
+    }
+
+    fn word_count(len: usize, bits: u8) -> usize {
+        (len * bits as usize + 63) / 64
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    /// Bits per index, `0` when uniform.
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// Packed indices, lowest bits first.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// The block of every cell, if there is only one.
+    pub fn uniform(&self) -> Option<BlockId> {
+        if self.bits == 0 {
+            Some(self.palette[0])
+        } else {
+            None
+        }
+    }
+
+    /// Approximate heap memory used.
+    pub fn memory(&self) -> usize {
+        self.palette.len() * std::mem::size_of::<BlockId>() + self.words.len() * 8
+    }
+
+    // region - Cells
+
+    #[inline]
+    fn palette_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let bit = index * self.bits as usize;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[bit / 64] >> (bit % 64)) & mask) as usize
+    }
+
+    #[inline]
+    fn set_palette_index(&mut self, index: usize, value: usize) {
+        let bit = index * self.bits as usize;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.words[bit / 64];
+        *word = (*word & !(mask << (bit % 64))) | ((value as u64 & mask) << (bit % 64));
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> BlockId {
+        self.palette[self.palette_index(index)]
+    }
+
+    pub fn set(&mut self, index: usize, block: BlockId) {
+        let value = match self.palette.iter().position(|known| *known == block) {
+            Some(value) => value,
+            None => {
+                if self.bits == 0 || self.palette.len() == 1 << self.bits {
+                    let bits = BIT_WIDTHS
+                        .iter()
+                        .cloned()
+                        .find(|bits| self.palette.len() < 1 << bits)
+                        .expect("a palette holds at most 2^16 blocks");
+                    self.repack(bits);
+                }
+                self.palette.push(block);
+                self.palette.len() - 1
+            }
+        };
+        if self.bits != 0 {
+            self.set_palette_index(index, value);
+        }
+    }
+
+    /// Blocks of all the cells, in order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = BlockId> + 'a {
+        (0..self.len).map(move |index| self.get(index))
+    }
+
+    // endregion
+
+    fn repack(&mut self, bits: u8) {
+        let indices: Vec<usize> = (0..self.len).map(|index| self.palette_index(index)).collect();
+        self.bits = bits;
+        self.words = vec![0; PalettedStorage::word_count(self.len, bits)];
+        if bits != 0 {
+            for (index, value) in indices.into_iter().enumerate() {
+                self.set_palette_index(index, value);
+            }
+        }
+    }
+
+    /// Drops the palette entries no cell uses and packs the indices at the smallest width, down to uniform.
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..self.len {
+            used[self.palette_index(index)] = true;
+        }
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (value, block) in self.palette.iter().enumerate() {
+            if used[value] {
+                remap[value] = palette.len();
+                palette.push(*block);
+            }
+        }
+        if palette.is_empty() {
+            palette.push(self.palette[0]);
+        }
+        let indices: Vec<usize> = (0..self.len).map(|index| remap[self.palette_index(index)]).collect();
+        let bits = if palette.len() <= 1 {
+            0
+        } else {
+            BIT_WIDTHS.iter().cloned().find(|bits| palette.len() <= 1 << bits).unwrap_or(16)
+        };
+
+        self.palette = palette;
+        self.bits = bits;
+        self.words = vec![0; PalettedStorage::word_count(self.len, bits)];
+        if bits != 0 {
+            for (index, value) in indices.into_iter().enumerate() {
+                self.set_palette_index(index, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: usize = 4096;
+
+    /// Storage of `LEN` cells using `count` blocks, cycling through them.
+    fn cycling(count: u16) -> PalettedStorage {
+        let mut storage = PalettedStorage::filled(LEN, BlockId(0));
+        for index in 0..LEN {
+            storage.set(index, BlockId((index * 7 % count as usize) as u16));
+        }
+        storage
+    }
+
+    #[test]
+    fn cells_round_trip_at_every_width() {
+        for (count, bits) in [(2, 1), (3, 2), (5, 4), (17, 8), (257, 16)].iter().cloned() {
+            let storage = cycling(count);
+            assert_eq!(storage.bits(), bits, "{} blocks", count);
+            assert_eq!(storage.words().len(), LEN * bits as usize / 64);
+            for index in 0..LEN {
+                assert_eq!(storage.get(index), BlockId((index * 7 % count as usize) as u16));
+            }
+        }
+    }
+
+    #[test]
+    fn indices_grow_across_each_bit_boundary() {
+        let mut storage = PalettedStorage::filled(LEN, BlockId(0));
+        for block in 1..=256u16 {
+            // Cell `block` gets the new block, the cells before keep theirs through every repack.
+            storage.set(block as usize, BlockId(block));
+            let expected = match storage.palette().len() {
+                2 => 1,
+                3..=4 => 2,
+                5..=16 => 4,
+                17..=256 => 8,
+                _ => 16,
+            };
+            assert_eq!(storage.bits(), expected, "{} blocks", storage.palette().len());
+            for index in 0..=block as usize {
+                assert_eq!(storage.get(index), BlockId(index as u16));
+            }
+            assert_eq!(storage.get(LEN - 1), BlockId(0));
+        }
+        storage.set(LEN - 1, BlockId(1000));
+        assert_eq!(storage.bits(), 16);
+        assert_eq!(storage.get(LEN - 1), BlockId(1000));
+        assert_eq!(storage.get(256), BlockId(256));
+    }
+
+    #[test]
+    fn uniform_storage_keeps_no_indices() {
+        let mut storage = PalettedStorage::filled(LEN, BlockId(3));
+        assert_eq!(storage.uniform(), Some(BlockId(3)));
+        assert!(storage.words().is_empty());
+
+        storage.set(10, BlockId(3));
+        assert_eq!((storage.bits(), storage.uniform()), (0, Some(BlockId(3))));
+        assert!(storage.iter().all(|block| block == BlockId(3)));
+
+        storage.set(10, BlockId(4));
+        assert_eq!((storage.bits(), storage.uniform()), (1, None));
+        assert_eq!((storage.get(9), storage.get(10)), (BlockId(3), BlockId(4)));
+    }
+
+    #[test]
+    fn compact_drops_removed_blocks() {
+        let mut storage = cycling(5);
+        for index in (0..LEN).filter(|index| storage.get(*index) == BlockId(2)).collect::<Vec<_>>() {
+            storage.set(index, BlockId(0));
+        }
+        for index in (0..LEN).filter(|index| storage.get(*index) == BlockId(4)).collect::<Vec<_>>() {
+            storage.set(index, BlockId(1));
+        }
+        let blocks: Vec<BlockId> = storage.iter().collect();
+        assert_eq!(storage.palette().len(), 5);
+
+        storage.compact();
+        assert_eq!(storage.palette(), &[BlockId(0), BlockId(1), BlockId(3)]);
+        assert_eq!(storage.bits(), 2);
+        assert_eq!(storage.iter().collect::<Vec<_>>(), blocks);
+
+        for index in 0..LEN {
+            storage.set(index, BlockId(3));
+        }
+        storage.compact();
+        assert_eq!(storage, PalettedStorage::filled(LEN, BlockId(3)));
+    }
+
+    #[test]
+    fn from_parts_rejects_inconsistent_parts() {
+        let storage = cycling(3);
+        let parts = |storage: &PalettedStorage| (storage.palette().to_vec(), storage.bits(), storage.words().to_vec());
+        let (palette, bits, words) = parts(&storage);
+        assert_eq!(PalettedStorage::from_parts(LEN, palette.clone(), bits, words.clone()), Some(storage));
+
+        // Words for another length.
+        assert_eq!(PalettedStorage::from_parts(LEN, palette.clone(), bits, words[1..].to_vec()), None);
+        assert_eq!(PalettedStorage::from_parts(LEN * 2, palette.clone(), bits, words.clone()), None);
+        // Not a packing width.
+        assert_eq!(PalettedStorage::from_parts(LEN, palette.clone(), 3, words.clone()), None);
+        // Palette too large for the width, or missing an entry an index uses.
+        let large: Vec<BlockId> = (0..5).map(BlockId).collect();
+        assert_eq!(PalettedStorage::from_parts(LEN, large, bits, words.clone()), None);
+        assert_eq!(PalettedStorage::from_parts(LEN, palette[..2].to_vec(), bits, words.clone()), None);
+        assert_eq!(PalettedStorage::from_parts(LEN, Vec::new(), bits, words), None);
+        // Uniform with indices, or with several blocks.
+        assert_eq!(PalettedStorage::from_parts(LEN, vec![BlockId(1)], 0, vec![0]), None);
+        assert_eq!(PalettedStorage::from_parts(LEN, palette, 0, Vec::new()), None);
+        assert!(PalettedStorage::from_parts(LEN, vec![BlockId(1)], 0, Vec::new()).is_some());
+    }
+}
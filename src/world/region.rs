@@ -0,0 +1,505 @@
+//! Region files: the saved chunks of a 32×32 chunk area of one chunk layer, in a single file.
+//!
+//! ```text
+//! header   magic "VXRG", format version u16, reserved u16
+//! table    REGION_CHUNKS × (offset u32, length u32), offset 0 for a chunk never saved
+//! payloads chunk payloads, see `encode_chunk`
+//! ```
+//!
+//! Numbers are little endian. A region is rewritten as a whole into a temporary file that then replaces the old
+//! one, so a crash while saving leaves the previous version intact.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{Chunk, ChunkPos, CHUNK_SIZE, CHUNK_VOLUME};
+use crate::world::palette::PalettedStorage;
+
+/// Chunks along X and Z in a region.
+pub const REGION_SIZE: i32 = 32;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"VXRG";
+/// Version of the region layout, changes when the header or table change.
+pub const REGION_VERSION: u16 = 1;
+/// Version of the chunk payloads, changes when `encode_chunk` does. Version 1 stored run-length encoded blocks and
+/// version 2 the packed index words as is, both are still read.
+pub const CHUNK_FORMAT_VERSION: u16 = 3;
+const HEADER_LEN: u64 = 8;
+const TABLE_LEN: u64 = REGION_CHUNKS as u64 * 8;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// region - Region Position
+
+/// Position of a region, chunk coordinates divided by `REGION_SIZE` along X and Z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RegionPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl RegionPos {
+    pub fn of_chunk(pos: ChunkPos) -> Self {
+        RegionPos {
+            x: pos.x.div_euclid(REGION_SIZE),
+            y: pos.y,
+            z: pos.z.div_euclid(REGION_SIZE),
+        }
+    }
+
+    /// Table index of a chunk of this region.
+    fn index(pos: ChunkPos) -> usize {
+        (pos.z.rem_euclid(REGION_SIZE) * REGION_SIZE + pos.x.rem_euclid(REGION_SIZE)) as usize
+    }
+
+    fn chunk(&self, index: usize) -> ChunkPos {
+        let index = index as i32;
+        ChunkPos::new(self.x * REGION_SIZE + index % REGION_SIZE, self.y, self.z * REGION_SIZE + index / REGION_SIZE)
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("r.{}.{}.{}.region", self.x, self.y, self.z)
+    }
+
+    /// Parses a name made by `file_name`.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let mut parts = name.strip_suffix(".region")?.strip_prefix("r.")?.split('.');
+        let x = parts.next()?.parse().ok()?;
+        let y = parts.next()?.parse().ok()?;
+        let z = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(RegionPos { x, y, z })
+    }
+}
+
+// endregion
+
+// region - Region File
+
+/// Encoded chunk payloads of a region, read and written as a whole.
+#[derive(Debug, Default)]
+pub struct RegionFile {
+    payloads: BTreeMap<usize, Vec<u8>>,
+}
+
+impl RegionFile {
+    /// Reads the whole region, a missing file is an empty region.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(RegionFile::default()),
+            Err(error) => return Err(error),
+        };
+        let table = read_table(&mut file)?;
+        let mut payloads = BTreeMap::new();
+        for (index, (offset, length)) in table.into_iter().enumerate() {
+            if offset != 0 {
+                payloads.insert(index, read_payload(&mut file, offset, length)?);
+            }
+        }
+        Ok(RegionFile { payloads })
+    }
+
+    /// Reads the payload of a single chunk, without loading the rest of the region.
+    pub fn read_chunk(path: &Path, pos: ChunkPos) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let (offset, length) = read_table(&mut file)?[RegionPos::index(pos)];
+        if offset == 0 {
+            return Ok(None);
+        }
+        read_payload(&mut file, offset, length).map(Some)
+    }
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<&[u8]> {
+        self.payloads.get(&RegionPos::index(pos)).map(Vec::as_slice)
+    }
+
+    pub fn set_chunk(&mut self, pos: ChunkPos, payload: Vec<u8>) {
+        self.payloads.insert(RegionPos::index(pos), payload);
+    }
+
+    /// Positions and payloads of the saved chunks of the region at `region`.
+    pub fn chunks<'a>(&'a self, region: RegionPos) -> impl Iterator<Item = (ChunkPos, &'a [u8])> + 'a {
+        self.payloads.iter().map(move |(index, payload)| (region.chunk(*index), payload.as_slice()))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = Vec::with_capacity((HEADER_LEN + TABLE_LEN) as usize);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+
+        let mut table = vec![(0u32, 0u32); REGION_CHUNKS];
+        let mut offset = HEADER_LEN + TABLE_LEN;
+        for (index, payload) in &self.payloads {
+            table[*index] = (offset as u32, payload.len() as u32);
+            offset += payload.len() as u64;
+        }
+        for (offset, length) in table {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&length.to_le_bytes());
+        }
+        for payload in self.payloads.values() {
+            data.extend_from_slice(payload);
+        }
+
+        let temporary: PathBuf = path.with_extension("region.tmp");
+        File::create(&temporary)?.write_all(&data)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+fn read_table(file: &mut File) -> io::Result<Vec<(u32, u32)>> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(invalid("not a region file".to_string()));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != REGION_VERSION {
+        return Err(invalid(format!("unsupported region version {}", version)));
+    }
+    let mut table = vec![0u8; TABLE_LEN as usize];
+    file.read_exact(&mut table)?;
+    Ok(table
+        .chunks_exact(8)
+        .map(|entry| {
+            (
+                u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+            )
+        })
+        .collect())
+}
+
+fn read_payload(file: &mut File, offset: u32, length: u32) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut payload = vec![0u8; length as usize];
+    file.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+// endregion
+
+// region - Chunk Payload
+
+/// Writes a chunk as:
+///
+/// ```text
+/// chunk format version u16
+/// palette   count u16, then per entry the block name as length u16 and UTF-8 bytes
+/// blocks    bits per index u8, then runs of (length varint, word u64) over the packed index words, none when the
+///           bits are 0
+/// tints     runs of (length varint, r u8, g u8, b u8) over the columns
+/// ```
+///
+/// Blocks are the compacted `PalettedStorage` of the chunk, its words run-length encoded as layers of air or stone
+/// pack into runs of equal words. Block names rather than ids keep saves valid when blocks are registered in another
+/// order. Light is not saved, it is recomputed when the chunk is inserted.
+pub fn encode_chunk(chunk: &Chunk, registry: &BlockRegistry) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&CHUNK_FORMAT_VERSION.to_le_bytes());
+
+    let mut blocks = chunk.blocks().clone();
+    blocks.compact();
+    data.extend_from_slice(&(blocks.palette().len() as u16).to_le_bytes());
+    for block in blocks.palette() {
+        let name = registry.get(*block).name.as_bytes();
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(name);
+    }
+    data.push(blocks.bits());
+    for (length, word) in runs(blocks.words()) {
+        write_varint(&mut data, length);
+        data.extend_from_slice(&word.to_le_bytes());
+    }
+
+    let size = CHUNK_SIZE as usize;
+    let tints: Vec<[u8; 3]> = (0..size * size).map(|column| chunk.tint(column % size, column / size)).collect();
+    for (length, tint) in runs(&tints) {
+        write_varint(&mut data, length);
+        data.extend_from_slice(tint);
+    }
+    data
+}
+
+/// Reads a chunk written by `encode_chunk`. Blocks no longer registered become air.
+pub fn decode_chunk(data: &[u8], registry: &BlockRegistry) -> io::Result<Chunk> {
+    let mut reader = Reader { data, at: 0 };
+    let version = reader.u16()?;
+    if version == 0 || version > CHUNK_FORMAT_VERSION {
+        return Err(invalid(format!("unsupported chunk format version {}", version)));
+    }
+
+    let palette_len = reader.u16()? as usize;
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        let length = reader.u16()? as usize;
+        let name = std::str::from_utf8(reader.bytes(length)?).map_err(|error| invalid(error.to_string()))?;
+        palette.push(registry.id(name).unwrap_or_else(|| {
+            log::warn!("Saved chunk uses unknown block `{}`", name);
+            BlockId::AIR
+        }));
+    }
+
+    let mut chunk = if version == 1 {
+        decode_block_runs(&mut reader, &palette)?
+    } else {
+        let bits = reader.bytes(1)?[0];
+        let count = (CHUNK_VOLUME * bits as usize + 63) / 64;
+        let mut words = Vec::with_capacity(count);
+        while words.len() < count {
+            let length = if version == 2 { 1 } else { reader.varint()? as usize };
+            let word = reader.u64()?;
+            if length == 0 || words.len() + length > count {
+                return Err(invalid("block word run out of range".to_string()));
+            }
+            words.extend(std::iter::repeat(word).take(length));
+        }
+        let blocks = PalettedStorage::from_parts(CHUNK_VOLUME, palette, bits, words)
+            .ok_or_else(|| invalid("invalid block palette".to_string()))?;
+        Chunk::default().with_blocks(blocks).ok_or_else(|| invalid("invalid block count".to_string()))?
+    };
+
+    let size = CHUNK_SIZE as usize;
+    let mut column = 0;
+    while column < size * size {
+        let length = reader.varint()? as usize;
+        let tint = reader.bytes(3)?;
+        let tint = [tint[0], tint[1], tint[2]];
+        if length == 0 || column + length > size * size {
+            return Err(invalid("tint run out of range".to_string()));
+        }
+        for index in column..column + length {
+            chunk.set_tint(index % size, index / size, tint);
+        }
+        column += length;
+    }
+    Ok(chunk)
+}
+
+/// Blocks of a version 1 payload, runs of (length varint, palette index varint).
+fn decode_block_runs(reader: &mut Reader, palette: &[BlockId]) -> io::Result<Chunk> {
+    let size = CHUNK_SIZE as usize;
+    let mut chunk = Chunk::default();
+    let mut at = 0;
+    while at < CHUNK_VOLUME {
+        let length = reader.varint()? as usize;
+        let block = *palette
+            .get(reader.varint()? as usize)
+            .ok_or_else(|| invalid("block palette index out of range".to_string()))?;
+        if length == 0 || at + length > CHUNK_VOLUME {
+            return Err(invalid("block run out of range".to_string()));
+        }
+        for index in at..at + length {
+            chunk.set_block(index % size, index / (size * size), (index / size) % size, block);
+        }
+        at += length;
+    }
+    Ok(chunk)
+}
+
+/// Lengths and values of the runs of equal values.
+fn runs<T: PartialEq>(values: &[T]) -> Vec<(u32, &T)> {
+    let mut runs: Vec<(u32, &T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((length, last)) if *last == value => *length += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+/// LEB128, 7 bits per byte, low bits first.
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte);
+            return;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        guard!(let Some(bytes) = self.data.get(self.at..self.at + count) else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunk payload"));
+        });
+        self.at += count;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn varint(&mut self) -> io::Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= ((byte & 0x7F) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint too long".to_string()))
+    }
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stone below y 5, a crate in the top corner and tinted columns along z 0.
+    fn layered(registry: &BlockRegistry) -> Chunk {
+        let (stone, crate_block) = (registry.id("stone").unwrap(), registry.id("crate").unwrap());
+        let mut chunk = Chunk::default();
+        for y in 0..5 {
+            for z in 0..16 {
+                for x in 0..16 {
+                    chunk.set_block(x, y, z, stone);
+                }
+            }
+        }
+        chunk.set_block(15, 15, 15, crate_block);
+        for x in 0..16 {
+            chunk.set_tint(x, 0, [10, 200, 30]);
+        }
+        chunk
+    }
+
+    fn assert_same_chunk(decoded: &Chunk, chunk: &Chunk) {
+        assert!(decoded.blocks().iter().eq(chunk.blocks().iter()));
+        for column in 0..256 {
+            assert_eq!(decoded.tint(column % 16, column / 16), chunk.tint(column % 16, column / 16));
+        }
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let registry = BlockRegistry::default();
+        let stone = registry.id("stone").unwrap();
+        for chunk in &[Chunk::default(), Chunk::filled(stone), layered(&registry)] {
+            let payload = encode_chunk(chunk, &registry);
+            assert_same_chunk(&decode_chunk(&payload, &registry).unwrap(), chunk);
+        }
+    }
+
+    #[test]
+    fn equal_block_words_are_run_length_encoded() {
+        let registry = BlockRegistry::default();
+        let chunk = layered(&registry);
+        // Three blocks pack into 2 bit indices, 128 words of 8 bytes unencoded.
+        assert_eq!(chunk.blocks().bits(), 2);
+        assert!(encode_chunk(&chunk, &registry).len() < 100);
+    }
+
+    #[test]
+    fn older_payloads_still_load() {
+        let registry = BlockRegistry::default();
+        let chunk = layered(&registry);
+        let header = |version: u16, names: &[&str]| {
+            let mut payload = version.to_le_bytes().to_vec();
+            payload.extend_from_slice(&(names.len() as u16).to_le_bytes());
+            for name in names {
+                payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                payload.extend_from_slice(name.as_bytes());
+            }
+            payload
+        };
+
+        // Version 1, runs of palette indices: stone below y 8, air above, one tint.
+        let mut payload = header(1, &["air", "stone"]);
+        for (length, index) in &[(CHUNK_VOLUME as u32 / 2, 1), (CHUNK_VOLUME as u32 / 2, 0)] {
+            write_varint(&mut payload, *length);
+            write_varint(&mut payload, *index);
+        }
+        write_varint(&mut payload, 256);
+        payload.extend_from_slice(&[1, 2, 3]);
+        let decoded = decode_chunk(&payload, &registry).unwrap();
+        assert_eq!(decoded.block(3, 7, 9), registry.id("stone").unwrap());
+        assert_eq!(decoded.block(3, 8, 9), BlockId::AIR);
+        assert_eq!(decoded.tint(5, 11), [1, 2, 3]);
+
+        // Version 2, every packed index word as is.
+        let mut blocks = chunk.blocks().clone();
+        blocks.compact();
+        let names: Vec<&str> = blocks.palette().iter().map(|block| registry.get(*block).name.as_str()).collect();
+        let mut payload = header(2, &names);
+        payload.push(blocks.bits());
+        for word in blocks.words() {
+            payload.extend_from_slice(&word.to_le_bytes());
+        }
+        let tints: Vec<[u8; 3]> = (0..256).map(|column| chunk.tint(column % 16, column / 16)).collect();
+        for (length, tint) in runs(&tints) {
+            write_varint(&mut payload, length);
+            payload.extend_from_slice(tint);
+        }
+        assert_same_chunk(&decode_chunk(&payload, &registry).unwrap(), &chunk);
+    }
+
+    #[test]
+    fn region_table_points_at_the_payloads() {
+        let directory = std::env::temp_dir().join(format!("region-table-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let region = RegionPos { x: -1, y: 2, z: 0 };
+        let path = directory.join(region.file_name());
+        let (first, last) = (ChunkPos::new(-32, 2, 0), ChunkPos::new(-1, 2, 31));
+        assert_eq!((RegionPos::index(first), RegionPos::index(last)), (0, REGION_CHUNKS - 1));
+
+        let mut file = RegionFile::default();
+        file.set_chunk(last, vec![4, 5]);
+        file.set_chunk(first, vec![1, 2, 3]);
+        file.save(&path).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(&data[..4], &MAGIC[..]);
+        let entry = |index: usize| {
+            let at = HEADER_LEN as usize + index * 8;
+            let word = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+            (word(at), word(at + 4))
+        };
+        let start = (HEADER_LEN + TABLE_LEN) as u32;
+        assert_eq!(entry(0), (start, 3));
+        assert_eq!(entry(REGION_CHUNKS - 1), (start + 3, 2));
+        assert!((1..REGION_CHUNKS - 1).all(|index| entry(index) == (0, 0)));
+        assert_eq!(data.len(), start as usize + 5);
+
+        assert_eq!(RegionFile::read_chunk(&path, last).unwrap(), Some(vec![4, 5]));
+        assert_eq!(RegionFile::read_chunk(&path, ChunkPos::new(-31, 2, 0)).unwrap(), None);
+        let opened = RegionFile::open(&path).unwrap();
+        let chunks: Vec<(ChunkPos, &[u8])> = opened.chunks(region).collect();
+        assert_eq!(chunks, vec![(first, &[1u8, 2, 3][..]), (last, &[4u8, 5][..])]);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,122 @@
+//! Saving and loading the voxel world to region files, and its metadata to `level.ron`.
+use amethyst::config::{Config, ConfigError};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::world::block::BlockRegistry;
+use crate::world::chunk::{Chunk, ChunkPos};
+use crate::world::level::{Level, LEVEL_FILE};
+use crate::world::region::{decode_chunk, encode_chunk, RegionFile, RegionPos};
+use crate::world::voxel_world::VoxelWorld;
+
+/// Resource with the directory the world is saved in.
+///
+/// Only modified chunks are saved, the others are generated again from the seed.
+#[derive(Debug, Clone)]
+pub struct WorldSave {
+    directory: PathBuf,
+    /// Held while region files are rewritten, saves from the thread pool and the frame thread never interleave.
+    writing: Arc<Mutex<()>>,
+}
+
+impl WorldSave {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        WorldSave { directory: directory.into(), writing: Arc::new(Mutex::new(())) }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// `None` for a world never saved.
+    pub fn load_level(&self) -> Result<Option<Level>, ConfigError> {
+        let path = self.directory.join(LEVEL_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Level::load(path).map(Some)
+    }
+
+    pub fn save_level(&self, level: &Level) -> Result<(), ConfigError> {
+        fs::create_dir_all(&self.directory)?;
+        level.write(self.directory.join(LEVEL_FILE))
+    }
+
+    fn region_path(&self, region: RegionPos) -> PathBuf {
+        self.directory.join(region.file_name())
+    }
+
+    /// Writes chunks to their regions, each region file rewritten once. Safe to call from any thread.
+    pub fn save_chunks<'a, I>(&self, chunks: I, registry: &BlockRegistry) -> io::Result<usize>
+    where I: IntoIterator<Item = (ChunkPos, &'a Chunk)> {
+        let mut regions: HashMap<RegionPos, Vec<(ChunkPos, Vec<u8>)>> = HashMap::new();
+        for (pos, chunk) in chunks {
+            regions.entry(RegionPos::of_chunk(pos)).or_default().push((pos, encode_chunk(chunk, registry)));
+        }
+        if regions.is_empty() {
+            return Ok(0);
+        }
+
+        let _writing = self.writing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        fs::create_dir_all(&self.directory)?;
+        let mut saved = 0;
+        for (region, chunks) in regions {
+            let path = self.region_path(region);
+            let mut file = RegionFile::open(&path)?;
+            for (pos, payload) in chunks {
+                file.set_chunk(pos, payload);
+                saved += 1;
+            }
+            file.save(&path)?;
+        }
+        Ok(saved)
+    }
+
+    /// `None` if the chunk was never saved. Safe to call from any thread.
+    pub fn load_chunk(&self, pos: ChunkPos, registry: &BlockRegistry) -> io::Result<Option<Chunk>> {
+        match RegionFile::read_chunk(&self.region_path(RegionPos::of_chunk(pos)), pos)? {
+            Some(payload) => decode_chunk(&payload, registry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Saves the modified chunks of the world and marks them saved. Returns the number of chunks written.
+    pub fn save_world(&self, world: &mut VoxelWorld, registry: &BlockRegistry) -> io::Result<usize> {
+        let modified = world.modified_chunks();
+        let saved = self.save_chunks(modified.iter().filter_map(|pos| world.chunk(*pos).map(|chunk| (*pos, chunk))),
+                                     registry)?;
+        for pos in modified {
+            world.mark_chunk_saved(pos);
+        }
+        Ok(saved)
+    }
+
+    /// Inserts every saved chunk into the world, top down so sky light comes from above. Returns the number of
+    /// chunks loaded.
+    pub fn load_world(&self, world: &mut VoxelWorld, registry: &BlockRegistry) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        let mut chunks = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            guard!(let Some(region) = name.to_str().and_then(RegionPos::from_file_name) else { continue });
+            let file = RegionFile::open(&entry.path())?;
+            for (pos, payload) in file.chunks(region) {
+                chunks.push((pos, decode_chunk(payload, registry)?));
+            }
+        }
+        chunks.sort_by_key(|(pos, _)| (-pos.y, pos.x, pos.z));
+        let loaded = chunks.len();
+        for (pos, chunk) in chunks {
+            world.insert_chunk(pos, chunk, registry);
+        }
+        Ok(loaded)
+    }
+}
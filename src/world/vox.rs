@@ -0,0 +1,430 @@
+//! MagicaVoxel `.vox` models: import as structures or coloured meshes, export of world areas.
+//!
+//! MagicaVoxel is Z up, models are turned so their Z axis is the world Y axis, and their Y axis the world Z axis.
+use amethyst::assets::{Asset, AssetStorage, Format, Handle, Loader};
+use amethyst::core::Transform;
+use amethyst::ecs::{Entity, VecStorage};
+use amethyst::prelude::{Builder, World, WorldExt};
+use amethyst::renderer::{palette::Srgba, rendy::texture::palette::load_from_srgba, types::Texture};
+use amethyst::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+
+use crate::render_cache::MaterialCache;
+use crate::render_chunk::build_color_mesh;
+use crate::render_material::{CompositeMaterial, Material, MaterialDefaults};
+use crate::render_mesh::{CompositeMesh, Mesh, MeshData};
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::voxel_world::VoxelWorld;
+use crate::worldgen::noise::hash;
+use crate::worldgen::structure::ResolvedStructure;
+
+const VERSION: i32 = 150;
+/// Material cache id of the white material vox meshes are drawn with, right below the chunk textures.
+const VOX_MATERIAL_CACHE_ID: u32 = (1 << 16) - 1;
+/// Largest model edge MagicaVoxel supports.
+pub const MAX_MODEL_SIZE: i32 = 256;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// region - Scene
+
+/// Colours of the 255 usable palette slots as RGBA, index 0 is never used by a voxel.
+pub type VoxPalette = [[u8; 4]; 256];
+
+/// Model of a `.vox` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxModel {
+    /// Size in world axes.
+    pub size: [i32; 3],
+    /// Positions in world axes and palette indices, from 1.
+    pub voxels: Vec<([i32; 3], u8)>,
+}
+
+/// Content of a `.vox` file. Asset loaded with `VoxFormat`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    pub palette: VoxPalette,
+}
+
+impl Asset for VoxScene {
+    const NAME: &'static str = "world::VoxScene";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+/// Palette MagicaVoxel uses when a file has none: a 6 level colour cube, then 10 level red, green, blue and grey
+/// ramps.
+pub fn default_palette() -> VoxPalette {
+    let mut palette = [[0u8; 4]; 256];
+    let mut index = 1;
+    let cube = [0xFF, 0xCC, 0x99, 0x66, 0x33, 0x00];
+    for r in cube.iter() {
+        for g in cube.iter() {
+            for b in cube.iter() {
+                if (*r, *g, *b) != (0, 0, 0) {
+                    palette[index] = [*r, *g, *b, 0xFF];
+                    index += 1;
+                }
+            }
+        }
+    }
+    let ramp = [0xEE, 0xDD, 0xBB, 0xAA, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for level in ramp.iter() {
+            palette[index] = match channel {
+                0 => [*level, 0, 0, 0xFF],
+                1 => [0, *level, 0, 0xFF],
+                2 => [0, 0, *level, 0xFF],
+                _ => [*level, *level, *level, 0xFF],
+            };
+            index += 1;
+        }
+    }
+    palette
+}
+
+// endregion
+
+// region - Reading
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        guard!(let Some(bytes) = self.data.get(self.at..self.at + count) else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated vox file"));
+        });
+        self.at += count;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// Parses a `.vox` file. Scene graph, material and layer chunks are ignored, models keep their file order.
+pub fn parse_vox(data: &[u8]) -> io::Result<VoxScene> {
+    let mut reader = Reader { data, at: 0 };
+    if reader.bytes(4)? != b"VOX " {
+        return Err(invalid("not a vox file"));
+    }
+    reader.u32()?;
+
+    let mut models = Vec::new();
+    let mut size: Option<[u32; 3]> = None;
+    let mut palette = default_palette();
+    while reader.at < data.len() {
+        let id = reader.bytes(4)?;
+        let content = reader.u32()? as usize;
+        let children = reader.u32()? as usize;
+        match id {
+            // Its children are the chunks that follow.
+            b"MAIN" => {
+                reader.bytes(content)?;
+                continue;
+            }
+            b"SIZE" => {
+                let mut content = Reader { data: reader.bytes(content)?, at: 0 };
+                size = Some([content.u32()?, content.u32()?, content.u32()?]);
+            }
+            b"XYZI" => {
+                let mut content = Reader { data: reader.bytes(content)?, at: 0 };
+                guard!(let Some([sx, sy, sz]) = size.take() else { return Err(invalid("XYZI chunk without SIZE")) });
+                let count = content.u32()? as usize;
+                let mut voxels = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let voxel = content.bytes(4)?;
+                    voxels.push(([voxel[0] as i32, voxel[2] as i32, voxel[1] as i32], voxel[3]));
+                }
+                models.push(VoxModel { size: [sx as i32, sz as i32, sy as i32], voxels });
+            }
+            b"RGBA" => {
+                let colors = reader.bytes(content)?;
+                for (index, color) in colors.chunks_exact(4).take(255).enumerate() {
+                    palette[index + 1] = [color[0], color[1], color[2], color[3]];
+                }
+            }
+            _ => {
+                reader.bytes(content)?;
+            }
+        }
+        reader.bytes(children)?;
+    }
+    Ok(VoxScene { models, palette })
+}
+
+// endregion
+
+// region - Format
+
+/// Format of MagicaVoxel files. Loads a `VoxScene`, or a `MeshData` of all the models coloured per vertex.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct VoxFormat;
+
+amethyst::assets::register_format!("VOX", VoxFormat as MeshData);
+impl Format<MeshData> for VoxFormat {
+    fn name(&self) -> &'static str {
+        "VOX"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<MeshData, Error> {
+        Ok(parse_vox(&bytes)?.mesh())
+    }
+}
+
+impl Format<VoxScene> for VoxFormat {
+    fn name(&self) -> &'static str {
+        "VOX"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<VoxScene, Error> {
+        Ok(parse_vox(&bytes)?)
+    }
+}
+
+// endregion
+
+// region - Conversion
+
+/// Blocks the palette colours of imported models become.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoxBlockMapping {
+    /// Palette index and block name.
+    pub blocks: Vec<(u8, String)>,
+    /// Block of the colours missing from `blocks`, `None` leaves them out.
+    pub fallback: Option<String>,
+}
+
+impl Default for VoxBlockMapping {
+    fn default() -> Self {
+        VoxBlockMapping {
+            blocks: Vec::new(),
+            fallback: Some("stone".to_string()),
+        }
+    }
+}
+
+impl VoxBlockMapping {
+    /// Block of every palette index, unknown block names are left out with a warning.
+    fn resolve(&self, registry: &BlockRegistry) -> [Option<BlockId>; 256] {
+        let lookup = |name: &str| {
+            let id = registry.id(name);
+            if id.is_none() {
+                log::warn!("Vox block mapping uses unknown block `{}`", name);
+            }
+            id
+        };
+        let fallback = self.fallback.as_ref().and_then(|name| lookup(name));
+        let mut blocks = [fallback; 256];
+        blocks[0] = None;
+        for (index, name) in &self.blocks {
+            blocks[*index as usize] = lookup(name);
+        }
+        blocks
+    }
+}
+
+impl VoxScene {
+    /// All the models as one structure, anchored at the bottom centre of the first model.
+    pub fn structure(&self, mapping: &VoxBlockMapping, registry: &BlockRegistry) -> ResolvedStructure {
+        let blocks = mapping.resolve(registry);
+        let anchor = self.models.first().map_or([0, 0, 0], |model| [model.size[0] / 2, 0, model.size[2] / 2]);
+        let mut structure = ResolvedStructure { blocks: Vec::new(), reach: 0 };
+        for model in &self.models {
+            for (position, index) in &model.voxels {
+                guard!(let Some(block) = blocks[*index as usize] else { continue });
+                let offset = [position[0] - anchor[0], position[1] - anchor[1], position[2] - anchor[2]];
+                structure.reach = structure.reach.max(offset[0].abs()).max(offset[2].abs());
+                structure.blocks.push((offset, block));
+            }
+        }
+        structure
+    }
+
+    /// All the models as one mesh coloured per vertex, centred on X and Z with the bottom at 0.
+    pub fn mesh(&self) -> MeshData {
+        let mut cells: HashMap<[i32; 3], u8> = HashMap::new();
+        let mut size = [0; 3];
+        for model in &self.models {
+            for (position, index) in &model.voxels {
+                cells.insert(*position, *index);
+            }
+            size = [size[0].max(model.size[0]), size[1].max(model.size[1]), size[2].max(model.size[2])];
+        }
+        let offset = [-(size[0] / 2) as f32, 0.0, -(size[2] / 2) as f32];
+        build_color_mesh(size, offset, |position| {
+            let index = *cells.get(&position)?;
+            let [r, g, b, a] = self.palette[index as usize];
+            Some([r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0])
+        })
+    }
+}
+
+/// Creates an entity drawing a mesh loaded with `VoxFormat`. Its material is plain white so the vertex colours show
+/// as they are.
+pub fn spawn_vox_entity(world: &mut World, mesh: Handle<Mesh>, transform: Transform) -> Entity {
+    let cached = world.read_resource::<MaterialCache>().cached(VOX_MATERIAL_CACHE_ID);
+    let material = cached.unwrap_or_else(|| {
+        let diffuse: Handle<Texture> = world.read_resource::<Loader>().load_from_data(
+            load_from_srgba(Srgba::new(1.0, 1.0, 1.0, 1.0)).into(),
+            (),
+            &world.read_resource::<AssetStorage<Texture>>(),
+        );
+        let defaults = world.read_resource::<MaterialDefaults>().0.clone();
+        let material = world.write_resource::<AssetStorage<Material>>().insert(Material { diffuse, ..defaults });
+        world.write_resource::<MaterialCache>().cache(material.clone(), VOX_MATERIAL_CACHE_ID);
+        material
+    });
+    world
+        .create_entity()
+        .with(CompositeMesh { elements: vec![mesh] })
+        .with(CompositeMaterial { components: vec![material] })
+        .with(transform)
+        .build()
+}
+
+// endregion
+
+// region - Export
+
+/// Writes the blocks of a world area, `min` and `max` included, as a single model `.vox` file.
+///
+/// Blocks with a palette index in `mapping` keep it, the others get free indices and a colour derived from their
+/// id. Areas larger than `MAX_MODEL_SIZE` on any axis are cut, block types beyond the 255 palette slots are skipped
+/// with a warning.
+pub fn export_vox(
+    world: &VoxelWorld, min: [i32; 3], max: [i32; 3], mapping: &VoxBlockMapping, registry: &BlockRegistry,
+) -> Vec<u8> {
+    let size = [
+        (max[0] - min[0] + 1).max(0).min(MAX_MODEL_SIZE),
+        (max[1] - min[1] + 1).max(0).min(MAX_MODEL_SIZE),
+        (max[2] - min[2] + 1).max(0).min(MAX_MODEL_SIZE),
+    ];
+
+    let mut palette = default_palette();
+    let mut indices: HashMap<BlockId, u8> = HashMap::new();
+    for (index, name) in &mapping.blocks {
+        if let Some(block) = registry.id(name) {
+            indices.entry(block).or_insert(*index);
+        }
+    }
+    let mut next_free = 1u16;
+
+    let mut voxels = Vec::new();
+    let mut skipped = 0;
+    for y in 0..size[1] {
+        for z in 0..size[2] {
+            for x in 0..size[0] {
+                let block = world.block([min[0] + x, min[1] + y, min[2] + z]);
+                if block.is_air() {
+                    continue;
+                }
+                let index = match indices.get(&block) {
+                    Some(index) => *index,
+                    None => {
+                        while next_free < 256 && indices.values().any(|used| *used as u16 == next_free) {
+                            next_free += 1;
+                        }
+                        if next_free >= 256 {
+                            skipped += 1;
+                            continue;
+                        }
+                        let index = next_free as u8;
+                        let color = hash(0x0B0C, block.0 as i32, 0, 0).to_le_bytes();
+                        palette[index as usize] = [color[0], color[1], color[2], 0xFF];
+                        indices.insert(block, index);
+                        index
+                    }
+                };
+                // Back to MagicaVoxel axes, Z up.
+                voxels.extend_from_slice(&[x as u8, z as u8, y as u8, index]);
+            }
+        }
+    }
+    if skipped > 0 {
+        log::warn!("Vox export has no palette slot left, skipped {} voxels", skipped);
+    }
+
+    let mut chunks = Vec::new();
+    write_chunk(&mut chunks, b"SIZE", &[size[0] as u32, size[2] as u32, size[1] as u32]
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect::<Vec<u8>>());
+    let mut xyzi = ((voxels.len() / 4) as u32).to_le_bytes().to_vec();
+    xyzi.extend_from_slice(&voxels);
+    write_chunk(&mut chunks, b"XYZI", &xyzi);
+    let rgba: Vec<u8> = palette[1..]
+        .iter()
+        .chain(std::iter::once(&[0u8; 4]))
+        .flat_map(|color| color.to_vec())
+        .collect();
+    write_chunk(&mut chunks, b"RGBA", &rgba);
+
+    let mut data = Vec::with_capacity(20 + chunks.len());
+    data.extend_from_slice(b"VOX ");
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(b"MAIN");
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    data.extend_from_slice(&chunks);
+    data
+}
+
+fn write_chunk(data: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    data.extend_from_slice(id);
+    data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(content);
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::chunk::{Chunk, ChunkPos};
+
+    #[test]
+    fn exported_area_parses_back() {
+        let registry = BlockRegistry::default();
+        let (stone, crate_block) = (registry.id("stone").unwrap(), registry.id("crate").unwrap());
+        let mut world = VoxelWorld::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::default(), &registry);
+        world.set_block([1, 0, 2], stone, &registry);
+        world.set_block([2, 3, 1], crate_block, &registry);
+        world.set_block([5, 0, 0], stone, &registry);
+
+        let mapping = VoxBlockMapping { blocks: vec![(9, "stone".to_string())], fallback: None };
+        let data = export_vox(&world, [0, 0, 0], [3, 4, 2], &mapping, &registry);
+        // MagicaVoxel axes in the file, Z up.
+        let size: Vec<u32> = data[32..44]
+            .chunks_exact(4)
+            .map(|value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect();
+        assert_eq!(size, vec![4, 3, 5]);
+
+        let scene = parse_vox(&data).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        let model = &scene.models[0];
+        assert_eq!(model.size, [4, 5, 3]);
+        let mut voxels = model.voxels.clone();
+        voxels.sort();
+        assert_eq!(voxels, vec![([1, 0, 2], 9), ([2, 3, 1], 1)]);
+
+        let color = hash(0x0B0C, crate_block.0 as i32, 0, 0).to_le_bytes();
+        assert_eq!(scene.palette[1], [color[0], color[1], color[2], 0xFF]);
+        assert_eq!(scene.palette[9], default_palette()[9]);
+        assert_eq!(scene.palette[2], default_palette()[2]);
+    }
+}
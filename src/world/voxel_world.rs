@@ -0,0 +1,202 @@
+//! The loaded part of the voxel world.
+use std::collections::{HashMap, HashSet};
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{split_block_pos, Chunk, ChunkPos, CHUNK_SIZE};
+use crate::world::light::{self, LightChannel};
+
+/// Resource holding the loaded chunks. Chunks whose mesh is out of date are collected as dirty, see `take_dirty`,
+/// and unloaded ones as removed, see `take_removed`. Chunks made dirty by a block edit are also collected as
+/// edited, see `take_edited`, and stay modified until they are saved.
+#[derive(Debug, Default)]
+pub struct VoxelWorld {
+    chunks: HashMap<ChunkPos, Chunk>,
+    dirty: HashSet<ChunkPos>,
+    /// Chunks with blocks changed since they were generated or loaded.
+    modified: HashSet<ChunkPos>,
+    edited: HashSet<ChunkPos>,
+    /// Set while `set_block` runs, dirty chunks are then edited too.
+    editing: bool,
+    removed: Vec<ChunkPos>,
+    /// Chunks not loaded yet but about to be, see `set_expected`.
+    expected: HashSet<ChunkPos>,
+}
+
+impl VoxelWorld {
+    pub fn new() -> Self {
+        VoxelWorld::default()
+    }
+
+    // region - Chunks
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    pub fn contains_chunk(&self, pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    pub fn chunk_positions(&self) -> impl Iterator<Item = &ChunkPos> {
+        self.chunks.keys()
+    }
+
+    /// Adds a chunk, lights it and marks it and its neighbours for remeshing.
+    pub fn insert_chunk(&mut self, pos: ChunkPos, chunk: Chunk, registry: &BlockRegistry) {
+        self.removed.retain(|removed| *removed != pos);
+        self.expected.remove(&pos);
+        self.modified.remove(&pos);
+        self.chunks.insert(pos, chunk);
+        light::relight_chunk(self, pos, registry);
+        self.mark_chunk_and_neighbours(pos);
+    }
+
+    /// Replaces the chunks about to be inserted. The chunks right below them get no sky light until they arrive,
+    /// those below a chunk no longer expected are relit with open sky above.
+    pub fn set_expected<I>(&mut self, expected: I, registry: &BlockRegistry)
+    where
+        I: IntoIterator<Item = ChunkPos>,
+    {
+        let expected: HashSet<ChunkPos> = expected.into_iter().filter(|pos| !self.contains_chunk(*pos)).collect();
+        let dropped: Vec<ChunkPos> = self.expected.difference(&expected).cloned().collect();
+        self.expected = expected;
+        for pos in dropped {
+            let below = pos.offset(0, -1, 0);
+            if !self.contains_chunk(pos) && self.contains_chunk(below) {
+                light::relight_chunk(self, below, registry);
+                self.mark_chunk_and_neighbours(below);
+            }
+        }
+    }
+
+    pub fn is_expected(&self, pos: ChunkPos) -> bool {
+        self.expected.contains(&pos)
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) -> Option<Chunk> {
+        let chunk = self.chunks.remove(&pos)?;
+        self.dirty.remove(&pos);
+        self.edited.remove(&pos);
+        self.modified.remove(&pos);
+        self.removed.push(pos);
+        self.mark_chunk_and_neighbours(pos);
+        Some(chunk)
+    }
+
+    fn mark_chunk_and_neighbours(&mut self, pos: ChunkPos) {
+        self.mark_chunk_dirty(pos);
+        for &(dx, dy, dz) in &[(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+            self.mark_chunk_dirty(pos.offset(dx, dy, dz));
+        }
+    }
+
+    /// Only loaded chunks can be dirty.
+    pub fn mark_chunk_dirty(&mut self, pos: ChunkPos) {
+        if self.chunks.contains_key(&pos) {
+            self.dirty.insert(pos);
+            if self.editing {
+                self.edited.insert(pos);
+            }
+        }
+    }
+
+    /// Marks the chunk of a block dirty, and the neighbouring chunks whose faces touch it.
+    fn mark_block_dirty(&mut self, pos: [i32; 3]) {
+        let (chunk_pos, local) = split_block_pos(pos);
+        self.mark_chunk_dirty(chunk_pos);
+        let last = (CHUNK_SIZE - 1) as usize;
+        for axis in 0..3 {
+            let mut offset = [0, 0, 0];
+            if local[axis] == 0 {
+                offset[axis] = -1;
+            } else if local[axis] == last {
+                offset[axis] = 1;
+            } else {
+                continue;
+            }
+            self.mark_chunk_dirty(chunk_pos.offset(offset[0], offset[1], offset[2]));
+        }
+    }
+
+    /// Returns the chunks that changed since the last call.
+    pub fn take_dirty(&mut self) -> Vec<ChunkPos> {
+        self.dirty.drain().collect()
+    }
+
+    /// Returns the chunks changed by `set_block` since the last call, light included. They are among the dirty ones.
+    pub fn take_edited(&mut self) -> Vec<ChunkPos> {
+        self.edited.drain().collect()
+    }
+
+    pub fn is_modified(&self, pos: ChunkPos) -> bool {
+        self.modified.contains(&pos)
+    }
+
+    pub fn modified_chunks(&self) -> Vec<ChunkPos> {
+        self.modified.iter().cloned().collect()
+    }
+
+    pub fn mark_chunk_saved(&mut self, pos: ChunkPos) {
+        self.modified.remove(&pos);
+    }
+
+    /// Returns the chunks removed since the last call.
+    pub fn take_removed(&mut self) -> Vec<ChunkPos> {
+        std::mem::replace(&mut self.removed, Vec::new())
+    }
+
+    // endregion
+
+    // region - Blocks
+
+    /// Air outside of the loaded chunks.
+    pub fn block(&self, pos: [i32; 3]) -> BlockId {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        self.chunks
+            .get(&chunk_pos)
+            .map_or(BlockId::AIR, |chunk| chunk.block(x, y, z))
+    }
+
+    /// Changes a block and updates the light around it. Returns `false` if the chunk is not loaded.
+    pub fn set_block(&mut self, pos: [i32; 3], block: BlockId, registry: &BlockRegistry) -> bool {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        guard!(let Some(chunk) = self.chunks.get_mut(&chunk_pos) else { return false });
+        let previous = chunk.block(x, y, z);
+        if previous == block {
+            return true;
+        }
+        chunk.set_block(x, y, z, block);
+        self.modified.insert(chunk_pos);
+        self.editing = true;
+        self.mark_block_dirty(pos);
+        light::update_block(self, pos, previous, block, registry);
+        self.editing = false;
+        true
+    }
+
+    // endregion
+
+    // region - Light
+
+    /// `None` outside of the loaded chunks.
+    pub fn light(&self, pos: [i32; 3], channel: LightChannel) -> Option<u8> {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        self.chunks.get(&chunk_pos).map(|chunk| chunk.light(x, y, z, channel))
+    }
+
+    pub(crate) fn set_light(&mut self, pos: [i32; 3], channel: LightChannel, level: u8) {
+        let (chunk_pos, [x, y, z]) = split_block_pos(pos);
+        guard!(let Some(chunk) = self.chunks.get_mut(&chunk_pos) else { return });
+        if chunk.light(x, y, z, channel) == level {
+            return;
+        }
+        chunk.set_light(x, y, z, channel, level);
+        self.mark_block_dirty(pos);
+    }
+
+    pub(crate) fn chunk_mut(&mut self, pos: ChunkPos) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&pos)
+    }
+
+    // endregion
+}
@@ -0,0 +1,252 @@
+//! Biomes: climate driven surface blocks and grass colour.
+//!
+//! Temperature and humidity are low frequency noise. Every biome sits at a point of that climate space and
+//! weighs in with its distance to the climate of a column, so grass colours fade smoothly between biomes. Blocks
+//! can not be blended, they come from the heaviest biome of a slightly jittered climate, which frays the borders
+//! instead of drawing straight lines.
+use serde::{Deserialize, Serialize};
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+use crate::worldgen::generator::GenerationStage;
+use crate::worldgen::noise::{Fractal, Noise};
+use crate::worldgen::terrain::TerrainBlocks;
+
+// region - Biome
+
+/// Biome description.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Biome {
+    pub name: String,
+    /// Climate the biome is at home in, both in the `0.0..=1.0` range.
+    pub temperature: f32,
+    pub humidity: f32,
+    /// Block names of the top block and the layer below it.
+    pub surface: String,
+    pub filler: String,
+    /// Trees per column, used by the decoration stage.
+    pub tree_density: f32,
+    /// Colour of tinted blocks, in the `0.0..=1.0` range.
+    pub grass_color: [f32; 3],
+}
+
+impl Biome {
+    fn new(name: &str, temperature: f32, humidity: f32, surface: &str, filler: &str, tree_density: f32,
+           grass_color: [f32; 3]) -> Self {
+        Biome {
+            name: name.to_string(),
+            temperature,
+            humidity,
+            surface: surface.to_string(),
+            filler: filler.to_string(),
+            tree_density,
+            grass_color,
+        }
+    }
+
+    /// Biomes made of the built-in blocks.
+    pub fn defaults() -> Vec<Biome> {
+        vec![
+            Biome::new("plains", 0.55, 0.45, "grass", "dirt", 0.002, [0.57, 0.74, 0.35]),
+            Biome::new("forest", 0.50, 0.75, "grass", "dirt", 0.03, [0.38, 0.64, 0.25]),
+            Biome::new("savanna", 0.85, 0.20, "grass", "dirt", 0.004, [0.75, 0.72, 0.36]),
+            Biome::new("swamp", 0.70, 0.95, "grass", "dirt", 0.01, [0.42, 0.51, 0.30]),
+            Biome::new("tundra", 0.10, 0.40, "grass", "dirt", 0.001, [0.50, 0.70, 0.62]),
+            Biome::new("badlands", 0.95, 0.05, "dirt", "dirt", 0.0, [0.78, 0.62, 0.40]),
+            Biome::new("mountains", 0.25, 0.15, "stone", "stone", 0.0, [0.54, 0.68, 0.55]),
+        ]
+    }
+}
+
+/// Climate noise settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeSettings {
+    pub biomes: Vec<Biome>,
+    pub climate_noise: Fractal,
+    /// Width of the colour transitions, in climate units.
+    pub blend: f32,
+    /// Climate offset applied before picking blocks, in climate units.
+    pub jitter: f32,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        BiomeSettings {
+            biomes: Biome::defaults(),
+            climate_noise: Fractal {
+                octaves: 3,
+                frequency: 1.0 / 512.0,
+                ..Fractal::default()
+            },
+            blend: 0.08,
+            jitter: 0.03,
+        }
+    }
+}
+
+// endregion
+
+// region - Biome Map
+
+/// Biome with its block names resolved.
+#[derive(Debug, Clone)]
+struct ResolvedBiome {
+    surface: BlockId,
+    filler: BlockId,
+}
+
+/// Climate at a column, and what it turns into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiomeSample {
+    /// Index of the biome blocks come from.
+    pub biome: usize,
+    pub grass_color: [f32; 3],
+}
+
+/// Seeded lookup of the biome of any column.
+#[derive(Debug, Clone)]
+pub struct BiomeMap {
+    settings: BiomeSettings,
+    resolved: Vec<ResolvedBiome>,
+    temperature: Noise,
+    humidity: Noise,
+    jitter: Noise,
+}
+
+impl BiomeMap {
+    /// Unknown block names fall back to the terrain blocks.
+    pub fn new(seed: u64, settings: BiomeSettings, registry: &BlockRegistry, terrain: TerrainBlocks) -> Self {
+        let resolved = settings
+            .biomes
+            .iter()
+            .map(|biome| ResolvedBiome {
+                surface: registry.id(&biome.surface).unwrap_or(terrain.surface),
+                filler: registry.id(&biome.filler).unwrap_or(terrain.filler),
+            })
+            .collect();
+        let noise = Noise::new(seed);
+        BiomeMap {
+            settings,
+            resolved,
+            temperature: noise.derive(4),
+            humidity: noise.derive(5),
+            jitter: noise.derive(6),
+        }
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.settings.biomes
+    }
+
+    /// Temperature and humidity of a column, in the `0.0..=1.0` range.
+    pub fn climate(&self, x: i32, z: i32) -> (f32, f32) {
+        let noise = &self.settings.climate_noise;
+        let temperature = noise.sample2(&self.temperature, x as f64, z as f64);
+        let humidity = noise.sample2(&self.humidity, x as f64, z as f64);
+        (
+            (temperature as f32 * 0.5 + 0.5).max(0.0).min(1.0),
+            (humidity as f32 * 0.5 + 0.5).max(0.0).min(1.0),
+        )
+    }
+
+    /// Logarithm of the blend weight of a biome.
+    fn exponent(&self, biome: &Biome, temperature: f32, humidity: f32) -> f32 {
+        let dt = biome.temperature - temperature;
+        let dh = biome.humidity - humidity;
+        -(dt * dt + dh * dh) / (self.settings.blend * self.settings.blend)
+    }
+
+    pub fn sample(&self, x: i32, z: i32) -> BiomeSample {
+        let (temperature, humidity) = self.climate(x, z);
+
+        // Weights are relative to the closest biome, so the total is at least 1 however far the climate is from
+        // every biome and the colour never falls back to a single biome.
+        let max_exponent = self
+            .settings
+            .biomes
+            .iter()
+            .map(|biome| self.exponent(biome, temperature, humidity))
+            .fold(std::f32::NEG_INFINITY, f32::max);
+        let mut total = 0.0;
+        let mut color = [0.0; 3];
+        for biome in &self.settings.biomes {
+            let weight = (self.exponent(biome, temperature, humidity) - max_exponent).exp();
+            total += weight;
+            for channel in 0..3 {
+                color[channel] += biome.grass_color[channel] * weight;
+            }
+        }
+
+        let jitter = self.settings.jitter;
+        let jittered_temperature = temperature + self.jitter.sample2(x as f64 / 6.0, z as f64 / 6.0) as f32 * jitter;
+        let jittered_humidity = humidity + self.jitter.sample2(z as f64 / 6.0, x as f64 / 6.0) as f32 * jitter;
+        let nearest = |biome: &Biome| {
+            let dt = biome.temperature - jittered_temperature;
+            let dh = biome.humidity - jittered_humidity;
+            dt * dt + dh * dh
+        };
+        let biome = (0..self.settings.biomes.len())
+            .min_by(|a, b| {
+                let (a, b) = (nearest(&self.settings.biomes[*a]), nearest(&self.settings.biomes[*b]));
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0);
+
+        if total > 0.0 {
+            color.iter_mut().for_each(|channel| *channel /= total);
+        } else {
+            // No biome at all.
+            color = [1.0; 3];
+        }
+        BiomeSample { biome, grass_color: color }
+    }
+}
+
+// endregion
+
+// region - Stage
+
+/// Generation stage swapping the terrain surface and filler blocks for the ones of the local biome, and colouring
+/// the grass. Runs right after the terrain.
+#[derive(Debug, Clone)]
+pub struct BiomeStage {
+    map: BiomeMap,
+    terrain: TerrainBlocks,
+}
+
+impl BiomeStage {
+    pub fn new(map: BiomeMap, terrain: TerrainBlocks) -> Self {
+        BiomeStage { map, terrain }
+    }
+}
+
+impl GenerationStage for BiomeStage {
+    fn apply(&self, pos: ChunkPos, chunk: &mut Chunk) {
+        let origin = pos.origin();
+        let size = CHUNK_SIZE as usize;
+        for z in 0..size {
+            for x in 0..size {
+                let sample = self.map.sample(origin[0] + x as i32, origin[2] + z as i32);
+                let color = sample.grass_color;
+                chunk.set_tint(x, z, [
+                    (color[0] * 255.0).round() as u8,
+                    (color[1] * 255.0).round() as u8,
+                    (color[2] * 255.0).round() as u8,
+                ]);
+
+                guard!(let Some(biome) = self.map.resolved.get(sample.biome) else { continue });
+                for y in 0..size {
+                    let block = chunk.block(x, y, z);
+                    if block == self.terrain.surface {
+                        chunk.set_block(x, y, z, biome.surface);
+                    } else if block == self.terrain.filler {
+                        chunk.set_block(x, y, z, biome.filler);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// endregion
@@ -0,0 +1,235 @@
+//! Cave carving: 3D noise caverns and worm tunnels.
+//!
+//! Worms start in cubic regions of `REGION_SIZE` blocks, each region seeding its own worms from the world seed and
+//! its position. A worm never travels further than `REGION_SIZE` from its start, so a chunk only has to replay the
+//! worms of the regions around its own to carve every tunnel crossing it, the same way its neighbours do.
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use crate::world::block::BlockId;
+use crate::world::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+use crate::worldgen::generator::GenerationStage;
+use crate::worldgen::noise::{hash, Fractal, Noise, Sequence};
+
+/// Edge length of a worm region, in blocks.
+pub const REGION_SIZE: i32 = CHUNK_SIZE * 4;
+
+/// Shape and amount of caves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaveSettings {
+    /// Caverns and worms only start below this height.
+    pub max_height: i32,
+    pub cavern_noise: Fractal,
+    /// Noise value above which caverns are carved, `1.0` disables them.
+    pub cavern_threshold: f64,
+    /// Vertical noise scale, above `1.0` flattens the caverns.
+    pub cavern_squash: f64,
+    pub worms_per_region: u32,
+    /// Length range of a worm, in steps of one block.
+    pub worm_length: (u32, u32),
+    /// Radius range of a worm tunnel, in blocks.
+    pub worm_radius: (f64, f64),
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        CaveSettings {
+            max_height: -4,
+            cavern_noise: Fractal {
+                octaves: 2,
+                frequency: 1.0 / 32.0,
+                ..Fractal::default()
+            },
+            cavern_threshold: 0.45,
+            cavern_squash: 2.0,
+            worms_per_region: 3,
+            worm_length: (24, 52),
+            worm_radius: (1.5, 3.0),
+        }
+    }
+}
+
+/// Generation stage replacing blocks with air along caverns and tunnels.
+#[derive(Debug, Clone)]
+pub struct CaveCarver {
+    settings: CaveSettings,
+    seed: u64,
+    cavern_noise: Noise,
+    worm_noise: Noise,
+}
+
+impl CaveCarver {
+    pub fn new(seed: u64, settings: CaveSettings) -> Self {
+        let noise = Noise::new(seed);
+        let max_reach = settings.worm_length.1 as f64 + settings.worm_radius.1 * 1.3 + 1.0;
+        if max_reach >= REGION_SIZE as f64 {
+            log::warn!("Cave worms reach {} blocks, only {} are carved across regions", max_reach, REGION_SIZE);
+        }
+        CaveCarver {
+            settings,
+            seed: hash(seed, 0, 0xCA7E, 0),
+            cavern_noise: noise.derive(2),
+            worm_noise: noise.derive(3),
+        }
+    }
+
+    /// Carves a chunk sized block of the world starting at `origin`, which needs not be a chunk origin.
+    fn carve(&self, origin: [i32; 3], chunk: &mut Chunk) {
+        self.carve_caverns(origin, chunk);
+        self.carve_worms(origin, chunk);
+    }
+
+    // region - Caverns
+
+    fn carve_caverns(&self, origin: [i32; 3], chunk: &mut Chunk) {
+        if self.settings.cavern_threshold >= 1.0 {
+            return;
+        }
+        for y in 0..CHUNK_SIZE {
+            let world_y = origin[1] + y;
+            if world_y > self.settings.max_height {
+                break;
+            }
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let value = self.settings.cavern_noise.sample3(
+                        &self.cavern_noise,
+                        (origin[0] + x) as f64,
+                        world_y as f64 * self.settings.cavern_squash,
+                        (origin[2] + z) as f64,
+                    );
+                    if value > self.settings.cavern_threshold {
+                        chunk.set_block(x as usize, y as usize, z as usize, BlockId::AIR);
+                    }
+                }
+            }
+        }
+    }
+
+    // endregion
+
+    // region - Worms
+
+    fn carve_worms(&self, origin: [i32; 3], chunk: &mut Chunk) {
+        let region = [
+            origin[0].div_euclid(REGION_SIZE),
+            origin[1].div_euclid(REGION_SIZE),
+            origin[2].div_euclid(REGION_SIZE),
+        ];
+        for rx in region[0] - 1..=region[0] + 1 {
+            for ry in region[1] - 1..=region[1] + 1 {
+                for rz in region[2] - 1..=region[2] + 1 {
+                    self.carve_region_worms([rx, ry, rz], origin, chunk);
+                }
+            }
+        }
+    }
+
+    fn carve_region_worms(&self, region: [i32; 3], origin: [i32; 3], chunk: &mut Chunk) {
+        let region_origin = [region[0] * REGION_SIZE, region[1] * REGION_SIZE, region[2] * REGION_SIZE];
+        if region_origin[1] > self.settings.max_height {
+            return;
+        }
+        let mut sequence = Sequence::new(hash(self.seed, region[0], region[1], region[2]));
+        let count = sequence.below(self.settings.worms_per_region + 1);
+        for worm in 0..count {
+            let mut position = [
+                region_origin[0] as f64 + sequence.next_f64() * REGION_SIZE as f64,
+                region_origin[1] as f64 + sequence.next_f64() * REGION_SIZE as f64,
+                region_origin[2] as f64 + sequence.next_f64() * REGION_SIZE as f64,
+            ];
+            let mut yaw = sequence.next_f64() * 2.0 * PI;
+            let mut pitch = sequence.range(-0.4, 0.4);
+            let (min_length, max_length) = self.settings.worm_length;
+            let length = min_length + sequence.below(max_length.saturating_sub(min_length) + 1);
+            let radius = sequence.range(self.settings.worm_radius.0, self.settings.worm_radius.1);
+            let noise = self.worm_noise.derive(sequence.next_u64() as u32 ^ worm);
+
+            for step in 0..length {
+                let t = step as f64 * 0.08;
+                let step_radius = radius * (1.0 + 0.3 * noise.sample2(t, 11.5));
+                carve_sphere(chunk, origin, position, step_radius);
+
+                yaw += noise.sample2(t, 0.5) * 0.35;
+                pitch = (pitch * 0.9 + noise.sample2(t, 5.5) * 0.15).max(-1.0).min(1.0);
+                position[0] += yaw.cos() * pitch.cos();
+                position[1] += pitch.sin();
+                position[2] += yaw.sin() * pitch.cos();
+            }
+        }
+    }
+
+    // endregion
+}
+
+/// Carves the part of a sphere that lies inside the chunk.
+fn carve_sphere(chunk: &mut Chunk, origin: [i32; 3], center: [f64; 3], radius: f64) {
+    let mut min = [0; 3];
+    let mut max = [0; 3];
+    for axis in 0..3 {
+        let local = center[axis] - origin[axis] as f64;
+        min[axis] = ((local - radius).floor() as i32).max(0);
+        max[axis] = ((local + radius).ceil() as i32).min(CHUNK_SIZE - 1);
+        if min[axis] > max[axis] {
+            return;
+        }
+    }
+    let radius2 = radius * radius;
+    for y in min[1]..=max[1] {
+        for z in min[2]..=max[2] {
+            for x in min[0]..=max[0] {
+                let dx = (origin[0] + x) as f64 + 0.5 - center[0];
+                let dy = (origin[1] + y) as f64 + 0.5 - center[1];
+                let dz = (origin[2] + z) as f64 + 0.5 - center[2];
+                if dx * dx + dy * dy + dz * dz <= radius2 {
+                    chunk.set_block(x as usize, y as usize, z as usize, BlockId::AIR);
+                }
+            }
+        }
+    }
+}
+
+impl GenerationStage for CaveCarver {
+    fn apply(&self, pos: ChunkPos, chunk: &mut Chunk) {
+        self.carve(pos.origin(), chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn carved(carver: &CaveCarver, origin: [i32; 3]) -> Chunk {
+        let mut chunk = Chunk::filled(BlockId(1));
+        carver.carve(origin, &mut chunk);
+        chunk
+    }
+
+    #[test]
+    fn tunnels_continue_across_chunk_borders() {
+        let carver = CaveCarver::new(7, CaveSettings { worms_per_region: 8, ..CaveSettings::default() });
+        let size = CHUNK_SIZE as usize;
+        let half = size / 2;
+        let mut carved_on_faces = 0;
+        for cx in -4..4 {
+            let origin = [cx * CHUNK_SIZE, -3 * CHUNK_SIZE, 0];
+            let left = carved(&carver, origin);
+            let right = carved(&carver, [origin[0] + CHUNK_SIZE, origin[1], origin[2]]);
+            // Block of the world centered on the face shared by the two chunks.
+            let across = carved(&carver, [origin[0] + half as i32, origin[1], origin[2]]);
+            for y in 0..size {
+                for z in 0..size {
+                    for x in 0..size {
+                        let expected = if x < half { left.block(x + half, y, z) } else { right.block(x - half, y, z) };
+                        assert_eq!(across.block(x, y, z), expected, "chunk {} at {:?}", cx, [x, y, z]);
+                    }
+                    if left.block(size - 1, y, z).is_air() {
+                        carved_on_faces += 1;
+                    }
+                }
+            }
+        }
+        assert!(carved_on_faces > 0, "no cave crosses the tested faces");
+    }
+}
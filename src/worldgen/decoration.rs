@@ -0,0 +1,224 @@
+//! Decoration: structures placed on the terrain surface.
+//!
+//! Placements are decided per cell of `CHUNK_SIZE` columns from the seed and the cell position only. A chunk
+//! replays the placements of the cells around its own and pastes the part of each structure that falls inside
+//! it, so a structure crossing a border is complete whichever side is generated first.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::world::block::BlockRegistry;
+use crate::world::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+use crate::worldgen::biome::BiomeMap;
+use crate::worldgen::generator::GenerationStage;
+use crate::worldgen::noise::{hash, Sequence};
+use crate::worldgen::structure::{ResolvedStructure, StructureTemplate};
+use crate::worldgen::terrain::HeightmapGenerator;
+
+// region - Settings
+
+/// Where and how often a structure is placed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecorationRule {
+    /// Template asset path, relative to the assets directory.
+    pub template: String,
+    /// Placement attempts per cell.
+    pub attempts: u32,
+    /// Success chance of an attempt, `None` follows the tree density of the biome.
+    pub chance: Option<f32>,
+    /// Biome names the structure appears in, empty for everywhere.
+    pub biomes: Vec<String>,
+    /// Blocks the structure is sunk into the ground.
+    pub sink: i32,
+}
+
+impl Default for DecorationRule {
+    fn default() -> Self {
+        DecorationRule {
+            template: String::new(),
+            attempts: 1,
+            chance: Some(1.0),
+            biomes: Vec::new(),
+            sink: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecorationSettings {
+    pub rules: Vec<DecorationRule>,
+}
+
+impl Default for DecorationSettings {
+    fn default() -> Self {
+        DecorationSettings {
+            rules: vec![
+                DecorationRule {
+                    template: "structure/tree.ron".to_string(),
+                    attempts: 8,
+                    chance: None,
+                    ..DecorationRule::default()
+                },
+                DecorationRule {
+                    template: "structure/boulder.ron".to_string(),
+                    attempts: 2,
+                    chance: Some(0.08),
+                    sink: 1,
+                    ..DecorationRule::default()
+                },
+                DecorationRule {
+                    template: "structure/ruin.ron".to_string(),
+                    attempts: 1,
+                    chance: Some(0.03),
+                    biomes: vec!["plains".to_string(), "savanna".to_string(), "badlands".to_string()],
+                    ..DecorationRule::default()
+                },
+            ],
+        }
+    }
+}
+
+// endregion
+
+// region - Stage
+
+struct PreparedRule {
+    rule: DecorationRule,
+    structure: ResolvedStructure,
+    /// Lowest and highest block offsets of the structure along Y.
+    heights: (i32, i32),
+    biomes: Vec<usize>,
+    seed: u64,
+}
+
+impl PreparedRule {
+    /// Whether a structure anchored at the height `anchor` reaches into the chunk at `origin`, along Y.
+    fn reaches_height(&self, anchor: (i32, i32), origin: [i32; 3]) -> bool {
+        anchor.0 + self.heights.0 < origin[1] + CHUNK_SIZE && anchor.1 + self.heights.1 >= origin[1]
+    }
+}
+
+/// Generation stage pasting the structures of the decoration rules onto the terrain surface.
+pub struct DecorationStage {
+    rules: Vec<PreparedRule>,
+    terrain: HeightmapGenerator,
+    biomes: BiomeMap,
+}
+
+impl DecorationStage {
+    /// Rules whose template is missing from `templates` are skipped.
+    pub fn new(
+        seed: u64, settings: DecorationSettings, templates: &HashMap<String, StructureTemplate>,
+        registry: &BlockRegistry, terrain: HeightmapGenerator, biomes: BiomeMap,
+    ) -> Self {
+        let rules = settings
+            .rules
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, rule)| {
+                guard!(let Some(template) = templates.get(&rule.template) else {
+                    log::warn!("Decoration template `{}` is not loaded", rule.template);
+                    return None;
+                });
+                let allowed = rule
+                    .biomes
+                    .iter()
+                    .filter_map(|name| biomes.biomes().iter().position(|biome| &biome.name == name))
+                    .collect();
+                let structure = ResolvedStructure::new(template, registry);
+                let offsets = || structure.blocks.iter().map(|(offset, _)| offset[1]);
+                let heights = (offsets().min().unwrap_or(0), offsets().max().unwrap_or(0));
+                Some(PreparedRule {
+                    structure,
+                    heights,
+                    biomes: allowed,
+                    seed: hash(seed, index as i32, 0xDEC0, 0),
+                    rule,
+                })
+            })
+            .collect();
+        DecorationStage { rules, terrain, biomes }
+    }
+
+    fn apply_cell(&self, rule: &PreparedRule, cell: (i32, i32), origin: [i32; 3], chunk: &mut Chunk) {
+        let mut sequence = Sequence::new(hash(rule.seed, cell.0, 0, cell.1));
+        for _ in 0..rule.rule.attempts {
+            // Always draw the same numbers, so attempts stay aligned whatever is rejected.
+            let x = cell.0 * CHUNK_SIZE + sequence.below(CHUNK_SIZE as u32) as i32;
+            let z = cell.1 * CHUNK_SIZE + sequence.below(CHUNK_SIZE as u32) as i32;
+            let roll = sequence.next_f64() as f32;
+            let rotation = sequence.below(4);
+
+            // Cheapest rejections first, the biome is only sampled for structures reaching into the chunk.
+            let reach = rule.structure.reach;
+            let outside = |value: i32, start: i32| value + reach < start || value - reach >= start + CHUNK_SIZE;
+            if outside(x, origin[0]) || outside(z, origin[2]) {
+                continue;
+            }
+            let anchor_y = self.terrain.surface_height(x, z) + 1 - rule.rule.sink;
+            if !rule.reaches_height((anchor_y, anchor_y), origin) {
+                continue;
+            }
+            let sample = self.biomes.sample(x, z);
+            if !rule.biomes.is_empty() && !rule.biomes.contains(&sample.biome) {
+                continue;
+            }
+            let chance = match rule.rule.chance {
+                Some(chance) => chance,
+                None => {
+                    let density = self.biomes.biomes().get(sample.biome).map_or(0.0, |biome| biome.tree_density);
+                    let columns = (CHUNK_SIZE * CHUNK_SIZE) as f32;
+                    density * columns / rule.rule.attempts.max(1) as f32
+                }
+            };
+            if roll >= chance {
+                continue;
+            }
+
+            paste(&rule.structure, [x, anchor_y, z], rotation, origin, chunk);
+        }
+    }
+}
+
+/// Writes the blocks of a structure that fall inside the chunk, rotated by quarter turns around Y.
+fn paste(structure: &ResolvedStructure, anchor: [i32; 3], rotation: u32, origin: [i32; 3], chunk: &mut Chunk) {
+    for (offset, block) in &structure.blocks {
+        let (dx, dz) = match rotation {
+            1 => (-offset[2], offset[0]),
+            2 => (-offset[0], -offset[2]),
+            3 => (offset[2], -offset[0]),
+            _ => (offset[0], offset[2]),
+        };
+        let local = [
+            anchor[0] + dx - origin[0],
+            anchor[1] + offset[1] - origin[1],
+            anchor[2] + dz - origin[2],
+        ];
+        if local.iter().all(|value| *value >= 0 && *value < CHUNK_SIZE) {
+            chunk.set_block(local[0] as usize, local[1] as usize, local[2] as usize, *block);
+        }
+    }
+}
+
+impl GenerationStage for DecorationStage {
+    fn apply(&self, pos: ChunkPos, chunk: &mut Chunk) {
+        let origin = pos.origin();
+        let (lowest, highest) = self.terrain.surface_range();
+        for rule in &self.rules {
+            // Skips chunks no surface of the world can put the structure in, most of those above and below ground.
+            let lift = 1 - rule.rule.sink;
+            if !rule.reaches_height((lowest + lift, highest + lift), origin) {
+                continue;
+            }
+            let cells = 1 + rule.structure.reach / CHUNK_SIZE;
+            for cell_x in pos.x - cells..=pos.x + cells {
+                for cell_z in pos.z - cells..=pos.z + cells {
+                    self.apply_cell(rule, (cell_x, cell_z), origin, chunk);
+                }
+            }
+        }
+    }
+}
+
+// endregion
@@ -0,0 +1,62 @@
+//! World generation entry point.
+use std::sync::Arc;
+
+use crate::world::chunk::{Chunk, ChunkPos};
+
+/// Produces the blocks of any chunk of the world on demand.
+///
+/// Implementations must be deterministic: the same generator settings and position always give the same chunk,
+/// whatever the order the chunks are generated in.
+pub trait WorldGenerator: Send + Sync {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk;
+}
+
+/// Resource with the generator of the current world, shared with the generation jobs. Nothing is streamed in
+/// until it is inserted.
+#[derive(Clone)]
+pub struct ActiveGenerator(pub Arc<dyn WorldGenerator>);
+
+impl ActiveGenerator {
+    pub fn new<G: WorldGenerator + 'static>(generator: G) -> Self {
+        ActiveGenerator(Arc::new(generator))
+    }
+}
+
+// region - Pipeline
+
+/// Step of a `GeneratorPipeline`, writes its features into a chunk produced by the previous stages.
+///
+/// Like generators, stages must only depend on their settings and the chunk position.
+pub trait GenerationStage: Send + Sync {
+    fn apply(&self, pos: ChunkPos, chunk: &mut Chunk);
+}
+
+/// Generator running its stages in order on an empty chunk.
+#[derive(Default)]
+pub struct GeneratorPipeline {
+    stages: Vec<Box<dyn GenerationStage>>,
+}
+
+impl GeneratorPipeline {
+    pub fn new() -> Self {
+        GeneratorPipeline::default()
+    }
+
+    /// Appends a stage, run after the ones already added.
+    pub fn with_stage<S: GenerationStage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+}
+
+impl WorldGenerator for GeneratorPipeline {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
+        let mut chunk = Chunk::default();
+        for stage in &self.stages {
+            stage.apply(pos, &mut chunk);
+        }
+        chunk
+    }
+}
+
+// endregion
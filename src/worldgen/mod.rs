@@ -0,0 +1,7 @@
+pub mod biome;
+pub mod caves;
+pub mod decoration;
+pub mod generator;
+pub mod noise;
+pub mod structure;
+pub mod terrain;
\ No newline at end of file
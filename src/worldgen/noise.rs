@@ -0,0 +1,230 @@
+//! Seeded gradient noise.
+//!
+//! Gradients come from hashing the lattice coordinates with the seed instead of a shuffled permutation table, so
+//! the noise is unbounded, needs no setup and gives the same values on every run.
+use serde::{Deserialize, Serialize};
+
+/// Hashes integer coordinates with a seed, splitmix64 finalizer.
+pub fn hash(seed: u64, x: i32, y: i32, z: i32) -> u64 {
+    let mut h = seed
+        ^ (x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as i64 as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 31)
+}
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const GRADIENTS_2D: [[f64; 2]; 8] = [
+    [1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0],
+    [0.7071, 0.7071], [-0.7071, 0.7071], [0.7071, -0.7071], [-0.7071, -0.7071],
+];
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const GRADIENTS_3D: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+#[inline]
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline]
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// region - Noise
+
+/// Perlin noise in the `-1.0..=1.0` range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        Noise { seed }
+    }
+
+    /// Independent noise derived from this one, for octaves and other layers.
+    pub fn derive(&self, salt: u32) -> Self {
+        Noise::new(hash(self.seed, salt as i32, 0x5EED, 0))
+    }
+
+    pub fn sample2(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (ix, iy) = (x0 as i32, y0 as i32);
+        let dot = |gx: i32, gy: i32, dx: f64, dy: f64| {
+            let g = GRADIENTS_2D[(hash(self.seed, gx, gy, 0) & 7) as usize];
+            g[0] * dx + g[1] * dy
+        };
+        let (u, v) = (fade(fx), fade(fy));
+        let bottom = lerp(dot(ix, iy, fx, fy), dot(ix + 1, iy, fx - 1.0, fy), u);
+        let top = lerp(dot(ix, iy + 1, fx, fy - 1.0), dot(ix + 1, iy + 1, fx - 1.0, fy - 1.0), u);
+        (lerp(bottom, top, v) * std::f64::consts::SQRT_2).max(-1.0).min(1.0)
+    }
+
+    pub fn sample3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i32, y0 as i32, z0 as i32);
+        let dot = |gx: i32, gy: i32, gz: i32, dx: f64, dy: f64, dz: f64| {
+            let g = GRADIENTS_3D[(hash(self.seed, gx, gy, gz) % 12) as usize];
+            g[0] * dx + g[1] * dy + g[2] * dz
+        };
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let near_bottom = lerp(dot(ix, iy, iz, fx, fy, fz), dot(ix + 1, iy, iz, fx - 1.0, fy, fz), u);
+        let near_top = lerp(
+            dot(ix, iy + 1, iz, fx, fy - 1.0, fz),
+            dot(ix + 1, iy + 1, iz, fx - 1.0, fy - 1.0, fz),
+            u,
+        );
+        let far_bottom = lerp(
+            dot(ix, iy, iz + 1, fx, fy, fz - 1.0),
+            dot(ix + 1, iy, iz + 1, fx - 1.0, fy, fz - 1.0),
+            u,
+        );
+        let far_top = lerp(
+            dot(ix, iy + 1, iz + 1, fx, fy - 1.0, fz - 1.0),
+            dot(ix + 1, iy + 1, iz + 1, fx - 1.0, fy - 1.0, fz - 1.0),
+            u,
+        );
+        lerp(lerp(near_bottom, near_top, v), lerp(far_bottom, far_top, v), w)
+            .max(-1.0)
+            .min(1.0)
+    }
+}
+
+// endregion
+
+// region - Fractal
+
+/// Fractal Brownian motion settings: octaves of noise summed with growing frequency and shrinking amplitude.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fractal {
+    pub octaves: u32,
+    /// Frequency of the first octave, in cycles per block.
+    pub frequency: f64,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves.
+    pub persistence: f64,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Fractal {
+            octaves: 4,
+            frequency: 1.0 / 64.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+}
+
+impl Fractal {
+    /// Sum of the octaves, normalized to the `-1.0..=1.0` range.
+    pub fn sample2(&self, noise: &Noise, x: f64, y: f64) -> f64 {
+        self.sum(|octave, frequency| noise.derive(octave).sample2(x * frequency, y * frequency))
+    }
+
+    /// Sum of the octaves, normalized to the `-1.0..=1.0` range.
+    pub fn sample3(&self, noise: &Noise, x: f64, y: f64, z: f64) -> f64 {
+        self.sum(|octave, frequency| noise.derive(octave).sample3(x * frequency, y * frequency, z * frequency))
+    }
+
+    fn sum<F: Fn(u32, f64) -> f64>(&self, sample: F) -> f64 {
+        let mut total = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for octave in 0..self.octaves.max(1) {
+            total += sample(octave, frequency) * amplitude;
+            norm += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        total / norm
+    }
+}
+
+// endregion
+
+// region - Sequence
+
+/// Deterministic random sequence for placement decisions, splitmix64.
+///
+/// Unlike the `rand` generators its output is fixed, so worlds stay the same across dependency updates.
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    state: u64,
+}
+
+impl Sequence {
+    pub fn new(seed: u64) -> Self {
+        Sequence { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in the `0.0..1.0` range.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in the `0..bound` range, `0` when `bound` is `0`.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next_u64() % bound as u64) as u32
+    }
+
+    /// Uniform in the `min..max` range.
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+}
+
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(noise: &Noise, fractal: &Fractal) -> Vec<f64> {
+        (0..64)
+            .map(|i| {
+                let (x, z) = (i as f64 * 7.3 - 200.0, i as f64 * -3.1 + 50.0);
+                fractal.sample2(noise, x, z) + fractal.sample3(noise, x, i as f64 * 1.7, z)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_noise() {
+        let fractal = Fractal::default();
+        assert_eq!(samples(&Noise::new(42), &fractal), samples(&Noise::new(42), &fractal));
+        assert_eq!(samples(&Noise::new(42).derive(3), &fractal), samples(&Noise::new(42).derive(3), &fractal));
+    }
+
+    #[test]
+    fn other_seed_gives_other_noise() {
+        let fractal = Fractal::default();
+        assert_ne!(samples(&Noise::new(42), &fractal), samples(&Noise::new(43), &fractal));
+        assert_ne!(samples(&Noise::new(42).derive(1), &fractal), samples(&Noise::new(42).derive(2), &fractal));
+    }
+}
@@ -0,0 +1,105 @@
+//! Voxel structure templates, loaded as RON assets.
+use amethyst::assets::{Asset, Handle};
+use amethyst::ecs::VecStorage;
+use serde::{Deserialize, Serialize};
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::voxel_world::VoxelWorld;
+
+// region - Template Asset
+
+/// Multi-block structure such as a tree or a ruin.
+///
+/// ```ron
+/// (
+///     name: "stump",
+///     anchor: (0, 0, 0),
+///     palette: [('#', "log")],
+///     layers: [["#"], ["#"]],
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructureTemplate {
+    pub name: String,
+    /// Template position placed right above the surface block.
+    pub anchor: [i32; 3],
+    /// Block names of the characters used in the layers.
+    pub palette: Vec<(char, String)>,
+    /// Horizontal slices from bottom to top, rows along Z and characters along X. Spaces and unknown characters
+    /// leave the world untouched, `.` carves air.
+    pub layers: Vec<Vec<String>>,
+}
+
+impl Asset for StructureTemplate {
+    const NAME: &'static str = "worldgen::StructureTemplate";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+// endregion
+
+// region - Resolved Template
+
+/// Template with the block names resolved, blocks relative to the anchor.
+#[derive(Debug, Clone)]
+pub struct ResolvedStructure {
+    pub blocks: Vec<([i32; 3], BlockId)>,
+    /// Farthest horizontal distance of a block from the anchor, bounds the chunks a placement can reach.
+    pub reach: i32,
+}
+
+impl ResolvedStructure {
+    /// Blocks named in the palette but not registered are skipped with a warning.
+    pub fn new(template: &StructureTemplate, registry: &BlockRegistry) -> Self {
+        let palette: Vec<(char, BlockId)> = template
+            .palette
+            .iter()
+            .filter_map(|(symbol, name)| match registry.id(name) {
+                Some(id) => Some((*symbol, id)),
+                None => {
+                    log::warn!("Structure `{}` uses unknown block `{}`", template.name, name);
+                    None
+                }
+            })
+            .collect();
+
+        let mut blocks = Vec::new();
+        let mut reach = 0;
+        for (y, layer) in template.layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, symbol) in row.chars().enumerate() {
+                    let block = match symbol {
+                        '.' => BlockId::AIR,
+                        _ => match palette.iter().find(|(known, _)| *known == symbol) {
+                            Some((_, block)) => *block,
+                            None => continue,
+                        },
+                    };
+                    let offset = [
+                        x as i32 - template.anchor[0],
+                        y as i32 - template.anchor[1],
+                        z as i32 - template.anchor[2],
+                    ];
+                    reach = reach.max(offset[0].abs()).max(offset[2].abs());
+                    blocks.push((offset, block));
+                }
+            }
+        }
+        ResolvedStructure { blocks, reach }
+    }
+
+    /// Writes the blocks into the loaded chunks of the world, relative to `anchor`. Returns the number of blocks
+    /// written, blocks in unloaded chunks are skipped.
+    pub fn place(&self, world: &mut VoxelWorld, anchor: [i32; 3], registry: &BlockRegistry) -> usize {
+        let mut written = 0;
+        for (offset, block) in &self.blocks {
+            let pos = [anchor[0] + offset[0], anchor[1] + offset[1], anchor[2] + offset[2]];
+            if world.set_block(pos, *block, registry) {
+                written += 1;
+            }
+        }
+        written
+    }
+}
+
+// endregion
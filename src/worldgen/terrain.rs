@@ -0,0 +1,145 @@
+//! Heightmap terrain.
+use serde::{Deserialize, Serialize};
+
+use crate::world::block::{BlockId, BlockRegistry};
+use crate::world::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+use crate::worldgen::generator::{GenerationStage, WorldGenerator};
+use crate::worldgen::noise::{Fractal, Noise};
+
+/// Shape of the heightmap terrain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainSettings {
+    /// Average surface height, in blocks.
+    pub base_height: i32,
+    /// Largest distance of the surface from `base_height`, in blocks.
+    pub amplitude: f32,
+    /// Depth of the filler layer below the surface block, stone starts under it.
+    pub filler_depth: i32,
+    pub height_noise: Fractal,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        TerrainSettings {
+            base_height: 0,
+            amplitude: 16.0,
+            filler_depth: 3,
+            height_noise: Fractal::default(),
+        }
+    }
+}
+
+/// Blocks the terrain layers are made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainBlocks {
+    pub surface: BlockId,
+    pub filler: BlockId,
+    pub stone: BlockId,
+}
+
+impl TerrainBlocks {
+    /// Grass, dirt and stone of the registry, missing ones are replaced by air.
+    pub fn from_registry(registry: &BlockRegistry) -> Self {
+        let id = |name: &str| registry.id(name).unwrap_or(BlockId::AIR);
+        TerrainBlocks {
+            surface: id("grass"),
+            filler: id("dirt"),
+            stone: id("stone"),
+        }
+    }
+}
+
+/// Default generator: layered columns whose height is fractal noise of the world seed.
+#[derive(Debug, Clone)]
+pub struct HeightmapGenerator {
+    settings: TerrainSettings,
+    blocks: TerrainBlocks,
+    height_noise: Noise,
+}
+
+impl HeightmapGenerator {
+    pub fn new(seed: u64, settings: TerrainSettings, blocks: TerrainBlocks) -> Self {
+        HeightmapGenerator {
+            settings,
+            blocks,
+            height_noise: Noise::new(seed).derive(1),
+        }
+    }
+
+    /// Height of the surface block of a column.
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        let noise = self.settings.height_noise.sample2(&self.height_noise, x as f64, z as f64);
+        self.settings.base_height + (noise * self.settings.amplitude as f64).round() as i32
+    }
+
+    /// Lowest and highest surface height any column can have.
+    pub fn surface_range(&self) -> (i32, i32) {
+        let amplitude = self.settings.amplitude.abs().ceil() as i32;
+        (self.settings.base_height - amplitude, self.settings.base_height + amplitude)
+    }
+
+    /// Block of a column at the given height, knowing the surface height.
+    pub fn layer_block(&self, y: i32, surface: i32) -> BlockId {
+        if y > surface {
+            BlockId::AIR
+        } else if y == surface {
+            self.blocks.surface
+        } else if y >= surface - self.settings.filler_depth {
+            self.blocks.filler
+        } else {
+            self.blocks.stone
+        }
+    }
+}
+
+impl GenerationStage for HeightmapGenerator {
+    fn apply(&self, pos: ChunkPos, chunk: &mut Chunk) {
+        let origin = pos.origin();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let surface = self.surface_height(origin[0] + x, origin[2] + z);
+                if surface < origin[1] {
+                    continue;
+                }
+                for y in 0..CHUNK_SIZE {
+                    let block = self.layer_block(origin[1] + y, surface);
+                    chunk.set_block(x as usize, y as usize, z as usize, block);
+                }
+            }
+        }
+    }
+}
+
+impl WorldGenerator for HeightmapGenerator {
+    fn generate_chunk(&self, pos: ChunkPos) -> Chunk {
+        let mut chunk = Chunk::default();
+        self.apply(pos, &mut chunk);
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chunks around the origin, where the surface crosses them.
+    fn generate(seed: u64) -> Vec<Chunk> {
+        let blocks = TerrainBlocks::from_registry(&BlockRegistry::default());
+        let generator = HeightmapGenerator::new(seed, TerrainSettings::default(), blocks);
+        let positions = (-2..2).flat_map(|x| (-1..1).flat_map(move |y| (-2..2).map(move |z| ChunkPos::new(x, y, z))));
+        positions.map(|pos| generator.generate_chunk(pos)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_blocks() {
+        let chunks = generate(1234);
+        assert!(chunks.iter().any(|chunk| !chunk.is_empty()));
+        assert_eq!(chunks, generate(1234));
+    }
+
+    #[test]
+    fn other_seed_gives_other_blocks() {
+        assert_ne!(generate(1234), generate(4321));
+    }
+}